@@ -0,0 +1,189 @@
+// asteroid.rs
+use std::f64::consts::TAU;
+use nalgebra_glm::Vec3;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use crate::ephemeris::{SolarSystem, DisplayScale, Orbit, OrbitalElements, DAYS_PER_CENTURY, J2000};
+use crate::obj::Obj;
+use crate::vertex::Vertex;
+
+// Rock meshes shared by every asteroid
+const SHAPES: usize = 6;
+// Rocks closer than this many of their own display radii are drawn as meshes
+const MESH_DISTANCE: f32 = 250.0;
+// Most meshes drawn in one frame, nearest first, so the belt stays interactive
+const MAX_MESHES: usize = 200;
+
+// Procedural belt between two orbit radii. The same seed always gives the same rocks.
+#[derive(Clone, Copy, Debug)]
+pub struct BeltSpec {
+    pub seed: u64,
+    pub count: usize,
+    pub inner_au: f64,
+    pub outer_au: f64,
+    pub max_eccentricity: f64,
+    pub max_inclination: f64, // degrees
+    pub min_radius_km: f64,
+    pub max_radius_km: f64,
+}
+
+impl Default for BeltSpec {
+    fn default() -> Self {
+        BeltSpec {
+            seed: 1,
+            count: 3000,
+            inner_au: 2.1,
+            outer_au: 3.3,
+            max_eccentricity: 0.2,
+            max_inclination: 12.0,
+            min_radius_km: 1.0,
+            max_radius_km: 60.0,
+        }
+    }
+}
+
+// Spin periods are days rather than the real hours, so rocks tumble visibly without
+// strobing at the default time step
+pub struct Asteroid {
+    pub orbit: Orbit,
+    pub radius_km: f64,
+    pub shape: usize,
+    pub rotation: Vec3, // Euler angles at J2000
+    pub spin: Vec3,     // rad/day
+    pub brightness: f32,
+}
+
+// Rock drawn as a mesh this frame
+#[derive(Clone, Copy, Debug)]
+pub struct RockInstance {
+    pub shape: usize,
+    pub position: Vec3,
+    pub rotation: Vec3,
+    pub scale: f32,
+}
+
+// Rocks orbiting `center`. Every frame `update` splits them into nearby meshes, which
+// share the vertex arrays in `shapes`, and distant points.
+pub struct AsteroidBelt {
+    pub center: usize,
+    pub asteroids: Vec<Asteroid>,
+    pub shapes: Vec<Vec<Vertex>>,
+    pub meshes: Vec<RockInstance>,
+    pub points: Vec<(Vec3, f32)>, // position and brightness
+}
+
+impl AsteroidBelt {
+    pub fn new(system: &SolarSystem, center: usize, spec: &BeltSpec) -> Self {
+        let mut rng = StdRng::seed_from_u64(spec.seed);
+        let shapes = (0..SHAPES)
+            .map(|_| Obj::from_triangles(&rock_shape(&mut rng)).get_vertex_array())
+            .collect();
+
+        let mu = system.gm_au(center);
+        let asteroids = (0..spec.count)
+            .map(|_| {
+                let a = rng.gen_range(spec.inner_au..spec.outer_au);
+                let long_node = rng.gen_range(0.0..360.0);
+                let elements = OrbitalElements {
+                    a,
+                    e: rng.gen_range(0.0..spec.max_eccentricity),
+                    i: rng.gen_range(0.0..spec.max_inclination),
+                    l: rng.gen_range(0.0..360.0),
+                    long_peri: long_node + rng.gen_range(0.0..360.0),
+                    long_node,
+                };
+                // Only the mean longitude moves, at the mean motion
+                let mean_motion = (mu / (a * a * a)).sqrt().to_degrees() * DAYS_PER_CENTURY;
+                let rates = OrbitalElements { a: 0.0, e: 0.0, i: 0.0, l: mean_motion, long_peri: 0.0, long_node: 0.0 };
+
+                // Many small rocks and few large ones
+                let size = rng.gen::<f64>().powi(3);
+                let radius_km = spec.min_radius_km * (spec.max_radius_km / spec.min_radius_km).powf(size);
+
+                let spin_axis = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+                let period = rng.gen_range(1.0..10.0);
+                Asteroid {
+                    orbit: Orbit { elements, rates },
+                    radius_km,
+                    shape: rng.gen_range(0..SHAPES),
+                    rotation: Vec3::new(rng.gen(), rng.gen(), rng.gen()) * std::f32::consts::TAU,
+                    spin: spin_axis * (TAU / period) as f32,
+                    brightness: rng.gen_range(0.45..1.0),
+                }
+            })
+            .collect();
+
+        AsteroidBelt {
+            center,
+            asteroids,
+            shapes,
+            meshes: Vec::new(),
+            points: Vec::new(),
+        }
+    }
+
+    // Scene positions at Julian day `jd` and level of detail as seen from `eye`
+    pub fn update(&mut self, system: &SolarSystem, jd: f64, scale: &DisplayScale, scene_positions: &[Vec3], eye: Vec3) {
+        let origin = scene_positions[self.center];
+        let mut candidates = Vec::new();
+        self.meshes.clear();
+        self.points.clear();
+
+        for (index, asteroid) in self.asteroids.iter().enumerate() {
+            let position = origin + system.offset_from(self.center, &asteroid.orbit.position(jd), scale);
+            let distance = (position - eye).magnitude();
+            let radius = scale.radius(asteroid.radius_km);
+            if distance < radius * MESH_DISTANCE {
+                candidates.push((distance, index, position));
+            } else {
+                self.points.push((position, asteroid.brightness));
+            }
+        }
+
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (rank, &(_, index, position)) in candidates.iter().enumerate() {
+            let asteroid = &self.asteroids[index];
+            if rank >= MAX_MESHES {
+                self.points.push((position, asteroid.brightness));
+                continue;
+            }
+
+            let elapsed = jd - J2000;
+            let spin = asteroid.spin.map(|rate| (rate as f64 * elapsed).rem_euclid(TAU) as f32);
+            self.meshes.push(RockInstance {
+                shape: asteroid.shape,
+                position,
+                rotation: asteroid.rotation + spin,
+                scale: scale.radius(asteroid.radius_km),
+            });
+        }
+    }
+}
+
+// Icosahedron with every corner pulled in by a random amount and squashed along two
+// axes, at most one unit from its center
+fn rock_shape(rng: &mut StdRng) -> Vec<[Vec3; 3]> {
+    let t = (1.0 + 5.0_f32.sqrt()) / 2.0;
+    let corners = [
+        [-1.0, t, 0.0], [1.0, t, 0.0], [-1.0, -t, 0.0], [1.0, -t, 0.0],
+        [0.0, -1.0, t], [0.0, 1.0, t], [0.0, -1.0, -t], [0.0, 1.0, -t],
+        [t, 0.0, -1.0], [t, 0.0, 1.0], [-t, 0.0, -1.0], [-t, 0.0, 1.0],
+    ];
+    let faces = [
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    let squash = Vec3::new(1.0, rng.gen_range(0.6..1.0), rng.gen_range(0.5..0.9));
+    let vertices: Vec<Vec3> = corners
+        .iter()
+        .map(|&[x, y, z]| Vec3::new(x, y, z).normalize().component_mul(&squash) * rng.gen_range(0.65..1.0))
+        .collect();
+
+    faces
+        .iter()
+        .map(|&[a, b, c]| [vertices[a], vertices[b], vertices[c]])
+        .collect()
+}
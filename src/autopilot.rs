@@ -0,0 +1,317 @@
+// autopilot.rs
+use std::fmt;
+use nalgebra_glm::{DVec3, Vec3};
+use crate::ephemeris::{SolarSystem, KM_PER_AU, SECONDS_PER_DAY, ecliptic_to_scene};
+use crate::kepler::lambert;
+use crate::ship::Ship;
+use crate::transfer::{TransferPlan, TransferKind, to_meters_per_second};
+
+// Burns end once the remaining delta-v drops below this, in m/s
+const BURN_TOLERANCE: f64 = 0.5;
+// Mid-course corrections smaller than this are skipped, in m/s
+const CORRECTION_THRESHOLD: f64 = 2.0;
+// Fractions of the flight time at which the arc is re-targeted
+const CORRECTIONS: [f64; 3] = [0.3, 0.6, 0.9];
+// Fraction of the angle to the burn direction turned per frame
+const SLEW_RATE: f32 = 0.15;
+const ALIGNMENT: f32 = 0.996; // cos(5°)
+// Seconds per frame while waiting in the parking orbit for the ejection point
+const EJECTION_STEP: f64 = 10.0;
+const EJECTION_TOLERANCE: f64 = 0.015;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AutopilotPhase {
+    Waiting,
+    Departure,
+    Cruise,
+    Correction,
+    Capture,
+    Done,
+    OutOfFuel,
+}
+
+impl fmt::Display for AutopilotPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AutopilotPhase::Waiting => write!(f, "waiting for the window"),
+            AutopilotPhase::Departure => write!(f, "departure burn"),
+            AutopilotPhase::Cruise => write!(f, "cruise"),
+            AutopilotPhase::Correction => write!(f, "course correction"),
+            AutopilotPhase::Capture => write!(f, "capture burn"),
+            AutopilotPhase::Done => write!(f, "done"),
+            AutopilotPhase::OutOfFuel => write!(f, "out of fuel"),
+        }
+    }
+}
+
+struct Ejection {
+    radial: DVec3,    // burn point direction from the origin body
+    normal: DVec3,    // parking orbit's angular momentum
+    direction: DVec3, // hyperbolic excess
+    asymptote: f64,   // cosine of the true anomaly at infinity
+    speed: f64,       // periapsis speed of the escape hyperbola
+}
+
+// Flies a transfer plan: departure burn, mid-course corrections and a capture burn
+// into a circular orbit when the ship enters the target's sphere of influence. Every burn
+// is re-solved each frame from the ship's actual state.
+pub struct Autopilot {
+    pub plan: TransferPlan,
+    pub phase: AutopilotPhase,
+    corrections_done: usize,
+}
+
+impl Autopilot {
+    pub fn new(plan: TransferPlan) -> Self {
+        Autopilot {
+            plan,
+            phase: AutopilotPhase::Waiting,
+            corrections_done: 0,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        !matches!(self.phase, AutopilotPhase::Done | AutopilotPhase::OutOfFuel)
+    }
+
+    // Largest step in days the simulation may take this frame so that no burn or
+    // sphere of influence is skipped
+    pub fn time_step(&self, system: &SolarSystem, ship: &Ship, jd: f64, requested: f64) -> f64 {
+        let one_second = 1.0 / SECONDS_PER_DAY;
+        match self.phase {
+            AutopilotPhase::Waiting => {
+                let start = self.plan.departure_jd - 0.5 * self.burn_days(ship, self.plan.departure_delta_v);
+                requested.min((start - jd).max(EJECTION_STEP * one_second))
+            }
+            AutopilotPhase::Departure | AutopilotPhase::Correction | AutopilotPhase::Capture => one_second,
+            AutopilotPhase::Cruise => {
+                let positions = system.heliocentric_positions(jd);
+                let target = self.plan.target;
+                let offset = ship.position - positions[target];
+                let speed = (ship.velocity - system.heliocentric_velocity(target, jd)).magnitude();
+                let approach = 0.25 * (offset.magnitude() - system.sphere_of_influence(target)) / speed.max(1e-12);
+
+                let next_correction = self.next_correction_jd().map_or(f64::INFINITY, |correction| correction - jd);
+                requested.min(approach.max(one_second)).min(next_correction.max(one_second))
+            }
+            AutopilotPhase::Done | AutopilotPhase::OutOfFuel => requested,
+        }
+    }
+
+    // Steers and throttles the ship for a frame of `dt` days. Call before `Ship::update`.
+    pub fn update(&mut self, system: &SolarSystem, ship: &mut Ship, jd: f64, dt: f64) {
+        if self.is_active() && ship.fuel <= 0.0 && self.phase != AutopilotPhase::Cruise {
+            ship.throttle = 0.0;
+            self.phase = AutopilotPhase::OutOfFuel;
+            return;
+        }
+
+        match self.phase {
+            AutopilotPhase::Waiting => {
+                let start = self.plan.departure_jd - 0.5 * self.burn_days(ship, self.plan.departure_delta_v);
+                if jd >= start && self.at_ejection_point(system, ship, jd) {
+                    self.phase = AutopilotPhase::Departure;
+                }
+            }
+            AutopilotPhase::Departure => {
+                if let Some(velocity) = self.departure_velocity(system, ship, jd) {
+                    if steer(ship, &velocity, dt) < BURN_TOLERANCE {
+                        self.phase = AutopilotPhase::Cruise;
+                    }
+                }
+            }
+            AutopilotPhase::Cruise => {
+                ship.throttle = 0.0;
+                let positions = system.heliocentric_positions(jd);
+                if system.soi_body(&ship.position, &positions) == self.plan.target {
+                    self.phase = AutopilotPhase::Capture;
+                } else if self.next_correction_jd().is_some_and(|correction| jd >= correction) {
+                    self.corrections_done += 1;
+                    // Skip corrections the ship could not finish, a later one may be cheaper
+                    if let Some(velocity) = self.intercept_velocity(system, ship, jd) {
+                        let correction = to_meters_per_second(&(velocity - ship.velocity));
+                        if correction > CORRECTION_THRESHOLD && correction < ship.delta_v() {
+                            self.phase = AutopilotPhase::Correction;
+                        }
+                    }
+                }
+            }
+            AutopilotPhase::Correction => {
+                match self.intercept_velocity(system, ship, jd) {
+                    Some(velocity) if steer(ship, &velocity, dt) >= BURN_TOLERANCE => {}
+                    _ => {
+                        ship.throttle = 0.0;
+                        self.phase = AutopilotPhase::Cruise;
+                    }
+                }
+            }
+            AutopilotPhase::Capture => {
+                if steer(ship, &self.orbit_velocity(system, ship, jd), dt) < BURN_TOLERANCE {
+                    ship.throttle = 0.0;
+                    self.phase = AutopilotPhase::Done;
+                }
+            }
+            AutopilotPhase::Done | AutopilotPhase::OutOfFuel => {}
+        }
+    }
+
+    fn next_correction_jd(&self) -> Option<f64> {
+        CORRECTIONS
+            .get(self.corrections_done)
+            .map(|fraction| self.plan.departure_jd + fraction * self.plan.flight_days())
+    }
+
+    // Estimated duration of a burn at full thrust, in days
+    fn burn_days(&self, ship: &Ship, delta_v: f64) -> f64 {
+        delta_v * ship.mass() / ship.spec.thrust / SECONDS_PER_DAY
+    }
+
+    // Transfer velocity relative to the central body at the departure point, plus the
+    // velocity of the point itself
+    fn transfer_velocity(&self, system: &SolarSystem, ship: &Ship, jd: f64) -> Option<(DVec3, DVec3)> {
+        let central = self.plan.central;
+        let mu = system.gm_au(central);
+        let positions = system.heliocentric_positions(jd);
+        let central_velocity = system.heliocentric_velocity(central, jd);
+
+        let (departure_position, departure_velocity) = match self.plan.origin {
+            Some(origin) => (positions[origin] - positions[central], system.heliocentric_velocity(origin, jd) - central_velocity),
+            None => (ship.position - positions[central], ship.velocity - central_velocity),
+        };
+        let transfer_velocity = match self.plan.kind {
+            TransferKind::Hohmann => {
+                // Prograde, so a finite burn only changes the speed and never chases the
+                // radial velocity it builds up
+                let r1 = departure_position.magnitude();
+                let r2 = self.plan.target_radius;
+                departure_velocity.normalize() * (mu * (2.0 / r1 - 2.0 / (r1 + r2))).sqrt()
+            }
+            TransferKind::Lambert => {
+                let target_position = system.relative_position(self.plan.target, self.plan.arrival_jd);
+                lambert(&departure_position, &target_position, self.plan.arrival_jd - jd, mu)?.0
+            }
+        };
+        Some((transfer_velocity, departure_velocity))
+    }
+
+    // Leaving a body, an impulsive burn at periapsis puts the escape hyperbola's
+    // asymptote at the true anomaly at infinity from the burn point. The ship waits
+    // until its radius makes that angle with the hyperbolic excess.
+    fn at_ejection_point(&self, system: &SolarSystem, ship: &Ship, jd: f64) -> bool {
+        let Some(ejection) = self.ejection(system, ship, jd) else {
+            return true;
+        };
+
+        // The angle to the excess must be opening towards it. When the excess is too
+        // far out of the orbit's plane, take the closest point, right after the radius
+        // passes furthest from the excess.
+        let alignment = ejection.radial.dot(&ejection.direction);
+        let rate = ejection.normal.cross(&ejection.radial).dot(&ejection.direction);
+        let closest = rate < EJECTION_TOLERANCE && alignment < 0.0 && alignment > ejection.asymptote;
+        rate > 0.0 && ((alignment - ejection.asymptote).abs() < EJECTION_TOLERANCE || closest)
+    }
+
+    // Escape geometry from the parking orbit, measured at the middle of what is left
+    // of the departure burn so a long burn is centred on the ideal point
+    fn ejection(&self, system: &SolarSystem, ship: &Ship, jd: f64) -> Option<Ejection> {
+        let origin = self.plan.origin?;
+        let (transfer_velocity, departure_velocity) = self.transfer_velocity(system, ship, jd)?;
+        let excess = transfer_velocity - departure_velocity;
+
+        let offset = ship.position - system.heliocentric_positions(jd)[origin];
+        let relative = ship.velocity - system.heliocentric_velocity(origin, jd);
+        let normal = offset.cross(&relative).normalize();
+        let mu = system.gm_au(origin);
+        let distance = offset.magnitude();
+
+        let speed = (excess.magnitude_squared() + 2.0 * mu / distance).sqrt();
+        let burn_delta_v = (speed - relative.magnitude()).max(0.0) * KM_PER_AU * 1000.0 / SECONDS_PER_DAY;
+        let lead = 0.5 * self.burn_days(ship, burn_delta_v) * relative.magnitude() / distance;
+        let radial = offset.normalize();
+
+        Some(Ejection {
+            radial: radial * lead.cos() + normal.cross(&radial) * lead.sin(),
+            normal,
+            direction: excess.normalize(),
+            asymptote: -1.0 / (1.0 + distance * excess.magnitude_squared() / mu),
+            speed,
+        })
+    }
+
+    // Heliocentric velocity the ship needs at the end of the departure burn
+    fn departure_velocity(&self, system: &SolarSystem, ship: &Ship, jd: f64) -> Option<DVec3> {
+        let Some(origin) = self.plan.origin else {
+            let (transfer_velocity, _) = self.transfer_velocity(system, ship, jd)?;
+            return Some(system.heliocentric_velocity(self.plan.central, jd) + transfer_velocity);
+        };
+
+        // Thrust perpendicular to the radius, in the plane that holds the radius and the
+        // excess, until the ship has the escape hyperbola's periapsis speed. Only the
+        // speed is targeted so the burn doesn't chase the radial velocity it builds up.
+        let ejection = self.ejection(system, ship, jd)?;
+        let radial = ejection.radial;
+        let tangent = (ejection.direction - radial * radial.dot(&ejection.direction)).normalize();
+        let speed = (ship.velocity - system.heliocentric_velocity(origin, jd)).magnitude();
+        Some(ship.velocity + tangent * (ejection.speed - speed).max(0.0))
+    }
+
+    // Heliocentric velocity that reaches the target's arrival position on time from
+    // where the ship is now
+    fn intercept_velocity(&self, system: &SolarSystem, ship: &Ship, jd: f64) -> Option<DVec3> {
+        let central = self.plan.central;
+        let remaining = self.plan.arrival_jd - jd;
+        if remaining <= 0.0 {
+            return None;
+        }
+
+        let position = ship.position - system.heliocentric_positions(jd)[central];
+        let target_position = system.relative_position(self.plan.target, self.plan.arrival_jd);
+        let (velocity, _) = lambert(&position, &target_position, remaining, system.gm_au(central))?;
+        Some(system.heliocentric_velocity(central, jd) + velocity)
+    }
+
+    // Circular orbit around the target at the ship's current distance, keeping the
+    // sense of rotation the ship arrives with
+    fn orbit_velocity(&self, system: &SolarSystem, ship: &Ship, jd: f64) -> DVec3 {
+        let target = self.plan.target;
+        let target_velocity = system.heliocentric_velocity(target, jd);
+        let offset = ship.position - system.heliocentric_positions(jd)[target];
+        let relative = ship.velocity - target_velocity;
+
+        let radial = offset.normalize();
+        let mut tangential = relative - radial * relative.dot(&radial);
+        if tangential.magnitude() < 1e-12 {
+            tangential = radial.cross(&DVec3::new(0.0, 0.0, 1.0));
+        }
+        target_velocity + tangential.normalize() * (system.gm_au(target) / offset.magnitude()).sqrt()
+    }
+}
+
+// Points the ship along the velocity change still needed and sets the throttle so the
+// burn ends on target. Returns the remaining delta-v in m/s.
+fn steer(ship: &mut Ship, target_velocity: &DVec3, dt: f64) -> f64 {
+    let change = target_velocity - ship.velocity;
+    let remaining = to_meters_per_second(&change);
+    if remaining < BURN_TOLERANCE {
+        ship.throttle = 0.0;
+        return remaining;
+    }
+
+    let direction = ecliptic_to_scene(&change.normalize());
+    let forward = ship.forward();
+    let up = ship.up();
+
+    // Turn through the ship's up axis when the burn is behind it
+    let slew_target = if forward.dot(&direction) < -0.9 { up } else { direction };
+    ship.face(&forward.lerp(&slew_target, SLEW_RATE), &up);
+    ship.angular_velocity = Vec3::zeros();
+
+    let acceleration = ship.spec.thrust / ship.mass();
+    let frame_seconds = dt * SECONDS_PER_DAY;
+    ship.throttle = if ship.forward().dot(&direction) > ALIGNMENT {
+        (remaining / (acceleration * frame_seconds)).min(1.0) as f32
+    } else {
+        0.0
+    };
+    remaining
+}
@@ -75,6 +75,15 @@ impl Camera {
     self.has_changed = true;
   }
 
+  // Looks straight down on `center` from `height` above it, slightly offset so the
+  // up vector never lines up with the view direction
+  pub fn bird_view(&mut self, center: Vec3, height: f32) {
+    self.eye = center + Vec3::new(0.1 * height, height, 0.0);
+    self.center = center;
+    self.up = Vec3::new(0.0, 1.0, 0.0);
+    self.has_changed = true;
+  }
+
   pub fn check_if_changed(&mut self) -> bool {
     if self.has_changed {
       self.has_changed = false;
@@ -0,0 +1,414 @@
+// collision.rs
+use std::fmt;
+use nalgebra_glm::{DVec3, Vec3};
+use crate::ephemeris::{SolarSystem, DisplayScale, KM_PER_AU, SECONDS_PER_DAY, ecliptic_to_scene, scene_to_ecliptic};
+use crate::obj::Obj;
+use crate::ship::{Ship, FlightModel, ShipStatus};
+
+// Shares of the normal and tangential speeds kept after a bounce
+const RESTITUTION: f64 = 0.4;
+const FRICTION: f64 = 0.7;
+// Bounces slower than this, or than the speed gravity gives back within one step,
+// settle into a landing instead, in m/s
+const REST_SPEED: f64 = 1.0;
+// Rays start this many mesh radii from the center, safely outside the mesh
+const RAY_START: f32 = 2.0;
+// Models are drawn at twice the body's display radius, so this is the body's radius
+const MODEL_RADIUS: f32 = 0.5;
+const EPSILON: f32 = 1e-7;
+
+#[derive(Clone, Copy, Debug)]
+pub struct BoundingSphere {
+    pub center: DVec3,
+    pub radius: f64,
+}
+
+// Where a ray met a mesh, with the distance along the ray and the face normal
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    pub distance: f32,
+    pub normal: Vec3,
+}
+
+// Triangles of the model a body is drawn with, for ray tests against the faceted
+// surface the player actually sees. `radius` bounds the mesh, mountains included.
+pub struct SurfaceMesh {
+    pub triangles: Vec<[Vec3; 3]>,
+    pub radius: f32,
+}
+
+// What happens when the ship reaches a surface. Merging only exists in the n-body
+// model; patched conics treat it as a stop.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CollisionResponse {
+    #[default]
+    Stop,
+    Bounce,
+    Crash,
+    Merge,
+}
+
+// A collision and the response that was applied, with the impact speed in m/s
+#[derive(Clone, Copy, Debug)]
+pub struct CollisionEvent {
+    pub body: usize,
+    pub speed: f64,
+    pub response: CollisionResponse,
+}
+
+// Two bodies that touched and became one, with the absorber's radius before the merge
+#[derive(Clone, Copy, Debug)]
+pub struct BodyMerge {
+    pub absorber: usize,
+    pub absorbed: usize,
+    pub radius_before_km: f64,
+}
+
+impl BoundingSphere {
+    pub fn new(center: DVec3, radius: f64) -> Self {
+        BoundingSphere { center, radius }
+    }
+
+    pub fn contains(&self, point: &DVec3) -> bool {
+        (point - self.center).magnitude_squared() < self.radius * self.radius
+    }
+
+    pub fn intersects(&self, other: &BoundingSphere) -> bool {
+        let reach = self.radius + other.radius;
+        (other.center - self.center).magnitude_squared() < reach * reach
+    }
+
+    // Fraction of the segment from `from` to `to` where it first enters the sphere,
+    // zero if it starts inside
+    pub fn sweep(&self, from: &DVec3, to: &DVec3) -> Option<f64> {
+        if self.contains(from) {
+            return Some(0.0);
+        }
+
+        let direction = to - from;
+        let offset = from - self.center;
+        let a = direction.magnitude_squared();
+        let b = offset.dot(&direction);
+        let c = offset.magnitude_squared() - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        if a <= 0.0 || discriminant < 0.0 {
+            return None;
+        }
+
+        let t = (-b - discriminant.sqrt()) / a;
+        (0.0..=1.0).contains(&t).then_some(t)
+    }
+
+    // Closest point at least `margin` outside the sphere, or the point itself
+    pub fn push_out(&self, point: &DVec3, margin: f64) -> DVec3 {
+        let offset = point - self.center;
+        let distance = offset.magnitude();
+        let minimum = self.radius + margin;
+        if distance >= minimum {
+            *point
+        } else if distance > 0.0 {
+            self.center + offset * (minimum / distance)
+        } else {
+            self.center + DVec3::new(0.0, 0.0, minimum)
+        }
+    }
+}
+
+impl fmt::Display for CollisionResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CollisionResponse::Stop => write!(f, "stop"),
+            CollisionResponse::Bounce => write!(f, "bounce"),
+            CollisionResponse::Crash => write!(f, "crash"),
+            CollisionResponse::Merge => write!(f, "merge"),
+        }
+    }
+}
+
+impl CollisionResponse {
+    pub fn next(self) -> Self {
+        match self {
+            CollisionResponse::Stop => CollisionResponse::Bounce,
+            CollisionResponse::Bounce => CollisionResponse::Crash,
+            CollisionResponse::Crash => CollisionResponse::Merge,
+            CollisionResponse::Merge => CollisionResponse::Stop,
+        }
+    }
+}
+
+impl SurfaceMesh {
+    // The mesh is centered on its bounding box, as sphere.obj sits slightly off its origin
+    pub fn new(model: &Obj) -> Self {
+        let mut triangles = model.triangles();
+        let corners = triangles.iter().flatten();
+        let low = corners.clone().fold(Vec3::repeat(f32::INFINITY), |low, vertex| low.inf(vertex));
+        let high = corners.fold(Vec3::repeat(f32::NEG_INFINITY), |high, vertex| high.sup(vertex));
+        let center = (low + high) * 0.5;
+        triangles.iter_mut().flatten().for_each(|vertex| *vertex -= center);
+
+        let radius = triangles
+            .iter()
+            .flatten()
+            .map(|vertex| vertex.magnitude())
+            .fold(0.0, f32::max);
+        SurfaceMesh { triangles, radius }
+    }
+
+    // Nearest face hit by the ray, with the normal turned against the ray
+    pub fn raycast(&self, origin: &Vec3, direction: &Vec3) -> Option<RayHit> {
+        self.triangles
+            .iter()
+            .filter_map(|triangle| {
+                let distance = ray_triangle(origin, direction, triangle)?;
+                let normal = (triangle[1] - triangle[0]).cross(&(triangle[2] - triangle[0])).normalize();
+                let normal = if normal.dot(direction) > 0.0 { -normal } else { normal };
+                Some(RayHit { distance, normal })
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+
+    // Radius of the sphere around the mesh on a body of `radius`
+    pub fn bounding_radius(&self, radius: f64) -> f64 {
+        radius * (self.radius / MODEL_RADIUS) as f64
+    }
+
+    // Height of the surface above the center of a body of `radius` in the direction
+    // of the ecliptic unit vector `up`, with the surface normal there. The mesh is
+    // scaled the way it is drawn, with MODEL_RADIUS matching the body.
+    pub fn surface(&self, up: &DVec3, radius: f64) -> Option<(f64, DVec3)> {
+        let direction = ecliptic_to_scene(up);
+        let origin = direction * (RAY_START * self.radius);
+        let hit = self.raycast(&origin, &-direction)?;
+        let height = (RAY_START * self.radius - hit.distance) / MODEL_RADIUS;
+        Some((height as f64 * radius, scene_to_ecliptic(&hit.normal).normalize()))
+    }
+}
+
+// Möller–Trumbore ray/triangle intersection, distance along the ray if it hits
+pub fn ray_triangle(origin: &Vec3, direction: &Vec3, triangle: &[Vec3; 3]) -> Option<f32> {
+    let edge1 = triangle[1] - triangle[0];
+    let edge2 = triangle[2] - triangle[0];
+    let p = direction.cross(&edge2);
+    let determinant = edge1.dot(&p);
+    if determinant.abs() < EPSILON {
+        return None;
+    }
+
+    let inverse = 1.0 / determinant;
+    let s = origin - triangle[0];
+    let u = s.dot(&p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(&edge1);
+    let v = direction.dot(&q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(&q) * inverse;
+    (t > EPSILON).then_some(t)
+}
+
+// Checks the ship's path over the last step, from `previous` at `previous_jd` to where
+// it is now at `jd`, against every body and applies the ship's collision response.
+// Bounding spheres swept along the path catch fast ships that would skip through a
+// body in one step; the body's mesh in `surfaces` then decides whether the ship
+// really touched the surface.
+pub fn collide_ship(ship: &mut Ship, system: &SolarSystem, surfaces: &[SurfaceMesh], previous: &DVec3, previous_jd: f64, jd: f64) -> Option<CollisionEvent> {
+    if ship.status != ShipStatus::Flying {
+        return None;
+    }
+
+    let before = system.heliocentric_positions(previous_jd);
+    let after = system.heliocentric_positions(jd);
+    let ship_radius = ship.spec.radius / (1000.0 * KM_PER_AU);
+
+    for (body, surface) in surfaces.iter().enumerate() {
+        if system.bodies[body].merged_into.is_some() {
+            continue;
+        }
+        let radius = system.bodies[body].radius_km / KM_PER_AU;
+        let from = previous - before[body];
+        let to = ship.position - after[body];
+
+        let bounds = BoundingSphere::new(DVec3::zeros(), surface.bounding_radius(radius) + ship_radius);
+        let Some(entry) = bounds.sweep(&from, &to) else {
+            continue;
+        };
+
+        // Closest approach to the center along the path, against the mesh below it.
+        // Coasting stops where the orbit meets the bounding sphere, above the faces,
+        // so a ship that ends the step there on its way down touches too.
+        let path = to - from;
+        let t = if path.magnitude_squared() > 0.0 {
+            (-from.dot(&path) / path.magnitude_squared()).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let closest = from + path * t;
+        let Some((height, _)) = surface.surface(&closest.normalize(), radius) else {
+            continue;
+        };
+        let body_velocity = system.heliocentric_velocity(body, jd);
+        let velocity = ship.velocity - body_velocity;
+        let descending = bounds.contains(&to) && velocity.dot(&to) < 0.0;
+        if closest.magnitude() - ship_radius >= height && !descending {
+            continue;
+        }
+
+        // Contact below where the step ended, or where it entered for a ship that
+        // went right through the body within one step
+        let up = if bounds.contains(&to) { to } else { from + path * entry }.normalize();
+        let (height, normal) = surface.surface(&up, radius).unwrap_or((radius, up));
+        let normal_speed = velocity.dot(&normal);
+        let contact = up * (height + ship_radius);
+
+        // Touching while already moving away is left alone, apart from leaving the surface
+        if normal_speed >= 0.0 {
+            ship.position = after[body] + contact;
+            return None;
+        }
+
+        let speed = velocity.magnitude() * KM_PER_AU * 1000.0 / SECONDS_PER_DAY;
+        let response = match ship.collision_response {
+            CollisionResponse::Merge if ship.flight_model != FlightModel::NBody => CollisionResponse::Stop,
+            CollisionResponse::Bounce => {
+                let tangential = velocity - normal * normal_speed;
+                let bounced = tangential * FRICTION - normal * (RESTITUTION * normal_speed);
+                let fallback = system.gm_au(body) / (height * height) * (jd - previous_jd);
+                let rest_speed = (REST_SPEED * SECONDS_PER_DAY / (1000.0 * KM_PER_AU)).max(fallback);
+                if -RESTITUTION * normal_speed < rest_speed {
+                    CollisionResponse::Stop
+                } else {
+                    ship.position = after[body] + contact;
+                    ship.velocity = body_velocity + bounced;
+                    CollisionResponse::Bounce
+                }
+            }
+            response => response,
+        };
+
+        ship.throttle = 0.0;
+        match response {
+            CollisionResponse::Stop => ship.status = ShipStatus::Landed { body, offset: contact },
+            CollisionResponse::Crash => ship.status = ShipStatus::Crashed { body, offset: contact },
+            CollisionResponse::Merge => ship.status = ShipStatus::Merged { body },
+            CollisionResponse::Bounce => {}
+        }
+        if response != CollisionResponse::Bounce {
+            ship.hold(system, jd);
+        }
+        return Some(CollisionEvent { body, speed, response });
+    }
+    None
+}
+
+// Merges every pair of bodies whose spheres touch at Julian day `jd`, the heavier one
+// absorbing the other. A body always absorbs the ones whose positions hang from it,
+// so a moon falling into its planet never drags the planet along.
+pub fn collide_bodies(system: &mut SolarSystem, jd: f64) -> Vec<BodyMerge> {
+    let positions = system.heliocentric_positions(jd);
+    let mut merges = Vec::new();
+
+    for first in 0..system.bodies.len() {
+        for second in first + 1..system.bodies.len() {
+            let (a, b) = (&system.bodies[first], &system.bodies[second]);
+            if a.merged_into.is_some() || b.merged_into.is_some() {
+                continue;
+            }
+            let sphere_a = BoundingSphere::new(positions[first], a.radius_km / KM_PER_AU);
+            let sphere_b = BoundingSphere::new(positions[second], b.radius_km / KM_PER_AU);
+            if !sphere_a.intersects(&sphere_b) {
+                continue;
+            }
+
+            let (absorber, absorbed) = if system.depends_on(second, first) {
+                (first, second)
+            } else if system.depends_on(first, second) || b.gm > a.gm {
+                (second, first)
+            } else {
+                (first, second)
+            };
+            merges.push(BodyMerge { absorber, absorbed, radius_before_km: system.bodies[absorber].radius_km });
+            system.merge(absorber, absorbed);
+        }
+    }
+    merges
+}
+
+// Display spheres of every body in the scene, for keeping the camera out of them
+pub fn scene_spheres(system: &SolarSystem, scene_positions: &[Vec3], scale: &DisplayScale) -> Vec<BoundingSphere> {
+    scene_positions
+        .iter()
+        .enumerate()
+        .map(|(index, position)| BoundingSphere::new(position.cast(), system.display_radius(index, scale) as f64))
+        .collect()
+}
+
+// Moves a scene point out of every sphere, at least `margin` from its surface
+pub fn keep_outside(point: Vec3, spheres: &[BoundingSphere], margin: f32) -> Vec3 {
+    let mut point = point.cast::<f64>();
+    for sphere in spheres {
+        point = sphere.push_out(&point, margin as f64);
+    }
+    point.cast()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ephemeris::J2000;
+    use crate::planet::random_system;
+
+    #[test]
+    fn touching_bodies_merge_into_the_heavier() {
+        let mut system = random_system(7);
+        system.bodies[2].orbit = system.bodies[1].orbit;
+        let gm = system.bodies[1].gm + system.bodies[2].gm;
+        let volume = system.bodies[1].radius_km.powi(3) + system.bodies[2].radius_km.powi(3);
+        let heavier = if system.bodies[2].gm > system.bodies[1].gm { 2 } else { 1 };
+
+        let merges = collide_bodies(&mut system, J2000);
+        assert_eq!(merges.len(), 1);
+        assert_eq!(merges[0].absorber, heavier);
+        assert_eq!(merges[0].absorbed, 3 - heavier);
+
+        let absorber = &system.bodies[heavier];
+        assert!((absorber.gm - gm).abs() < 1e-9 * gm);
+        assert!((absorber.radius_km.powi(3) - volume).abs() < 1e-9 * volume);
+        assert_eq!(system.bodies[3 - heavier].gm, 0.0);
+
+        let positions = system.heliocentric_positions(J2000 + 10.0);
+        assert_eq!(positions[3 - heavier], positions[heavier]);
+        assert!(collide_bodies(&mut system, J2000 + 10.0).is_empty());
+    }
+
+    #[test]
+    fn parent_absorbs_its_satellite() {
+        let mut system = random_system(7);
+        system.bodies[2].parent = Some(1);
+        system.bodies[2].orbit = None;
+        system.bodies[2].gm = 10.0 * system.bodies[1].gm;
+
+        let merges = collide_bodies(&mut system, J2000);
+        assert_eq!(merges.len(), 1);
+        assert_eq!((merges[0].absorber, merges[0].absorbed), (1, 2));
+    }
+
+    #[test]
+    fn surface_follows_the_displaced_mesh() {
+        let up = DVec3::new(0.3, -0.5, 0.8).normalize();
+        let sphere = SurfaceMesh::new(&Obj::sphere(3));
+        let (height, _) = sphere.surface(&up, 1000.0).unwrap();
+        assert!((height - 1000.0).abs() < 5.0, "{}", height);
+
+        let mut raised = Obj::sphere(3);
+        raised.displace(|_| 1.2);
+        let raised = SurfaceMesh::new(&raised);
+        let (height, _) = raised.surface(&up, 1000.0).unwrap();
+        assert!((height - 1200.0).abs() < 6.0, "{}", height);
+        assert!((raised.bounding_radius(1000.0) - 1200.0).abs() < 1.0);
+    }
+}
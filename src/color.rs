@@ -27,6 +27,11 @@ impl Color {
     }
   }
 
+  // Channels as f32 from 0.0 to 1.0, the inverse of from_float
+  pub fn to_float(self) -> (f32, f32, f32) {
+    (self.r as f32 / 255.0, self.g as f32 / 255.0, self.b as f32 / 255.0)
+  }
+
   // Function to create a color from a hex value
   pub fn from_hex(hex: u32) -> Self {
     let r = ((hex >> 16) & 0xFF) as u8;
@@ -35,6 +40,15 @@ impl Color {
     Color { r, g, b }
   }
 
+  // Parses "#RRGGBB", "0xRRGGBB" or "RRGGBB"
+  pub fn parse_hex(text: &str) -> Option<Self> {
+    let digits = text.trim_start_matches('#').trim_start_matches("0x");
+    if digits.len() != 6 {
+      return None;
+    }
+    u32::from_str_radix(digits, 16).ok().map(Color::from_hex)
+  }
+
   // Function to return the color as a hex value
   pub fn to_hex(&self) -> u32 {
     ((self.r as u32) << 16) | ((self.g as u32) << 8) | (self.b as u32)
@@ -94,7 +108,7 @@ impl Color {
 }
 
 // Implement addition for Color
-use std::ops::Add;
+use std::ops::{Add, Sub, Div, AddAssign, MulAssign};
 
 impl Add for Color {
   type Output = Color;
@@ -129,3 +143,184 @@ impl fmt::Display for Color {
     write!(f, "Color(r: {}, g: {}, b: {})", self.r, self.g, self.b)
   }
 }
+
+// Color in linear light with straight (not premultiplied) alpha. Shading math happens
+// here; sRGB bytes are decoded when textures load and encoded when pixels reach the
+// framebuffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinearColor {
+  pub r: f32,
+  pub g: f32,
+  pub b: f32,
+  pub a: f32,
+}
+
+impl LinearColor {
+  pub const BLACK: LinearColor = LinearColor { r: 0.0, g: 0.0, b: 0.0, a: 1.0 };
+  pub const WHITE: LinearColor = LinearColor { r: 1.0, g: 1.0, b: 1.0, a: 1.0 };
+
+  pub fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+    LinearColor { r, g, b, a }
+  }
+
+  pub fn rgb(r: f32, g: f32, b: f32) -> Self {
+    LinearColor { r, g, b, a: 1.0 }
+  }
+
+  // Opaque color from an sRGB hex value
+  pub fn from_hex(hex: u32) -> Self {
+    Color::from_hex(hex).into()
+  }
+
+  // Encodes to sRGB, clamping whatever is outside [0, 1] and dropping alpha
+  pub fn to_color(self) -> Color {
+    let encode = |value: f32| (linear_to_srgb(value.clamp(0.0, 1.0)) * 255.0).round() as u8;
+    Color::new(encode(self.r), encode(self.g), encode(self.b))
+  }
+
+  pub fn to_hex(self) -> u32 {
+    self.to_color().to_hex()
+  }
+
+  pub fn with_alpha(self, a: f32) -> Self {
+    LinearColor { a, ..self }
+  }
+
+  pub fn lerp(&self, other: &LinearColor, t: f32) -> Self {
+    let t = t.clamp(0.0, 1.0);
+    let mix = |from: f32, to: f32| from + (to - from) * t;
+    LinearColor::new(mix(self.r, other.r), mix(self.g, other.g), mix(self.b, other.b), mix(self.a, other.a))
+  }
+
+  // Relative luminance (Rec. 709)
+  pub fn luminance(&self) -> f32 {
+    0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+  }
+}
+
+impl Default for LinearColor {
+  fn default() -> Self {
+    LinearColor::BLACK
+  }
+}
+
+impl From<Color> for LinearColor {
+  fn from(color: Color) -> Self {
+    LinearColor::rgb(
+      srgb_to_linear(color.r as f32 / 255.0),
+      srgb_to_linear(color.g as f32 / 255.0),
+      srgb_to_linear(color.b as f32 / 255.0),
+    )
+  }
+}
+
+impl From<LinearColor> for Color {
+  fn from(color: LinearColor) -> Self {
+    color.to_color()
+  }
+}
+
+// Colors add, subtract and multiply channel by channel, alpha included
+impl Add for LinearColor {
+  type Output = LinearColor;
+
+  fn add(self, other: LinearColor) -> LinearColor {
+    LinearColor::new(self.r + other.r, self.g + other.g, self.b + other.b, self.a + other.a)
+  }
+}
+
+impl Sub for LinearColor {
+  type Output = LinearColor;
+
+  fn sub(self, other: LinearColor) -> LinearColor {
+    LinearColor::new(self.r - other.r, self.g - other.g, self.b - other.b, self.a - other.a)
+  }
+}
+
+impl Mul for LinearColor {
+  type Output = LinearColor;
+
+  fn mul(self, other: LinearColor) -> LinearColor {
+    LinearColor::new(self.r * other.r, self.g * other.g, self.b * other.b, self.a * other.a)
+  }
+}
+
+// Scaling by a number changes how much light there is and leaves alpha alone
+impl Mul<f32> for LinearColor {
+  type Output = LinearColor;
+
+  fn mul(self, scalar: f32) -> LinearColor {
+    LinearColor::new(self.r * scalar, self.g * scalar, self.b * scalar, self.a)
+  }
+}
+
+impl Div<f32> for LinearColor {
+  type Output = LinearColor;
+
+  fn div(self, scalar: f32) -> LinearColor {
+    LinearColor::new(self.r / scalar, self.g / scalar, self.b / scalar, self.a)
+  }
+}
+
+impl AddAssign for LinearColor {
+  fn add_assign(&mut self, other: LinearColor) {
+    *self = *self + other;
+  }
+}
+
+impl MulAssign<f32> for LinearColor {
+  fn mul_assign(&mut self, scalar: f32) {
+    *self = *self * scalar;
+  }
+}
+
+// Exact sRGB transfer functions, for channels in [0, 1]
+pub fn srgb_to_linear(value: f32) -> f32 {
+  if value <= 0.04045 {
+    value / 12.92
+  } else {
+    ((value + 0.055) / 1.055).powf(2.4)
+  }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+  if value <= 0.0031308 {
+    value * 12.92
+  } else {
+    1.055 * value.powf(1.0 / 2.4) - 0.055
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn lerp_mixes_every_channel_including_alpha() {
+    let from = LinearColor::new(0.0, 0.2, 1.0, 0.0);
+    let to = LinearColor::new(1.0, 0.6, 0.0, 1.0);
+    let half = from.lerp(&to, 0.5);
+    for (channel, expected) in [(half.r, 0.5), (half.g, 0.4), (half.b, 0.5), (half.a, 0.5)] {
+      assert!((channel - expected).abs() < 1e-6);
+    }
+    assert_eq!(from.lerp(&to, 0.0), from);
+    assert_eq!(from.lerp(&to, 2.0), to);
+  }
+
+  #[test]
+  fn srgb_round_trips_through_linear() {
+    for byte in 0..=255u8 {
+      let color = Color::new(byte, byte, byte);
+      assert_eq!(LinearColor::from(color).to_hex(), color.to_hex());
+    }
+    for step in 0..=100 {
+      let value = step as f32 / 100.0;
+      assert!((srgb_to_linear(linear_to_srgb(value)) - value).abs() < 1e-5);
+    }
+
+    // Reference points of the sRGB curve, on either side of the linear segment
+    assert!((srgb_to_linear(0.5) - 0.214_041).abs() < 1e-5);
+    assert!((srgb_to_linear(0.04) - 0.04 / 12.92).abs() < 1e-7);
+    assert!((linear_to_srgb(0.002) - 0.002 * 12.92).abs() < 1e-7);
+  }
+}
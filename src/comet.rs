@@ -0,0 +1,121 @@
+// comet.rs
+use nalgebra_glm::Vec3;
+use crate::color::LinearColor;
+use crate::ephemeris::{SolarSystem, ecliptic_to_scene};
+use crate::framebuffer::Framebuffer;
+use crate::particle::{Emitter, EmitterSpec, Curve, ParticleShape, BlendMode};
+use crate::Uniforms;
+
+// Nuclei farther than this from the Sun are inactive, in au, and fade in over the
+// last au inside it
+const ACTIVE_DISTANCE: f64 = 5.0;
+// Tail lengths at 1 au in scene units, growing as the comet gets closer
+const ION_LENGTH: f32 = 3.0;
+const DUST_LENGTH: f32 = 2.0;
+// Particles emitted per frame at full brightness
+const ION_RATE: f32 = 10.0;
+const DUST_RATE: f32 = 8.0;
+// Share of the dust tail's length it bends back along the orbit
+const DUST_CURVE: f32 = 0.6;
+// Typical dust particle lifetime in frames, which sets its speed and push per unit of length
+const DUST_LIFETIME: f32 = 100.0;
+
+const ION_COLOR: u32 = 0x4F8CFF;
+const DUST_COLOR: u32 = 0xFFD890;
+
+// Ion tail streaming straight away from the Sun and a dust tail curving back along
+// the orbit, both drawn additively. Particles live relative to the nucleus, so the
+// tails keep their shape at any time warp.
+pub struct CometTail {
+    pub body: usize,
+    ion: Emitter,
+    dust: Emitter,
+}
+
+impl CometTail {
+    pub fn new(body: usize, seed: u64) -> Self {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        CometTail {
+            body,
+            ion: Emitter::new(ion_tail(), Vec3::zeros(), up, seed).with_local(true),
+            dust: Emitter::new(dust_tail(), Vec3::zeros(), up, seed.wrapping_add(1)).with_local(true),
+        }
+    }
+
+    // Moves both emitters to the nucleus and sets how much they emit according to the
+    // comet's distance from the Sun at Julian day `jd`
+    pub fn update(&mut self, system: &SolarSystem, jd: f64, scene_positions: &[Vec3]) {
+        let nucleus = scene_positions[self.body];
+        self.ion.position = nucleus;
+        self.dust.position = nucleus;
+        self.ion.intensity = 0.0;
+        self.dust.intensity = 0.0;
+
+        let distance = system.relative_position(self.body, jd).magnitude();
+        if let (Some(sun), true) = (system.bodies[self.body].parent, distance < ACTIVE_DISTANCE) {
+            let closeness = (1.0 / distance) as f32;
+            let fade = (ACTIVE_DISTANCE - distance).min(1.0) as f32;
+            let brightness = (closeness * closeness).min(1.5) * fade;
+            let length = closeness.sqrt().min(2.0);
+
+            let away = (nucleus - scene_positions[sun]).normalize();
+            let motion = ecliptic_to_scene(&system.heliocentric_velocity(self.body, jd)).normalize();
+
+            self.ion.direction = away;
+            self.ion.scale = ION_LENGTH * length;
+            self.ion.intensity = brightness;
+
+            // Dust leaves slowly, is pushed outwards by sunlight and falls behind the
+            // nucleus, which bends the tail into an arc
+            let life = DUST_LIFETIME * DUST_LIFETIME;
+            self.dust.direction = away;
+            self.dust.acceleration = away * (1.4 / life) - motion * (2.0 * DUST_CURVE / life);
+            self.dust.scale = DUST_LENGTH * length;
+            self.dust.intensity = brightness;
+        }
+
+        self.ion.update(&[]);
+        self.dust.update(&[]);
+    }
+
+    pub fn render(&self, framebuffer: &mut Framebuffer, uniforms: &Uniforms) {
+        self.ion.render(framebuffer, uniforms);
+        self.dust.render(framebuffer, uniforms);
+    }
+}
+
+fn ion_tail() -> EmitterSpec {
+    EmitterSpec {
+        rate: ION_RATE,
+        burst: 0,
+        lifetime: (50, 70),
+        speed: (0.8 / 60.0, 1.2 / 60.0),
+        spread: 0.03,
+        speed_curve: Curve::constant(1.0),
+        color: Curve::constant(LinearColor::from_hex(ION_COLOR)),
+        size: Curve::constant(1.5),
+        alpha: Curve::linear(0.35, 0.0),
+        drag: 0.0,
+        gravity: 0.0,
+        shape: ParticleShape::Point,
+        blend: BlendMode::Additive,
+    }
+}
+
+fn dust_tail() -> EmitterSpec {
+    EmitterSpec {
+        rate: DUST_RATE,
+        burst: 0,
+        lifetime: (80, 120),
+        speed: (0.25 / DUST_LIFETIME, 0.35 / DUST_LIFETIME),
+        spread: 0.15,
+        speed_curve: Curve::constant(1.0),
+        color: Curve::constant(LinearColor::from_hex(DUST_COLOR)),
+        size: Curve::constant(2.5),
+        alpha: Curve::linear(0.35, 0.0),
+        drag: 0.0,
+        gravity: 0.0,
+        shape: ParticleShape::Point,
+        blend: BlendMode::Additive,
+    }
+}
@@ -0,0 +1,484 @@
+// conic.rs
+use std::f64::consts::PI;
+use nalgebra_glm::{DVec3, Vec3};
+use crate::color::LinearColor;
+use crate::ephemeris::{SolarSystem, DisplayScale, KM_PER_AU};
+use crate::framebuffer::Framebuffer;
+use crate::kepler::propagate;
+use crate::line::{LineStyle, draw_polyline_3d, draw_point_3d};
+use crate::Uniforms;
+
+// Patched conics: the ship follows a two-body orbit around the body whose sphere of
+// influence contains it, and switches to a new conic when it crosses into a moon's
+// sphere or out of its current one.
+
+// The transition search never steps less than a minute, in days
+const MIN_STEP: f64 = 1.0 / 1440.0;
+const MAX_STEPS: usize = 4000;
+const BISECTIONS: usize = 40;
+// Open orbits, and closed ones slower than this, are predicted this far ahead in days
+const HORIZON: f64 = 3650.0;
+
+// Colors of the predicted segments, in order
+const SEGMENT_COLORS: [u32; 4] = [0xFFC83C, 0xFF6EC8, 0x9678FF, 0x6EFFA0];
+
+// Osculating orbit around `body`, with the state relative to the body at Julian day `jd`
+#[derive(Clone, Copy, Debug)]
+pub struct Conic {
+    pub body: usize,
+    pub jd: f64,
+    pub position: DVec3,
+    pub velocity: DVec3,
+    pub mu: f64,
+}
+
+// How a segment ends: still on the same conic when the search stopped, entering the
+// sphere of influence of another body, or hitting the surface of its own body
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SegmentEnd {
+    Horizon,
+    Enter(usize),
+    Impact,
+}
+
+// Part of a predicted trajectory on a single conic, up to `end_jd`
+#[derive(Clone, Copy, Debug)]
+pub struct Segment {
+    pub conic: Conic,
+    pub end_jd: f64,
+    pub end: SegmentEnd,
+}
+
+// Predicted trajectory as consecutive conics, starting with the ship's current one
+#[derive(Clone, Debug, Default)]
+pub struct Prediction {
+    pub segments: Vec<Segment>,
+}
+
+// Sphere the ship may cross, either leaving the sphere of influence it is in
+// (`inside`), entering a moon's, or reaching the surface of the body it orbits.
+// `moon` is the body at the sphere's center when it is not the conic's own.
+struct Boundary {
+    moon: Option<usize>,
+    radius: f64,
+    inside: bool,
+    end: SegmentEnd,
+    closing_speed: f64, // upper bound of how fast the ship can reach the edge, au/day
+}
+
+impl Conic {
+    // Orbit around the body whose sphere of influence contains the heliocentric state
+    pub fn from_state(system: &SolarSystem, position: &DVec3, velocity: &DVec3, jd: f64) -> Self {
+        let body = system.soi_body(position, &system.heliocentric_positions(jd));
+        Conic::around(system, body, position, velocity, jd)
+    }
+
+    pub fn around(system: &SolarSystem, body: usize, position: &DVec3, velocity: &DVec3, jd: f64) -> Self {
+        let (body_position, body_velocity) = body_state(system, body, jd);
+        Conic {
+            body,
+            jd,
+            position: position - body_position,
+            velocity: velocity - body_velocity,
+            mu: system.gm_au(body),
+        }
+    }
+
+    // Position and velocity relative to the body
+    pub fn state_at(&self, jd: f64) -> (DVec3, DVec3) {
+        propagate(&self.position, &self.velocity, jd - self.jd, self.mu)
+    }
+
+    pub fn heliocentric_state(&self, system: &SolarSystem, jd: f64) -> (DVec3, DVec3) {
+        let (position, velocity) = self.state_at(jd);
+        let (body_position, body_velocity) = body_state(system, self.body, jd);
+        (body_position + position, body_velocity + velocity)
+    }
+
+    // Negative for hyperbolic orbits
+    pub fn semi_major_axis(&self) -> f64 {
+        1.0 / (2.0 / self.position.magnitude() - self.velocity.magnitude_squared() / self.mu)
+    }
+
+    pub fn eccentricity_vector(&self) -> DVec3 {
+        let r = self.position.magnitude();
+        (self.position * (self.velocity.magnitude_squared() - self.mu / r)
+            - self.velocity * self.position.dot(&self.velocity)) / self.mu
+    }
+
+    pub fn eccentricity(&self) -> f64 {
+        self.eccentricity_vector().magnitude()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.eccentricity() < 1.0
+    }
+
+    // Semi-latus rectum, which stays finite for parabolas
+    fn semi_latus_rectum(&self) -> f64 {
+        self.position.cross(&self.velocity).magnitude_squared() / self.mu
+    }
+
+    // Distances from the body's center
+    pub fn periapsis(&self) -> f64 {
+        self.semi_latus_rectum() / (1.0 + self.eccentricity())
+    }
+
+    pub fn apoapsis(&self) -> Option<f64> {
+        let e = self.eccentricity();
+        (e < 1.0).then(|| self.semi_latus_rectum() / (1.0 - e))
+    }
+
+    pub fn period(&self) -> Option<f64> {
+        self.is_closed().then(|| 2.0 * PI * (self.semi_major_axis().powi(3) / self.mu).sqrt())
+    }
+
+    // Fastest speed on the orbit, reached at periapsis
+    pub fn periapsis_speed(&self) -> f64 {
+        (self.mu * (1.0 + self.eccentricity()) / self.periapsis()).sqrt()
+    }
+
+    // Unit vectors towards periapsis and 90° ahead of it in the orbital plane. Circular
+    // orbits have no periapsis, so the current position stands in for it.
+    fn perifocal_basis(&self) -> (DVec3, DVec3) {
+        let eccentricity = self.eccentricity_vector();
+        let p = if eccentricity.magnitude() > 1e-9 {
+            eccentricity.normalize()
+        } else {
+            self.position.normalize()
+        };
+        let q = self.position.cross(&self.velocity).normalize().cross(&p);
+        (p, q)
+    }
+
+    pub fn periapsis_position(&self) -> DVec3 {
+        self.perifocal_basis().0 * self.periapsis()
+    }
+
+    pub fn apoapsis_position(&self) -> Option<DVec3> {
+        self.apoapsis().map(|apoapsis| -self.perifocal_basis().0 * apoapsis)
+    }
+
+    fn true_anomaly(&self, position: &DVec3) -> f64 {
+        let (p, q) = self.perifocal_basis();
+        position.dot(&q).atan2(position.dot(&p))
+    }
+
+    // Position relative to the body at a true anomaly
+    fn position_at_anomaly(&self, anomaly: f64) -> DVec3 {
+        let (p, q) = self.perifocal_basis();
+        let radius = self.semi_latus_rectum() / (1.0 + self.eccentricity() * anomaly.cos());
+        (p * anomaly.cos() + q * anomaly.sin()) * radius
+    }
+}
+
+impl Segment {
+    // Apoapsis beyond the sphere of influence is never reached
+    pub fn reaches_apoapsis(&self, system: &SolarSystem) -> bool {
+        self.conic.apoapsis().is_some_and(|apoapsis| apoapsis < system.sphere_of_influence(self.conic.body))
+    }
+
+    // Positions relative to the body from where the ship is at `jd` to the end of
+    // the segment, sampled evenly in true anomaly so fast periapsis passes stay smooth
+    fn path(&self, jd: f64, samples: usize) -> Vec<DVec3> {
+        let conic = &self.conic;
+        let start_jd = jd.clamp(conic.jd, self.end_jd);
+        let start = conic.true_anomaly(&conic.state_at(start_jd).0);
+        let end = if conic.period().is_some_and(|period| self.end_jd - start_jd >= period) {
+            start + 2.0 * PI
+        } else {
+            let anomaly = conic.true_anomaly(&conic.state_at(self.end_jd).0);
+            start + (anomaly - start).rem_euclid(2.0 * PI)
+        };
+
+        (0..=samples)
+            .map(|i| conic.position_at_anomaly(start + (end - start) * i as f64 / samples as f64))
+            .collect()
+    }
+}
+
+impl Prediction {
+    // Up to `max_segments` conics from a heliocentric state. Closed orbits that stay
+    // in their sphere end after one revolution.
+    pub fn new(system: &SolarSystem, position: &DVec3, velocity: &DVec3, jd: f64, max_segments: usize) -> Self {
+        let mut segments = Vec::with_capacity(max_segments);
+        let mut conic = Conic::from_state(system, position, velocity, jd);
+
+        while segments.len() < max_segments {
+            let horizon = conic.period().map_or(HORIZON, |period| period.min(HORIZON));
+            let (end_jd, end) = find_transition(system, &conic, conic.jd + horizon);
+            segments.push(Segment { conic, end_jd, end });
+
+            let SegmentEnd::Enter(next) = end else {
+                break;
+            };
+            let (position, velocity) = conic.heliocentric_state(system, end_jd);
+            conic = Conic::around(system, next, &position, &velocity, end_jd);
+        }
+        Prediction { segments }
+    }
+
+    pub fn current(&self) -> Option<&Segment> {
+        self.segments.first()
+    }
+
+    // Scene position segment `index` is drawn around at Julian day `jd`. Like a map
+    // view, everything follows the body the ship orbits now: an encounter with one
+    // of its moons is drawn where the moon will be relative to the body's current
+    // position, other bodies where they are now.
+    pub fn anchor(&self, system: &SolarSystem, scale: &DisplayScale, index: usize, jd: f64) -> Vec3 {
+        let body = self.segments[index].conic.body;
+        let frame = self.segments[0].conic.body;
+        let now = system.scene_positions(jd, scale);
+        if body != frame && descends_from(system, body, frame) {
+            let then = system.scene_positions(self.segments[index].conic.jd, scale);
+            now[frame] + then[body] - then[frame]
+        } else {
+            now[body]
+        }
+    }
+
+    pub fn scene_points(&self, system: &SolarSystem, scale: &DisplayScale, index: usize, jd: f64, samples: usize) -> Vec<Vec3> {
+        let anchor = self.anchor(system, scale, index, jd);
+        let body = self.segments[index].conic.body;
+        self.segments[index]
+            .path(jd, samples)
+            .iter()
+            .map(|position| anchor + system.offset_from(body, position, scale))
+            .collect()
+    }
+
+    // Scene position of a point given relative to the body of segment `index`
+    pub fn scene_point(&self, system: &SolarSystem, scale: &DisplayScale, index: usize, jd: f64, relative: &DVec3) -> Vec3 {
+        self.anchor(system, scale, index, jd) + system.offset_from(self.segments[index].conic.body, relative, scale)
+    }
+}
+
+// Heliocentric position and velocity of a body
+pub fn body_state(system: &SolarSystem, body: usize, jd: f64) -> (DVec3, DVec3) {
+    (system.heliocentric_positions(jd)[body], system.heliocentric_velocity(body, jd))
+}
+
+// Follows the conic until it leaves its sphere of influence, enters a moon's or hits
+// the surface, up to `end_jd`. Returns when the search stopped and why.
+// Steps are as long as the ship can go without reaching any boundary, so crossings
+// are never skipped, and the crossing itself is found by bisection.
+pub fn find_transition(system: &SolarSystem, conic: &Conic, end_jd: f64) -> (f64, SegmentEnd) {
+    let boundaries = boundaries(system, conic);
+    if boundaries.is_empty() {
+        return (end_jd, SegmentEnd::Horizon);
+    }
+
+    let mut jd = conic.jd;
+    for _ in 0..MAX_STEPS {
+        if jd >= end_jd {
+            return (end_jd, SegmentEnd::Horizon);
+        }
+
+        let position = conic.state_at(jd).0;
+        let step = boundaries
+            .iter()
+            .map(|boundary| margin(system, boundary, &position, jd) / boundary.closing_speed)
+            .fold(f64::INFINITY, f64::min)
+            .max(MIN_STEP)
+            .min(end_jd - jd);
+        let next = jd + step;
+
+        let position = conic.state_at(next).0;
+        let crossing = boundaries
+            .iter()
+            .filter(|boundary| margin(system, boundary, &position, next) < 0.0)
+            .map(|boundary| (bisect(system, conic, boundary, jd, next), boundary.end))
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+        if let Some(crossing) = crossing {
+            return crossing;
+        }
+
+        jd = next;
+    }
+    (jd, SegmentEnd::Horizon)
+}
+
+// Predicted path, one color per conic, with periapsis, apoapsis and transition markers
+pub fn render_prediction(framebuffer: &mut Framebuffer, uniforms: &Uniforms, system: &SolarSystem, prediction: &Prediction, jd: f64, scale: &DisplayScale) {
+    for (index, segment) in prediction.segments.iter().enumerate() {
+        let style = LineStyle::new(LinearColor::from_hex(SEGMENT_COLORS[index % SEGMENT_COLORS.len()])).with_width(1.5);
+        let points = prediction.scene_points(system, scale, index, jd, 256);
+        draw_polyline_3d(framebuffer, uniforms, &points, &style);
+
+        let marker = style.with_width(6.0);
+        let periapsis = prediction.scene_point(system, scale, index, jd, &segment.conic.periapsis_position());
+        draw_point_3d(framebuffer, uniforms, periapsis, &marker);
+        if segment.reaches_apoapsis(system) {
+            if let Some(apoapsis) = segment.conic.apoapsis_position() {
+                draw_point_3d(framebuffer, uniforms, prediction.scene_point(system, scale, index, jd, &apoapsis), &marker);
+            }
+        }
+        if segment.end != SegmentEnd::Horizon {
+            if let Some(end) = points.last() {
+                let width = if segment.end == SegmentEnd::Impact { 10.0 } else { 8.0 };
+                draw_point_3d(framebuffer, uniforms, *end, &marker.with_width(width));
+            }
+        }
+    }
+}
+
+// Whether `body` orbits `ancestor`, directly or through its parents
+fn descends_from(system: &SolarSystem, body: usize, ancestor: usize) -> bool {
+    let mut current = system.bodies[body].parent;
+    while let Some(parent) = current {
+        if parent == ancestor {
+            return true;
+        }
+        current = system.bodies[parent].parent;
+    }
+    false
+}
+
+// Only spheres the conic can actually reach are searched, which keeps low orbits
+// from stepping a minute at a time towards a surface they never touch
+fn boundaries(system: &SolarSystem, conic: &Conic) -> Vec<Boundary> {
+    let speed = conic.periapsis_speed();
+    let mut boundaries = Vec::new();
+
+    let surface = system.bodies[conic.body].radius_km / KM_PER_AU;
+    if conic.periapsis() < surface {
+        boundaries.push(Boundary {
+            moon: None,
+            radius: surface,
+            inside: false,
+            end: SegmentEnd::Impact,
+            closing_speed: speed,
+        });
+    }
+
+    if let Some(parent) = system.bodies[conic.body].parent {
+        let radius = system.sphere_of_influence(conic.body);
+        if conic.apoapsis().is_none_or(|apoapsis| apoapsis >= radius) {
+            boundaries.push(Boundary {
+                moon: None,
+                radius,
+                inside: true,
+                end: SegmentEnd::Enter(parent),
+                closing_speed: speed,
+            });
+        }
+    }
+
+    for (index, body) in system.bodies.iter().enumerate() {
+        if body.parent != Some(conic.body) {
+            continue;
+        }
+        let Some(orbit) = body.orbit else {
+            continue;
+        };
+        // Fastest the moon moves, at its periapsis
+        let elements = orbit.elements_at(conic.jd);
+        let moon_speed = (conic.mu * (1.0 + elements.e) / (elements.a * (1.0 - elements.e))).sqrt();
+        boundaries.push(Boundary {
+            moon: Some(index),
+            radius: system.sphere_of_influence(index),
+            inside: false,
+            end: SegmentEnd::Enter(index),
+            closing_speed: speed + moon_speed,
+        });
+    }
+    boundaries
+}
+
+// Distance to the boundary, negative once it has been crossed
+fn margin(system: &SolarSystem, boundary: &Boundary, position: &DVec3, jd: f64) -> f64 {
+    let distance = match boundary.moon {
+        Some(moon) => (position - system.relative_position(moon, jd)).magnitude(),
+        None => position.magnitude(),
+    };
+    if boundary.inside {
+        boundary.radius - distance
+    } else {
+        distance - boundary.radius
+    }
+}
+
+fn bisect(system: &SolarSystem, conic: &Conic, boundary: &Boundary, mut before: f64, mut after: f64) -> f64 {
+    for _ in 0..BISECTIONS {
+        let middle = 0.5 * (before + after);
+        if margin(system, boundary, &conic.state_at(middle).0, middle) < 0.0 {
+            after = middle;
+        } else {
+            before = middle;
+        }
+    }
+    after
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ephemeris::J2000;
+
+    fn solar_system() -> SolarSystem {
+        SolarSystem::load("assets/ephemeris/solar_system.csv").unwrap()
+    }
+
+    // Heliocentric state `offset` away from a body, moving at `speed` at right angles
+    // to the offset
+    fn state_near(system: &SolarSystem, body: usize, offset: f64, speed: f64) -> (DVec3, DVec3) {
+        let (position, velocity) = body_state(system, body, J2000);
+        (position + DVec3::new(offset, 0.0, 0.0), velocity + DVec3::new(0.0, speed, 0.0))
+    }
+
+    #[test]
+    fn ellipse_apsides_and_period() {
+        // Periapsis at r = 1 on an orbit with a = 2, so e = 0.5 and apoapsis 3
+        let mu: f64 = 1.0;
+        let speed = (mu * (2.0 - 1.0 / 2.0)).sqrt();
+        let conic = Conic { body: 0, jd: 0.0, position: DVec3::new(1.0, 0.0, 0.0), velocity: DVec3::new(0.0, speed, 0.0), mu };
+
+        assert!((conic.semi_major_axis() - 2.0).abs() < 1e-12);
+        assert!((conic.eccentricity() - 0.5).abs() < 1e-12);
+        assert!((conic.periapsis() - 1.0).abs() < 1e-12);
+        assert!((conic.apoapsis().unwrap() - 3.0).abs() < 1e-12);
+        assert!((conic.period().unwrap() - 2.0 * PI * 8.0f64.sqrt()).abs() < 1e-9);
+        assert!((conic.apoapsis_position().unwrap() - DVec3::new(-3.0, 0.0, 0.0)).magnitude() < 1e-12);
+    }
+
+    #[test]
+    fn hyperbolic_escape_enters_the_parent_at_the_sphere_of_influence() {
+        let system = solar_system();
+        let (earth, moon) = (system.find("Earth").unwrap(), system.find("Moon").unwrap());
+        let offset = 5.0 * system.bodies[moon].radius_km / KM_PER_AU;
+        let escape = (2.0 * system.gm_au(moon) / offset).sqrt();
+        let (position, velocity) = state_near(&system, moon, offset, 1.5 * escape);
+
+        let prediction = Prediction::new(&system, &position, &velocity, J2000, 2);
+        assert_eq!(prediction.segments.len(), 2);
+        let escape = &prediction.segments[0];
+        assert_eq!(escape.conic.body, moon);
+        assert!(escape.conic.apoapsis().is_none());
+        assert_eq!(escape.end, SegmentEnd::Enter(earth));
+        assert_eq!(prediction.segments[1].conic.body, earth);
+
+        let soi = system.sphere_of_influence(moon);
+        let distance = escape.conic.state_at(escape.end_jd).0.magnitude();
+        assert!((distance - soi).abs() < 1e-6 * soi, "{} {}", distance, soi);
+    }
+
+    #[test]
+    fn periapsis_below_the_surface_ends_in_an_impact() {
+        let system = solar_system();
+        let earth = system.find("Earth").unwrap();
+        let surface = system.bodies[earth].radius_km / KM_PER_AU;
+        let circular = (system.gm_au(earth) / (10.0 * surface)).sqrt();
+        let (position, velocity) = state_near(&system, earth, 10.0 * surface, 0.2 * circular);
+
+        let prediction = Prediction::new(&system, &position, &velocity, J2000, 3);
+        assert_eq!(prediction.segments.len(), 1);
+        let fall = &prediction.segments[0];
+        assert_eq!(fall.conic.body, earth);
+        assert!(fall.conic.periapsis() < surface);
+        assert_eq!(fall.end, SegmentEnd::Impact);
+
+        let distance = fall.conic.state_at(fall.end_jd).0.magnitude();
+        assert!((distance - surface).abs() < 1e-6 * surface, "{} {}", distance, surface);
+    }
+}
@@ -0,0 +1,699 @@
+// ephemeris.rs
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::ops::Add;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use nalgebra_glm::{DVec3, Vec3};
+use serde::Deserialize;
+use crate::renderer::ShaderType;
+use crate::color::Color;
+use crate::planet::Archetype;
+use crate::material::{MaterialPaths, LayerPath};
+use crate::texture::{WrapMode, Filter};
+
+pub const J2000: f64 = 2451545.0;
+pub const DAYS_PER_CENTURY: f64 = 36525.0;
+pub const KM_PER_AU: f64 = 149597870.7;
+pub const SECONDS_PER_DAY: f64 = 86400.0;
+const EARTH_RADIUS_KM: f64 = 6371.0;
+const DEFAULT_ORBIT_COLOR: u32 = 0x888888;
+
+// Keplerian elements in the layout of the JPL approximate-element tables.
+// Distances in au, angles in degrees.
+#[derive(Debug, Clone, Copy)]
+pub struct OrbitalElements {
+    pub a: f64,
+    pub e: f64,
+    pub i: f64,
+    pub l: f64,
+    pub long_peri: f64,
+    pub long_node: f64,
+}
+
+// Elements at J2000 plus their linear rates per Julian century
+#[derive(Debug, Clone, Copy)]
+pub struct Orbit {
+    pub elements: OrbitalElements,
+    pub rates: OrbitalElements,
+}
+
+pub struct Body {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub orbit: Option<Orbit>,
+    pub radius_km: f64,
+    pub gm: f64,
+    pub shader: ShaderType,
+    pub rings: bool,
+    pub orbit_color: Color,
+    pub trail_length: usize,
+    pub comet: bool,
+    pub temperature_k: Option<f64>, // stars only
+    pub archetype: Option<Archetype>, // generated look instead of the shader
+    pub maps: MaterialPaths, // textures, which take over from the shader when there is an albedo
+    pub merged_into: Option<usize>, // body that absorbed this one in a collision
+}
+
+pub struct SolarSystem {
+    pub bodies: Vec<Body>,
+}
+
+// Maps real distances and radii to scene units. An exponent below 1.0
+// compresses the range so the outer planets and the small bodies fit on screen.
+#[derive(Debug, Clone, Copy)]
+pub struct DisplayScale {
+    pub distance_unit: f32,     // scene units for 1 au
+    pub distance_exponent: f32,
+    pub radius_unit: f32,       // scene units for one Earth radius
+    pub radius_exponent: f32,
+}
+
+// One row of the elements file, shared by the CSV and JSON loaders
+#[derive(Debug, Deserialize)]
+struct BodyRecord {
+    name: String,
+    parent: Option<String>,
+    a: f64,
+    e: f64,
+    i: f64,
+    l: f64,
+    long_peri: f64,
+    long_node: f64,
+    a_rate: f64,
+    e_rate: f64,
+    i_rate: f64,
+    l_rate: f64,
+    long_peri_rate: f64,
+    long_node_rate: f64,
+    radius_km: f64,
+    gm: f64,
+    shader: ShaderType,
+    #[serde(default)]
+    rings: bool,
+    #[serde(default)]
+    orbit_color: Option<String>,
+    #[serde(default)]
+    trail_length: usize,
+    #[serde(default)]
+    comet: bool,
+    #[serde(default)]
+    temperature_k: Option<f64>,
+    #[serde(default)]
+    archetype: Option<Archetype>,
+    #[serde(default)]
+    albedo_map: Option<String>,
+    #[serde(default)]
+    normal_map: Option<String>,
+    #[serde(default)]
+    specular_map: Option<String>,
+    #[serde(default)]
+    emissive_map: Option<String>,
+    #[serde(default)]
+    cloud_map: Option<String>,
+    #[serde(default)]
+    height_map: Option<String>,
+    // How each layer is sampled, repeat and trilinear when left out
+    #[serde(default)]
+    albedo_wrap: Option<WrapMode>,
+    #[serde(default)]
+    albedo_filter: Option<Filter>,
+    #[serde(default)]
+    normal_wrap: Option<WrapMode>,
+    #[serde(default)]
+    normal_filter: Option<Filter>,
+    #[serde(default)]
+    specular_wrap: Option<WrapMode>,
+    #[serde(default)]
+    specular_filter: Option<Filter>,
+    #[serde(default)]
+    emissive_wrap: Option<WrapMode>,
+    #[serde(default)]
+    emissive_filter: Option<Filter>,
+    #[serde(default)]
+    cloud_wrap: Option<WrapMode>,
+    #[serde(default)]
+    cloud_filter: Option<Filter>,
+    #[serde(default)]
+    height_wrap: Option<WrapMode>,
+    #[serde(default)]
+    height_filter: Option<Filter>,
+}
+
+impl OrbitalElements {
+    // Position in the J2000 ecliptic frame, in au
+    pub fn position(&self) -> DVec3 {
+        let (x, y) = self.perifocal_position(eccentric_anomaly(self.mean_anomaly(), self.e));
+        self.ecliptic_position(x, y)
+    }
+
+    pub fn mean_anomaly(&self) -> f64 {
+        (self.l - self.long_peri).to_radians()
+    }
+
+    // Point on the orbit for a given eccentric anomaly, in the orbital plane
+    pub fn perifocal_position(&self, eccentric_anomaly: f64) -> (f64, f64) {
+        let x = self.a * (eccentric_anomaly.cos() - self.e);
+        let y = self.a * (1.0 - self.e * self.e).sqrt() * eccentric_anomaly.sin();
+        (x, y)
+    }
+
+    pub fn ecliptic_position(&self, x: f64, y: f64) -> DVec3 {
+        let arg_peri = (self.long_peri - self.long_node).to_radians();
+        let (sin_w, cos_w) = arg_peri.sin_cos();
+        let (sin_n, cos_n) = self.long_node.to_radians().sin_cos();
+        let (sin_i, cos_i) = self.i.to_radians().sin_cos();
+
+        DVec3::new(
+            (cos_w * cos_n - sin_w * sin_n * cos_i) * x + (-sin_w * cos_n - cos_w * sin_n * cos_i) * y,
+            (cos_w * sin_n + sin_w * cos_n * cos_i) * x + (-sin_w * sin_n + cos_w * cos_n * cos_i) * y,
+            (sin_w * sin_i) * x + (cos_w * sin_i) * y,
+        )
+    }
+}
+
+impl Orbit {
+    pub fn elements_at(&self, jd: f64) -> OrbitalElements {
+        let t = (jd - J2000) / DAYS_PER_CENTURY;
+        let (e, r) = (&self.elements, &self.rates);
+
+        OrbitalElements {
+            a: e.a + r.a * t,
+            e: e.e + r.e * t,
+            i: e.i + r.i * t,
+            l: e.l + r.l * t,
+            long_peri: e.long_peri + r.long_peri * t,
+            long_node: e.long_node + r.long_node * t,
+        }
+    }
+
+    pub fn position(&self, jd: f64) -> DVec3 {
+        self.elements_at(jd).position()
+    }
+}
+
+impl DisplayScale {
+    pub fn compressed() -> Self {
+        DisplayScale {
+            distance_unit: 10.0,
+            distance_exponent: 0.5,
+            radius_unit: 0.3,
+            radius_exponent: 0.4,
+        }
+    }
+
+    // Distances and radii share the same unit, so sizes are physically correct
+    pub fn true_scale(distance_unit: f32) -> Self {
+        DisplayScale {
+            distance_unit,
+            distance_exponent: 1.0,
+            radius_unit: distance_unit * (EARTH_RADIUS_KM / KM_PER_AU) as f32,
+            radius_exponent: 1.0,
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.distance_exponent < 1.0 || self.radius_exponent < 1.0
+    }
+
+    pub fn distance(&self, au: f64) -> f32 {
+        self.distance_unit * (au as f32).powf(self.distance_exponent)
+    }
+
+    pub fn radius(&self, radius_km: f64) -> f32 {
+        self.radius_unit * ((radius_km / EARTH_RADIUS_KM) as f32).powf(self.radius_exponent)
+    }
+}
+
+impl SolarSystem {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let is_json = Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+
+        let records: Vec<BodyRecord> = if is_json {
+            serde_json::from_reader(file)?
+        } else {
+            csv::ReaderBuilder::new()
+                .comment(Some(b'#'))
+                .trim(csv::Trim::All)
+                .from_reader(file)
+                .deserialize()
+                .collect::<Result<_, _>>()?
+        };
+
+        Self::from_records(records)
+    }
+
+    fn from_records(records: Vec<BodyRecord>) -> Result<Self, Box<dyn Error>> {
+        let mut bodies: Vec<Body> = Vec::with_capacity(records.len());
+
+        for record in records {
+            // Parents must be listed before their satellites
+            let parent = match record.parent.as_deref() {
+                Some(name) if !name.is_empty() => Some(
+                    bodies.iter()
+                        .position(|body| body.name == name)
+                        .ok_or_else(|| format!("{}: unknown parent body '{}'", record.name, name))?,
+                ),
+                _ => None,
+            };
+
+            let orbit = if parent.is_some() && record.a > 0.0 {
+                Some(Orbit {
+                    elements: OrbitalElements {
+                        a: record.a,
+                        e: record.e,
+                        i: record.i,
+                        l: record.l,
+                        long_peri: record.long_peri,
+                        long_node: record.long_node,
+                    },
+                    rates: OrbitalElements {
+                        a: record.a_rate,
+                        e: record.e_rate,
+                        i: record.i_rate,
+                        l: record.l_rate,
+                        long_peri: record.long_peri_rate,
+                        long_node: record.long_node_rate,
+                    },
+                })
+            } else {
+                None
+            };
+
+            let orbit_color = match record.orbit_color.as_deref() {
+                Some(hex) if !hex.is_empty() => Color::parse_hex(hex)
+                    .ok_or_else(|| format!("{}: invalid orbit color '{}'", record.name, hex))?,
+                _ => Color::from_hex(DEFAULT_ORBIT_COLOR),
+            };
+
+            bodies.push(Body {
+                name: record.name,
+                parent,
+                orbit,
+                radius_km: record.radius_km,
+                gm: record.gm,
+                shader: record.shader,
+                rings: record.rings,
+                orbit_color,
+                trail_length: record.trail_length,
+                comet: record.comet,
+                temperature_k: record.temperature_k,
+                archetype: record.archetype,
+                maps: MaterialPaths {
+                    albedo: layer_path(record.albedo_map, record.albedo_wrap, record.albedo_filter),
+                    normal: layer_path(record.normal_map, record.normal_wrap, record.normal_filter),
+                    specular: layer_path(record.specular_map, record.specular_wrap, record.specular_filter),
+                    emissive: layer_path(record.emissive_map, record.emissive_wrap, record.emissive_filter),
+                    clouds: layer_path(record.cloud_map, record.cloud_wrap, record.cloud_filter),
+                    height: layer_path(record.height_map, record.height_wrap, record.height_filter),
+                },
+                merged_into: None,
+            });
+        }
+
+        Ok(SolarSystem { bodies })
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.bodies.iter().position(|body| body.name.eq_ignore_ascii_case(name))
+    }
+
+    // Position relative to the parent body, in au and ecliptic coordinates
+    pub fn relative_position(&self, index: usize, jd: f64) -> DVec3 {
+        self.bodies[index].orbit
+            .map(|orbit| orbit.position(jd))
+            .unwrap_or_else(DVec3::zeros)
+    }
+
+    // Velocity relative to the parent body in au/day, by central differences
+    pub fn relative_velocity(&self, index: usize, jd: f64) -> DVec3 {
+        let h = 1e-3;
+        (self.relative_position(index, jd + h) - self.relative_position(index, jd - h)) / (2.0 * h)
+    }
+
+    // Heliocentric positions of every body, in au
+    pub fn heliocentric_positions(&self, jd: f64) -> Vec<DVec3> {
+        self.place_bodies(DVec3::zeros(), |index| self.relative_position(index, jd))
+    }
+
+    // Adds each body's offset to where its parent is, or puts it on the body that
+    // absorbed it. Merges can point to later bodies, so positions are placed on demand.
+    fn place_bodies<T: Copy + Add<Output = T>>(&self, origin: T, offset: impl Fn(usize) -> T) -> Vec<T> {
+        let mut placed: Vec<Option<T>> = vec![None; self.bodies.len()];
+        for index in 0..self.bodies.len() {
+            self.place_body(index, origin, &offset, &mut placed);
+        }
+        placed.into_iter().flatten().collect()
+    }
+
+    fn place_body<T: Copy + Add<Output = T>>(&self, index: usize, origin: T, offset: &impl Fn(usize) -> T, placed: &mut [Option<T>]) -> T {
+        if let Some(position) = placed[index] {
+            return position;
+        }
+        let body = &self.bodies[index];
+        let position = match (body.merged_into, body.parent) {
+            (Some(absorber), _) => self.place_body(absorber, origin, offset, placed),
+            (None, Some(parent)) => self.place_body(parent, origin, offset, placed) + offset(index),
+            (None, None) => origin,
+        };
+        placed[index] = Some(position);
+        position
+    }
+
+    // Whether the position of `index` is measured from `other`, through its parents
+    // or the bodies that absorbed it
+    pub fn depends_on(&self, index: usize, other: usize) -> bool {
+        let body = &self.bodies[index];
+        let mut current = body.merged_into.or(body.parent);
+        while let Some(next) = current {
+            if next == other {
+                return true;
+            }
+            current = self.bodies[next].merged_into.or(self.bodies[next].parent);
+        }
+        false
+    }
+
+    // Folds `absorbed` into `absorber` after a collision: masses add up, the volume is
+    // kept and the absorbed body's satellites carry on, with the same elements, around
+    // the absorber. The absorbed body stays in the list without mass or size.
+    pub fn merge(&mut self, absorber: usize, absorbed: usize) {
+        let (gm, radius_km) = (self.bodies[absorbed].gm, self.bodies[absorbed].radius_km);
+        let body = &mut self.bodies[absorber];
+        body.gm += gm;
+        body.radius_km = (body.radius_km.powi(3) + radius_km.powi(3)).cbrt();
+
+        let body = &mut self.bodies[absorbed];
+        body.merged_into = Some(absorber);
+        body.gm = 0.0;
+        body.radius_km = 0.0;
+        for body in &mut self.bodies {
+            if body.parent == Some(absorbed) {
+                body.parent = Some(absorber);
+            }
+        }
+    }
+
+    // Heliocentric velocity in au/day, by central differences
+    pub fn heliocentric_velocity(&self, index: usize, jd: f64) -> DVec3 {
+        let h = 1e-3;
+        (self.heliocentric_positions(jd + h)[index] - self.heliocentric_positions(jd - h)[index]) / (2.0 * h)
+    }
+
+    // Gravitational parameter in au^3/day^2
+    pub fn gm_au(&self, index: usize) -> f64 {
+        self.bodies[index].gm * SECONDS_PER_DAY * SECONDS_PER_DAY / KM_PER_AU.powi(3)
+    }
+
+    // Acceleration in au/day^2 at a heliocentric point, from every body
+    pub fn gravity(&self, point: &DVec3, positions: &[DVec3]) -> DVec3 {
+        let mut acceleration = DVec3::zeros();
+        for (index, position) in positions.iter().enumerate() {
+            let offset = position - point;
+            let distance_squared = offset.magnitude_squared();
+            if distance_squared > 0.0 {
+                acceleration += offset * (self.gm_au(index) / (distance_squared * distance_squared.sqrt()));
+            }
+        }
+        acceleration
+    }
+
+    // Laplace sphere of influence: inside it the body is the better center for a
+    // two-body orbit, with the parent's pull treated as a perturbation
+    pub fn sphere_of_influence(&self, index: usize) -> f64 {
+        let body = &self.bodies[index];
+        match (body.parent, body.orbit) {
+            (Some(parent), Some(orbit)) => orbit.elements.a * (body.gm / self.bodies[parent].gm).powf(0.4),
+            _ => f64::INFINITY,
+        }
+    }
+
+    // Innermost body whose sphere of influence contains the point
+    pub fn soi_body(&self, point: &DVec3, positions: &[DVec3]) -> usize {
+        let mut innermost = 0;
+        let mut smallest_radius = f64::INFINITY;
+        for (index, position) in positions.iter().enumerate() {
+            let zone = self.sphere_of_influence(index);
+            if (point - position).magnitude() < zone && zone < smallest_radius {
+                innermost = index;
+                smallest_radius = zone;
+            }
+        }
+        innermost
+    }
+
+    // Scene position of an arbitrary heliocentric point (a ship, a particle...). The
+    // point is compressed relative to the body whose sphere of influence holds it, blending
+    // towards the parent's mapping near the edge of the sphere so crossing it doesn't make
+    // the point jump.
+    pub fn scene_point(&self, point: &DVec3, positions: &[DVec3], scene_positions: &[Vec3], scale: &DisplayScale) -> Vec3 {
+        let reference = self.soi_body(point, positions);
+        let mapped = scene_positions[reference] + self.offset_from(reference, &(point - positions[reference]), scale);
+
+        let Some(parent) = self.bodies[reference].parent else {
+            return mapped;
+        };
+
+        let zone = self.sphere_of_influence(reference);
+        let blend = smoothstep(0.7 * zone, zone, (point - positions[reference]).magnitude()) as f32;
+        let parent_mapped = scene_positions[parent] + self.offset_from(parent, &(point - positions[parent]), scale);
+        mapped.lerp(&parent_mapped, blend)
+    }
+
+    pub fn display_radius(&self, index: usize, scale: &DisplayScale) -> f32 {
+        scale.radius(self.bodies[index].radius_km)
+    }
+
+    // Scene positions of every body at the given Julian day
+    pub fn scene_positions(&self, jd: f64, scale: &DisplayScale) -> Vec<Vec3> {
+        self.place_bodies(Vec3::zeros(), |index| self.scene_offset(index, &self.relative_position(index, jd), scale))
+    }
+
+    // Closed polyline following the body's orbit at the given Julian day, in scene
+    // coordinates. `positions` are the current scene positions from `scene_positions`.
+    pub fn orbit_path(&self, index: usize, jd: f64, positions: &[Vec3], scale: &DisplayScale, segments: usize) -> Vec<Vec3> {
+        let body = &self.bodies[index];
+        let (Some(orbit), Some(parent)) = (body.orbit, body.parent) else {
+            return Vec::new();
+        };
+
+        let elements = orbit.elements_at(jd);
+        (0..=segments)
+            .map(|i| {
+                let eccentric_anomaly = 2.0 * PI * i as f64 / segments as f64;
+                let (x, y) = elements.perifocal_position(eccentric_anomaly);
+                positions[parent] + self.scene_offset(index, &elements.ecliptic_position(x, y), scale)
+            })
+            .collect()
+    }
+
+    // Offset from the parent in scene units for a position relative to it in au
+    fn scene_offset(&self, index: usize, relative: &DVec3, scale: &DisplayScale) -> Vec3 {
+        match self.bodies[index].parent {
+            Some(parent) => self.offset_from(parent, relative, scale),
+            None => Vec3::zeros(),
+        }
+    }
+
+    // Scene offset of a point given relative to `center`, in au
+    pub fn offset_from(&self, center: usize, relative: &DVec3, scale: &DisplayScale) -> Vec3 {
+        let distance = relative.magnitude();
+        if distance <= 0.0 {
+            return Vec3::zeros();
+        }
+
+        // Compressed distances are measured from the center's surface,
+        // otherwise inner moons end up inside their inflated planet
+        let mut display_distance = scale.distance(distance);
+        if scale.is_compressed() && self.bodies[center].parent.is_some() {
+            let altitude = (distance - self.bodies[center].radius_km / KM_PER_AU).max(0.0);
+            display_distance = scale.distance(altitude) + self.display_radius(center, scale);
+        }
+
+        ecliptic_to_scene(&(relative / distance)) * display_distance
+    }
+}
+
+// A material layer from its columns; an empty path leaves the layer out
+fn layer_path(path: Option<String>, wrap: Option<WrapMode>, filter: Option<Filter>) -> Option<LayerPath> {
+    let path = path.filter(|path| !path.is_empty())?;
+    Some(LayerPath { path, wrap: wrap.unwrap_or_default(), filter: filter.unwrap_or_default() })
+}
+
+// The ecliptic is z-up, the scene is y-up
+pub fn ecliptic_to_scene(v: &DVec3) -> Vec3 {
+    Vec3::new(v.x as f32, v.z as f32, -v.y as f32)
+}
+
+pub fn scene_to_ecliptic(v: &Vec3) -> DVec3 {
+    DVec3::new(v.x as f64, -v.z as f64, v.y as f64)
+}
+
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Solves Kepler's equation M = E - e sin(E) with Newton's method
+pub fn eccentric_anomaly(mean_anomaly: f64, e: f64) -> f64 {
+    let m = (mean_anomaly + PI).rem_euclid(2.0 * PI) - PI;
+    let mut ecc = if e < 0.8 { m } else { PI.copysign(m) };
+
+    for _ in 0..50 {
+        let delta = (ecc - e * ecc.sin() - m) / (1.0 - e * ecc.cos());
+        ecc -= delta;
+        if delta.abs() < 1e-12 {
+            break;
+        }
+    }
+
+    ecc
+}
+
+// Julian day of a proleptic Gregorian calendar date (Meeus, Astronomical Algorithms ch. 7)
+pub fn julian_day(year: i32, month: u32, day: f64) -> f64 {
+    let (y, m) = if month <= 2 { (year - 1, month + 12) } else { (year, month) };
+    let a = (y as f64 / 100.0).floor();
+    let b = 2.0 - a + (a / 4.0).floor();
+
+    (365.25 * (y as f64 + 4716.0)).floor() + (30.6001 * (m as f64 + 1.0)).floor() + day + b - 1524.5
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Parses "YYYY-MM-DD" with an optional "THH:MM" time of day (UTC)
+pub fn parse_date(date: &str) -> Result<f64, String> {
+    let (date, time) = match date.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (date, None),
+    };
+
+    // Leading '-' is allowed for years before the common era
+    let (sign, date) = match date.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, date),
+    };
+    let fields: Vec<&str> = date.split('-').collect();
+    let [year, month, day] = fields[..] else {
+        return Err(format!("expected YYYY-MM-DD, got {}", date));
+    };
+    let year = sign * year.parse::<i32>().map_err(|_| format!("invalid year {}", year))?;
+    let month: u32 = month.parse().map_err(|_| format!("invalid month {}", month))?;
+    let day: u32 = day.parse().map_err(|_| format!("invalid day {}", day))?;
+    if !(1..=12).contains(&month) {
+        return Err(format!("month {} is out of range", month));
+    }
+    if !(1..=days_in_month(year, month)).contains(&day) {
+        return Err(format!("{:04}-{:02} has no day {}", year, month, day));
+    }
+
+    let fraction = match time {
+        Some(time) => {
+            let (hours, minutes) = time.split_once(':').unwrap_or((time, "0"));
+            let hours: f64 = hours.parse().map_err(|_| format!("invalid hour {}", hours))?;
+            let minutes: f64 = minutes.parse().map_err(|_| format!("invalid minutes {}", minutes))?;
+            if !(0.0..24.0).contains(&hours) || !(0.0..60.0).contains(&minutes) {
+                return Err(format!("time {} is out of range", time));
+            }
+            (hours + minutes / 60.0) / 24.0
+        }
+        None => 0.0,
+    };
+
+    Ok(julian_day(year, month, day as f64 + fraction))
+}
+
+// Calendar date "YYYY-MM-DD HH:MM" (UTC) of a Julian day, inverse of `julian_day`.
+// Dates before 1582 stay in the proleptic Gregorian calendar so both directions agree.
+pub fn format_date(jd: f64) -> String {
+    // Snap to the nearest minute first, so round trips don't lose one to truncation
+    let jd = (jd * 1440.0).round() / 1440.0 + 0.5;
+    let z = jd.floor();
+    let alpha = ((z - 1867216.25) / 36524.25).floor();
+    let a = z + 1.0 + alpha - (alpha / 4.0).floor();
+    let b = a + 1524.0;
+    let c = ((b - 122.1) / 365.25).floor();
+    let d = (365.25 * c).floor();
+    let e = ((b - d) / 30.6001).floor();
+
+    let day = b - d - (30.6001 * e).floor();
+    let month = if e < 14.0 { e - 1.0 } else { e - 13.0 };
+    let year = if month > 2.0 { c - 4716.0 } else { c - 4715.0 };
+    let minutes = ((jd - z) * 1440.0).round();
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, (minutes / 60.0).floor(), minutes % 60.0)
+}
+
+pub fn julian_day_now() -> f64 {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs_f64())
+        .unwrap_or(0.0);
+    2440587.5 + seconds / 86400.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn j2000_epoch() {
+        assert_eq!(julian_day(2000, 1, 1.5), 2451545.0);
+        assert_eq!(parse_date("2000-01-01T12:00"), Ok(2451545.0));
+        assert_eq!(format_date(2451545.0), "2000-01-01 12:00");
+    }
+
+    #[test]
+    fn dates_round_trip() {
+        for date in ["1969-07-20 20:17", "2024-02-29 00:00", "1582-10-04 06:30", "0800-12-25 00:00"] {
+            let jd = parse_date(&date.replace(' ', "T")).unwrap();
+            assert_eq!(format_date(jd), date);
+        }
+    }
+
+    #[test]
+    fn material_layers_keep_their_sampling() {
+        let system = SolarSystem::load("assets/ephemeris/solar_system.csv").unwrap();
+        let neptune = &system.bodies[system.find("Neptune").unwrap()];
+        let albedo = neptune.maps.albedo.as_ref().unwrap();
+        assert_eq!((albedo.wrap, albedo.filter), (WrapMode::Repeat, Filter::Trilinear));
+        assert!(neptune.maps.normal.is_none());
+
+        let records = serde_json::from_str(r#"[{
+            "name": "Sun", "a": 0, "e": 0, "i": 0, "l": 0, "long_peri": 0, "long_node": 0,
+            "a_rate": 0, "e_rate": 0, "i_rate": 0, "l_rate": 0, "long_peri_rate": 0, "long_node_rate": 0,
+            "radius_km": 695700, "gm": 132712440018, "shader": "Lava",
+            "albedo_map": "sun.png", "albedo_wrap": "mirror", "albedo_filter": "nearest", "cloud_map": ""
+        }]"#).unwrap();
+        let system = SolarSystem::from_records(records).unwrap();
+        let albedo = system.bodies[0].maps.albedo.as_ref().unwrap();
+        assert_eq!((albedo.wrap, albedo.filter), (WrapMode::Mirror, Filter::Nearest));
+        assert!(system.bodies[0].maps.clouds.is_none());
+    }
+
+    #[test]
+    fn rejects_days_past_the_end_of_the_month() {
+        assert!(parse_date("2023-02-29").is_err());
+        assert!(parse_date("1900-02-29").is_err());
+        assert!(parse_date("2000-02-29").is_ok());
+        assert!(parse_date("2024-04-31").is_err());
+        assert!(parse_date("2024-13-01").is_err());
+        assert!(parse_date("2024-01-01T24:00").is_err());
+        assert!(parse_date("2024-01").is_err());
+    }
+}
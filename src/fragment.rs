@@ -1,36 +1,32 @@
 use nalgebra_glm::{Vec3, Vec2};
 use crate::color::Color;
 
+// Positions and normals come in two spaces: object space stays fixed on the model no
+// matter how it moves or where the camera is, so surface patterns should be sampled
+// there; world space is what lighting needs.
 pub struct Fragment {
     pub position: Vec2,
     pub color: Color,
     pub depth: f32,
-    pub normal: Vec3,
     pub intensity: f32,
-    pub vertex_position: Vec3,
+    pub object_position: Vec3,
+    pub object_normal: Vec3,
+    pub world_position: Vec3,
+    pub world_normal: Vec3,
+    // World-space direction in which u grows, and whether v grows along normal × tangent
+    // (1) or against it (-1). Together with the normal they frame normal maps.
+    pub world_tangent: Vec3,
+    pub bitangent_sign: f32,
     pub tex_coords: Vec2,
+    // Change of the texture coordinates from one pixel to the next along x and y, which
+    // tells samplers how much of the texture a pixel covers
+    pub tex_dx: Vec2,
+    pub tex_dy: Vec2,
 }
 
 impl Fragment {
-    pub fn new(
-        position: Vec2,
-        color: Color,
-        depth: f32,
-        normal: Vec3,
-        intensity: f32,
-        vertex_position: Vec3,
-        tex_coords: Vec2,
-    ) -> Self {  
-        Fragment {
-            position,
-            color,
-            depth,
-            normal,
-            intensity,
-            vertex_position,
-            tex_coords
-        }
+    // Point on the unit sphere under this fragment, where spherical bodies sample noise
+    pub fn surface_point(&self) -> Vec3 {
+        self.object_position.try_normalize(1e-6).unwrap_or(Vec3::new(0.0, 1.0, 0.0))
     }
 }
-
-
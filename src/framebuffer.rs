@@ -1,4 +1,5 @@
 // framebuffer.rs
+use crate::color::LinearColor;
 
 pub struct Framebuffer {
     pub width: usize,
@@ -40,6 +41,34 @@ impl Framebuffer {
         }
     }
 
+    // Nothing has been drawn here since the last clear
+    pub fn is_empty(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.zbuffer[y * self.width + x] == f32::INFINITY
+    }
+
+    // Depth-tested alpha blend that leaves the z-buffer untouched. The destination is
+    // decoded to linear light, mixed there and encoded back to sRGB
+    pub fn blend_point(&mut self, x: isize, y: isize, depth: f32, color: LinearColor, alpha: f32) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            let index = y as usize * self.width + x as usize;
+            if self.zbuffer[index] > depth {
+                let background = LinearColor::from_hex(self.buffer[index]);
+                self.buffer[index] = background.lerp(&color, alpha).to_hex();
+            }
+        }
+    }
+
+    // Depth-tested additive blend that leaves the z-buffer untouched, for glowing
+    // particles whose overlaps should brighten; light adds up in linear space
+    pub fn add_point(&mut self, x: isize, y: isize, depth: f32, color: LinearColor) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            let index = y as usize * self.width + x as usize;
+            if self.zbuffer[index] > depth {
+                self.buffer[index] = (LinearColor::from_hex(self.buffer[index]) + color).to_hex();
+            }
+        }
+    }
+
     pub fn set_background_color(&mut self, color: u32) {
         self.background_color = color;
     }
@@ -55,18 +84,18 @@ impl Framebuffer {
         }
     }
 
+    // 2D overlay line in pixel coordinates, clipped to the screen. For
+    // depth-tested, anti-aliased lines in world space use `line::draw_line_3d`.
     pub fn draw_line(
         &mut self,
-        x0: usize,
-        y0: usize,
-        x1: usize,
-        y1: usize,
+        x0: isize,
+        y0: isize,
+        x1: isize,
+        y1: isize,
         color: u32,
     ) {
-        let mut x0 = x0 as isize;
-        let mut y0 = y0 as isize;
-        let x1 = x1 as isize;
-        let y1 = y1 as isize;
+        let mut x0 = x0;
+        let mut y0 = y0;
     
         let dx = (x1 - x0).abs();
         let dy = (y1 - y0).abs();
@@ -0,0 +1,214 @@
+// hud.rs
+use std::collections::VecDeque;
+use nalgebra_glm::Vec3;
+use crate::conic::{Prediction, SegmentEnd};
+use crate::ephemeris::{SolarSystem, DisplayScale, KM_PER_AU};
+use crate::framebuffer::Framebuffer;
+use crate::line::project_point;
+use crate::Uniforms;
+
+// 5x7 bitmap font, one byte per row with the leftmost pixel in bit 4
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 3;
+const PANEL_MARGIN: usize = 8;
+
+const TEXT_COLOR: u32 = 0xE8F0FF;
+const SHADOW_COLOR: u32 = 0x000000;
+const MESSAGE_COLOR: u32 = 0xFFE08A;
+
+// Messages stay this many frames, and only the newest few are kept
+const MESSAGE_FRAMES: u32 = 240;
+const MAX_MESSAGES: usize = 5;
+
+fn glyph(character: char) -> [u8; GLYPH_HEIGHT] {
+    match character.to_ascii_uppercase() {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '=' => [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '>' => [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08],
+        ' ' => [0x00; GLYPH_HEIGHT],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+// Draws text with its top-left corner at (x, y), over a one pixel drop shadow so it
+// stays readable on bright planets
+pub fn draw_text(framebuffer: &mut Framebuffer, x: isize, y: isize, text: &str, color: u32) {
+    for (offset, shade) in [(1, SHADOW_COLOR), (0, color)] {
+        for (index, character) in text.chars().enumerate() {
+            let left = x + offset + (index * (GLYPH_WIDTH + 1)) as isize;
+            for (row, bits) in glyph(character).iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (0x10 >> column) != 0 {
+                        let px = left + column as isize;
+                        let py = y + offset + row as isize;
+                        if px >= 0 && py >= 0 {
+                            framebuffer.set_pixel(px as usize, py as usize, shade);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Text next to a world-space point. Labels are skipped when the point is off screen
+// or when they would overlap one of the rectangles in `taken`, which they then join.
+pub fn draw_label(framebuffer: &mut Framebuffer, uniforms: &Uniforms, point: Vec3, text: &str, color: u32, taken: &mut Vec<[isize; 4]>) {
+    let Some(screen) = project_point(uniforms, point) else {
+        return;
+    };
+
+    let x = screen.x as isize + 6;
+    let y = screen.y as isize - GLYPH_HEIGHT as isize / 2;
+    let rectangle = [x, y, x + (text.chars().count() * (GLYPH_WIDTH + 1)) as isize, y + LINE_HEIGHT as isize];
+    let overlaps = |other: &[isize; 4]| rectangle[0] < other[2] && other[0] < rectangle[2] && rectangle[1] < other[3] && other[1] < rectangle[3];
+    if taken.iter().any(overlaps) {
+        return;
+    }
+
+    draw_text(framebuffer, x, y, text, color);
+    taken.push(rectangle);
+}
+
+// Lines of text stacked from the top-left corner of the screen
+pub fn draw_panel(framebuffer: &mut Framebuffer, lines: &[String]) {
+    for (index, line) in lines.iter().enumerate() {
+        let y = PANEL_MARGIN + index * LINE_HEIGHT;
+        draw_text(framebuffer, PANEL_MARGIN as isize, y as isize, line, TEXT_COLOR);
+    }
+}
+
+// Recent events (key toggles, plans, autopilot phases, collisions) stacked above the
+// bottom-left corner, newest at the bottom
+#[derive(Default)]
+pub struct MessageLog {
+    messages: VecDeque<(String, u32)>, // text and the frame it was posted on
+}
+
+impl MessageLog {
+    pub fn post(&mut self, text: impl Into<String>, frame: u32) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((text.into(), frame));
+    }
+
+    pub fn render(&mut self, framebuffer: &mut Framebuffer, frame: u32) {
+        self.messages.retain(|(_, posted)| frame.saturating_sub(*posted) < MESSAGE_FRAMES);
+        let bottom = framebuffer.height.saturating_sub(PANEL_MARGIN + LINE_HEIGHT * self.messages.len());
+        for (index, (text, _)) in self.messages.iter().enumerate() {
+            let y = bottom + index * LINE_HEIGHT;
+            draw_text(framebuffer, PANEL_MARGIN as isize, y as isize, text, MESSAGE_COLOR);
+        }
+    }
+}
+
+// Periapsis and apoapsis labels on the predicted orbit plus the orbit panel, like a
+// map view. Altitudes are measured from the body's surface.
+pub fn render_orbit_hud(framebuffer: &mut Framebuffer, uniforms: &Uniforms, system: &SolarSystem, prediction: &Prediction, jd: f64, scale: &DisplayScale) {
+    let Some(current) = prediction.current() else {
+        return;
+    };
+
+    let mut taken = Vec::new();
+    for (index, segment) in prediction.segments.iter().enumerate() {
+        let conic = &segment.conic;
+        let label = format!("PE {}", format_altitude(system, conic.body, conic.periapsis()));
+        let periapsis = prediction.scene_point(system, scale, index, jd, &conic.periapsis_position());
+        draw_label(framebuffer, uniforms, periapsis, &label, TEXT_COLOR, &mut taken);
+
+        if let (true, Some(apoapsis), Some(position)) = (segment.reaches_apoapsis(system), conic.apoapsis(), conic.apoapsis_position()) {
+            let label = format!("AP {}", format_altitude(system, conic.body, apoapsis));
+            draw_label(framebuffer, uniforms, prediction.scene_point(system, scale, index, jd, &position), &label, TEXT_COLOR, &mut taken);
+        }
+    }
+
+    let conic = &current.conic;
+    let mut lines = vec![format!("ORBITING {}", system.bodies[conic.body].name)];
+    lines.push(format!("PE {}", format_altitude(system, conic.body, conic.periapsis())));
+    if current.reaches_apoapsis(system) {
+        if let (Some(apoapsis), Some(period)) = (conic.apoapsis(), conic.period()) {
+            lines.push(format!("AP {}", format_altitude(system, conic.body, apoapsis)));
+            lines.push(format!("PERIOD {}", format_duration(period)));
+        }
+    }
+    for segment in &prediction.segments {
+        let name = &system.bodies[segment.conic.body].name;
+        let event = match segment.end {
+            SegmentEnd::Horizon => continue,
+            SegmentEnd::Impact => format!("IMPACT {}", name),
+            SegmentEnd::Enter(next) if system.bodies[segment.conic.body].parent == Some(next) => format!("ESCAPE {}", name),
+            SegmentEnd::Enter(next) => format!("ENCOUNTER {}", system.bodies[next].name),
+        };
+        lines.push(format!("{} T+{}", event, format_duration(segment.end_jd - jd)));
+    }
+    draw_panel(framebuffer, &lines);
+}
+
+// Altitude above the surface in km, or in au far from small bodies
+fn format_altitude(system: &SolarSystem, body: usize, radius: f64) -> String {
+    let altitude_km = radius * KM_PER_AU - system.bodies[body].radius_km;
+    if altitude_km.abs() >= 0.05 * KM_PER_AU {
+        format!("{:.3} AU", altitude_km / KM_PER_AU)
+    } else {
+        format!("{:.0} KM", altitude_km)
+    }
+}
+
+// Largest two units of a duration given in days
+fn format_duration(days: f64) -> String {
+    let minutes = (days.max(0.0) * 1440.0).round() as u64;
+    let (years, days, hours, minutes) = (minutes / 525_960, minutes % 525_960 / 1440, minutes % 1440 / 60, minutes % 60);
+    if years > 0 {
+        format!("{}Y {}D", years, days)
+    } else if days > 0 {
+        format!("{}D {}H", days, hours)
+    } else {
+        format!("{}H {}M", hours, minutes)
+    }
+}
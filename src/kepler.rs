@@ -0,0 +1,190 @@
+// kepler.rs
+use std::f64::consts::PI;
+use nalgebra_glm::DVec3;
+
+// Two-body motion in universal variables, valid for elliptic and hyperbolic orbits
+// alike. Positions, velocities and `mu` share units (au, au/day, au^3/day^2 in this crate).
+
+const TOLERANCE: f64 = 1e-10;
+const MAX_ITERATIONS: usize = 200;
+const LAGUERRE_ORDER: f64 = 5.0;
+
+// Stumpff functions C(z) and S(z)
+pub fn stumpff(z: f64) -> (f64, f64) {
+    if z > 1e-6 {
+        let s = z.sqrt();
+        ((1.0 - s.cos()) / z, (s - s.sin()) / (s * s * s))
+    } else if z < -1e-6 {
+        let s = (-z).sqrt();
+        ((s.cosh() - 1.0) / -z, (s.sinh() - s) / (s * s * s))
+    } else {
+        (0.5 - z / 24.0, 1.0 / 6.0 - z / 120.0)
+    }
+}
+
+// State after `dt` from (position, velocity)
+pub fn propagate(position: &DVec3, velocity: &DVec3, dt: f64, mu: f64) -> (DVec3, DVec3) {
+    let r0 = position.magnitude();
+    let sqrt_mu = mu.sqrt();
+    let radial_velocity = position.dot(velocity) / r0;
+    let alpha = 2.0 / r0 - velocity.magnitude_squared() / mu; // 1/a
+
+    // Laguerre-Conway iterations on the universal anomaly. Plain Newton diverges
+    // on near-parabolic orbits for some time steps, this converges for any conic.
+    let sigma = r0 * radial_velocity / sqrt_mu;
+    let mut chi = sqrt_mu * alpha.abs() * dt;
+    for _ in 0..MAX_ITERATIONS {
+        let z = alpha * chi * chi;
+        let (c, s) = stumpff(z);
+        let f = sigma * chi * chi * c + (1.0 - alpha * r0) * chi * chi * chi * s + r0 * chi - sqrt_mu * dt;
+        let df = sigma * chi * (1.0 - z * s) + (1.0 - alpha * r0) * chi * chi * c + r0;
+        let ddf = sigma * (1.0 - z * c) + (1.0 - alpha * r0) * chi * (1.0 - z * s);
+
+        let n = LAGUERRE_ORDER;
+        let root = ((n - 1.0).powi(2) * df * df - n * (n - 1.0) * f * ddf).abs().sqrt();
+        let step = n * f / (df + root.copysign(df));
+        chi -= step;
+        if step.abs() < TOLERANCE {
+            break;
+        }
+    }
+
+    let z = alpha * chi * chi;
+    let (c, s) = stumpff(z);
+    let f = 1.0 - chi * chi / r0 * c;
+    let g = dt - chi * chi * chi / sqrt_mu * s;
+    let new_position = position * f + velocity * g;
+
+    let r = new_position.magnitude();
+    let f_dot = sqrt_mu / (r * r0) * (z * s - 1.0) * chi;
+    let g_dot = 1.0 - chi * chi / r * c;
+    (new_position, position * f_dot + velocity * g_dot)
+}
+
+// Lambert's problem: velocities at both ends of the prograde, single-revolution
+// arc that goes from `start` to `end` in `time_of_flight`. Bisection on the
+// universal variable (Vallado), which is slow but never diverges.
+pub fn lambert(start: &DVec3, end: &DVec3, time_of_flight: f64, mu: f64) -> Option<(DVec3, DVec3)> {
+    let r1 = start.magnitude();
+    let r2 = end.magnitude();
+    if time_of_flight <= 0.0 || r1 <= 0.0 || r2 <= 0.0 {
+        return None;
+    }
+
+    // Prograde means counter-clockwise seen from the ecliptic north pole
+    let cos_angle = (start.dot(end) / (r1 * r2)).clamp(-1.0, 1.0);
+    let mut transfer_angle = cos_angle.acos();
+    if start.cross(end).z < 0.0 {
+        transfer_angle = 2.0 * PI - transfer_angle;
+    }
+
+    let a = transfer_angle.sin() * (r1 * r2 / (1.0 - cos_angle)).sqrt();
+    if a.abs() < 1e-12 || !a.is_finite() {
+        return None;
+    }
+
+    let y_of = |psi: f64| {
+        let (c, s) = stumpff(psi);
+        (r1 + r2 + a * (psi * s - 1.0) / c.sqrt(), c, s)
+    };
+
+    let mut low = -4.0 * PI * PI;
+    let mut high = 4.0 * PI * PI;
+    let mut psi = 0.0;
+    let mut y = 0.0;
+    for _ in 0..MAX_ITERATIONS {
+        let (mut value, mut c, mut s) = y_of(psi);
+
+        // y must stay positive, move the lower bound up until it is
+        while a > 0.0 && value < 0.0 {
+            psi += 0.1;
+            low = psi;
+            (value, c, s) = y_of(psi);
+        }
+        y = value;
+
+        let chi = (y / c).sqrt();
+        let time = (chi * chi * chi * s + a * y.sqrt()) / mu.sqrt();
+        if (time - time_of_flight).abs() < TOLERANCE * time_of_flight {
+            break;
+        }
+        if time <= time_of_flight {
+            low = psi;
+        } else {
+            high = psi;
+        }
+        psi = 0.5 * (low + high);
+    }
+
+    let f = 1.0 - y / r1;
+    let g = a * (y / mu).sqrt();
+    let g_dot = 1.0 - y / r2;
+    if g.abs() < 1e-15 {
+        return None;
+    }
+
+    let start_velocity = (end - start * f) / g;
+    let end_velocity = (end * g_dot - start) / g;
+    (start_velocity.iter().all(|v| v.is_finite())).then_some((start_velocity, end_velocity))
+}
+
+// Angle of a vector in the ecliptic plane, in radians
+pub fn ecliptic_longitude(v: &DVec3) -> f64 {
+    v.y.atan2(v.x)
+}
+
+// Wraps an angle to (-PI, PI]
+pub fn wrap_angle(angle: f64) -> f64 {
+    let wrapped = (angle + PI).rem_euclid(2.0 * PI) - PI;
+    if wrapped <= -PI { wrapped + 2.0 * PI } else { wrapped }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &DVec3, b: &DVec3, tolerance: f64) {
+        assert!((a - b).magnitude() < tolerance, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn circular_orbit_returns_after_one_period() {
+        let mu: f64 = 2.959122e-4;
+        let position = DVec3::new(1.0, 0.0, 0.0);
+        let velocity = DVec3::new(0.0, mu.sqrt(), 0.0);
+        let period = 2.0 * PI / mu.sqrt();
+
+        let (end, end_velocity) = propagate(&position, &velocity, period, mu);
+        assert_close(&end, &position, 1e-9);
+        assert_close(&end_velocity, &velocity, 1e-9);
+
+        let (half, _) = propagate(&position, &velocity, period / 2.0, mu);
+        assert_close(&half, &-position, 1e-9);
+    }
+
+    #[test]
+    fn lambert_matches_hohmann_arc() {
+        let mu: f64 = 1.0;
+        let (r1, r2) = (1.0, 1.5);
+        let start = DVec3::new(r1, 0.0, 0.0);
+        let departure_velocity = DVec3::new(0.0, (mu * (2.0 / r1 - 2.0 / (r1 + r2))).sqrt(), 0.0);
+
+        // Stop short of the apsis, where the 180 degree transfer plane is undefined
+        let half_period = PI * ((r1 + r2).powi(3) / (8.0 * mu)).sqrt();
+        let flight_time = 0.8 * half_period;
+        let (end, arrival_velocity) = propagate(&start, &departure_velocity, flight_time, mu);
+
+        let (v1, v2) = lambert(&start, &end, flight_time, mu).unwrap();
+        assert_close(&v1, &departure_velocity, 1e-6);
+        assert_close(&v2, &arrival_velocity, 1e-6);
+    }
+
+    #[test]
+    fn wrap_angle_keeps_pi_and_maps_minus_pi() {
+        assert_eq!(wrap_angle(PI), PI);
+        assert_eq!(wrap_angle(-PI), PI);
+        assert!((wrap_angle(3.0 * PI) - PI).abs() < 1e-12);
+        assert!((wrap_angle(-0.5 * PI) + 0.5 * PI).abs() < 1e-12);
+        assert!((wrap_angle(2.5 * PI) - 0.5 * PI).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,221 @@
+// line.rs
+use nalgebra_glm::{Vec3, Vec4};
+use crate::color::{Color, LinearColor};
+use crate::framebuffer::Framebuffer;
+use crate::Uniforms;
+
+// Appearance of line and point primitives. `width` is in pixels.
+#[derive(Clone, Copy, Debug)]
+pub struct LineStyle {
+    pub color: LinearColor,
+    pub width: f32,
+    pub alpha: f32,
+    pub depth_test: bool,
+}
+
+impl LineStyle {
+    pub fn new(color: LinearColor) -> Self {
+        LineStyle {
+            color,
+            width: 1.0,
+            alpha: 1.0,
+            depth_test: true,
+        }
+    }
+
+    pub fn with_width(mut self, width: f32) -> Self {
+        self.width = width;
+        self
+    }
+
+    pub fn with_alpha(mut self, alpha: f32) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    // Overlays such as gizmos are drawn on top of everything
+    pub fn without_depth_test(mut self) -> Self {
+        self.depth_test = false;
+        self
+    }
+}
+
+// Draws a world-space segment, clipped to the view frustum. Lines are blended
+// over the frame and never write depth, so draw them after opaque geometry.
+pub fn draw_line_3d(framebuffer: &mut Framebuffer, uniforms: &Uniforms, start: Vec3, end: Vec3, style: &LineStyle) {
+    let view_projection = uniforms.projection_matrix * uniforms.view_matrix;
+    let clip_start = view_projection * Vec4::new(start.x, start.y, start.z, 1.0);
+    let clip_end = view_projection * Vec4::new(end.x, end.y, end.z, 1.0);
+
+    let Some((clip_start, clip_end)) = clip_segment(clip_start, clip_end) else {
+        return;
+    };
+
+    let screen_start = to_screen(uniforms, &clip_start);
+    let screen_end = to_screen(uniforms, &clip_end);
+    rasterize_segment(framebuffer, screen_start, screen_end, style);
+}
+
+pub fn draw_polyline_3d(framebuffer: &mut Framebuffer, uniforms: &Uniforms, points: &[Vec3], style: &LineStyle) {
+    for segment in points.windows(2) {
+        draw_line_3d(framebuffer, uniforms, segment[0], segment[1], style);
+    }
+}
+
+// Anti-aliased round point of `style.width` pixels in diameter
+pub fn draw_point_3d(framebuffer: &mut Framebuffer, uniforms: &Uniforms, point: Vec3, style: &LineStyle) {
+    let clip = uniforms.projection_matrix * uniforms.view_matrix * Vec4::new(point.x, point.y, point.z, 1.0);
+    if !inside_frustum(&clip) {
+        return;
+    }
+
+    let screen = to_screen(uniforms, &clip);
+    let radius = (style.width * 0.5).max(0.5);
+    let min_x = (screen.x - radius).floor() as isize;
+    let max_x = (screen.x + radius).ceil() as isize;
+    let min_y = (screen.y - radius).floor() as isize;
+    let max_y = (screen.y + radius).ceil() as isize;
+
+    for y in min_y..=max_y {
+        for x in min_x..=max_x {
+            let dx = x as f32 + 0.5 - screen.x;
+            let dy = y as f32 + 0.5 - screen.y;
+            let coverage = (radius + 0.5 - (dx * dx + dy * dy).sqrt()).clamp(0.0, 1.0);
+            plot(framebuffer, x, y, screen.z, coverage, style);
+        }
+    }
+}
+
+// Red, green and blue segments along the world X, Y and Z axes
+pub fn draw_axes(framebuffer: &mut Framebuffer, uniforms: &Uniforms, origin: Vec3, length: f32) {
+    let axes = [
+        (Vec3::new(1.0, 0.0, 0.0), Color::new(230, 60, 60)),
+        (Vec3::new(0.0, 1.0, 0.0), Color::new(60, 230, 60)),
+        (Vec3::new(0.0, 0.0, 1.0), Color::new(60, 120, 255)),
+    ];
+
+    for (axis, color) in axes {
+        let style = LineStyle::new(color.into()).with_width(2.0).without_depth_test();
+        draw_line_3d(framebuffer, uniforms, origin, origin + axis * length, &style);
+    }
+}
+
+// Screen position in pixels of a world-space point, if it is in view
+pub fn project_point(uniforms: &Uniforms, point: Vec3) -> Option<Vec3> {
+    let clip = uniforms.projection_matrix * uniforms.view_matrix * Vec4::new(point.x, point.y, point.z, 1.0);
+    inside_frustum(&clip).then(|| to_screen(uniforms, &clip))
+}
+
+// Screen position of any point in front of the camera, even off screen, for shapes
+// that are clipped pixel by pixel
+pub fn project_to_screen(uniforms: &Uniforms, point: Vec3) -> Option<Vec3> {
+    let clip = uniforms.projection_matrix * uniforms.view_matrix * Vec4::new(point.x, point.y, point.z, 1.0);
+    (clip.w > 0.0).then(|| to_screen(uniforms, &clip))
+}
+
+fn inside_frustum(clip: &Vec4) -> bool {
+    clip.w > 0.0
+        && clip.x.abs() <= clip.w
+        && clip.y.abs() <= clip.w
+        && clip.z.abs() <= clip.w
+}
+
+// Liang-Barsky clipping against the six planes of the clip-space volume -w <= x, y, z <= w
+fn clip_segment(start: Vec4, end: Vec4) -> Option<(Vec4, Vec4)> {
+    let plane_distances = |p: &Vec4| [p.w + p.x, p.w - p.x, p.w + p.y, p.w - p.y, p.w + p.z, p.w - p.z];
+    let start_distances = plane_distances(&start);
+    let end_distances = plane_distances(&end);
+
+    let mut t_enter: f32 = 0.0;
+    let mut t_exit: f32 = 1.0;
+
+    for (d0, d1) in start_distances.iter().zip(end_distances.iter()) {
+        if *d0 < 0.0 && *d1 < 0.0 {
+            return None;
+        }
+        if *d0 < 0.0 {
+            t_enter = t_enter.max(d0 / (d0 - d1));
+        } else if *d1 < 0.0 {
+            t_exit = t_exit.min(d0 / (d0 - d1));
+        }
+    }
+
+    if t_enter > t_exit {
+        return None;
+    }
+
+    Some((start.lerp(&end, t_enter), start.lerp(&end, t_exit)))
+}
+
+// Screen position in pixels, z keeps the NDC depth used by the z-buffer
+pub fn to_screen(uniforms: &Uniforms, clip: &Vec4) -> Vec3 {
+    let ndc = clip / clip.w;
+    let screen = uniforms.viewport_matrix * Vec4::new(ndc.x, ndc.y, ndc.z, 1.0);
+    Vec3::new(screen.x, screen.y, screen.z)
+}
+
+// Xiaolin Wu's algorithm generalised to wide lines: along the major axis every
+// column gets the coverage of the [center - width/2, center + width/2] span.
+fn rasterize_segment(framebuffer: &mut Framebuffer, start: Vec3, end: Vec3, style: &LineStyle) {
+    let steep = (end.y - start.y).abs() > (end.x - start.x).abs();
+
+    // Work in a frame where the line is x-major and goes left to right
+    let swizzle = |p: Vec3| if steep { Vec3::new(p.y, p.x, p.z) } else { p };
+    let (mut a, mut b) = (swizzle(start), swizzle(end));
+    if a.x > b.x {
+        std::mem::swap(&mut a, &mut b);
+    }
+
+    let dx = b.x - a.x;
+    if dx < 1e-4 {
+        let point = swizzle(a);
+        plot(framebuffer, point.x.floor() as isize, point.y.floor() as isize, point.z, 1.0, style);
+        return;
+    }
+
+    let gradient = (b.y - a.y) / dx;
+    let depth_gradient = (b.z - a.z) / dx;
+
+    // Half-width measured along the minor axis
+    let half_span = style.width.max(1.0) * 0.5 * (1.0 + gradient * gradient).sqrt();
+
+    let first_column = (a.x - 0.5).round() as isize;
+    let last_column = (b.x - 0.5).round() as isize;
+
+    for column in first_column..=last_column {
+        let column_center = column as f32 + 0.5;
+
+        // Partial coverage at the end points
+        let column_coverage = ((column_center + 0.5).min(b.x) - (column_center - 0.5).max(a.x)).clamp(0.0, 1.0);
+        if column_coverage <= 0.0 {
+            continue;
+        }
+
+        let x = column_center.clamp(a.x, b.x);
+        let center = a.y + gradient * (x - a.x);
+        let depth = a.z + depth_gradient * (x - a.x);
+
+        let low = center - half_span;
+        let high = center + half_span;
+        for row in low.floor() as isize..=high.floor() as isize {
+            let row_coverage = ((row as f32 + 1.0).min(high) - (row as f32).max(low)).clamp(0.0, 1.0);
+            let coverage = row_coverage * column_coverage;
+
+            if steep {
+                plot(framebuffer, row, column, depth, coverage, style);
+            } else {
+                plot(framebuffer, column, row, depth, coverage, style);
+            }
+        }
+    }
+}
+
+fn plot(framebuffer: &mut Framebuffer, x: isize, y: isize, depth: f32, coverage: f32, style: &LineStyle) {
+    let alpha = coverage * style.alpha;
+    if alpha <= 0.0 {
+        return;
+    }
+
+    let depth = if style.depth_test { depth } else { f32::NEG_INFINITY };
+    framebuffer.blend_point(x, y, depth, style.color, alpha);
+}
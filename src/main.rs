@@ -1,5 +1,5 @@
-use nalgebra_glm::{Vec3, Mat4, look_at, perspective};
-use minifb::{Key, Window, WindowOptions};
+use nalgebra_glm::{Vec3, Vec4, DVec3, Mat4, look_at, perspective};
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
 use std::f32::consts::PI;
 
 mod framebuffer;
@@ -14,21 +14,57 @@ mod renderer;
 mod texture;
 mod normal_map;
 mod skybox;
+mod ephemeris;
+mod orbit;
+mod line;
+mod trail;
+mod ship;
+mod kepler;
+mod transfer;
+mod autopilot;
+mod conic;
+mod hud;
+mod collision;
+mod asteroid;
+mod comet;
+mod particle;
+mod star;
+mod planet;
+mod noise;
+mod ramp;
+mod material;
+mod nebula;
 
 use framebuffer::Framebuffer;
 use vertex::Vertex;
-use obj::Obj;
+use obj::{Obj, MAX_SUBDIVISIONS};
 use camera::Camera;
 use triangle::triangle;
 use crate::fragment::Fragment;
-use crate::color::Color;
+use crate::color::{Color, LinearColor};
 use shaders::{vertex_shader, moon_shader, ring_shader, gaseous_giant_shader, black_and_white,
-    lava_shader, cloud_shader, solar_shader, blue_green_shader, fragment_shader};
-use fastnoise_lite::{FastNoiseLite, NoiseType};
+    lava_shader, cloud_shader, cellular_shader, solar_shader, blue_green_shader, ship_shader, rock_shader};
 use crate::renderer::{ShaderType, Object, Transform};
-use texture::init_texture;
-use normal_map::init_normal_map;
-use skybox::Skybox;
+use texture::TextureRegistry;
+use material::Material;
+use skybox::{Skybox, SkyImage, MAGNITUDE_LIMIT};
+use nebula::{Nebula, NebulaSpec};
+use ramp::ColorRamp;
+use ephemeris::{SolarSystem, DisplayScale, KM_PER_AU, SECONDS_PER_DAY, parse_date, julian_day_now};
+use orbit::{OrbitPath, OrbitFade, render_orbit};
+use line::{LineStyle, draw_axes, draw_point_3d, to_screen};
+use trail::{Trail, render_trail};
+use ship::{Ship, ShipSpec, ShipCamera, ShipStatus, FlightModel};
+use transfer::{TransferPlan, TransferKind, plan_transfer, render_transfer};
+use autopilot::Autopilot;
+use conic::{Segment, Prediction, render_prediction};
+use hud::{MessageLog, render_orbit_hud};
+use collision::{SurfaceMesh, CollisionResponse, BodyMerge, collide_ship, collide_bodies, scene_spheres, keep_outside};
+use asteroid::{AsteroidBelt, BeltSpec};
+use comet::CometTail;
+use particle::{Emitter, ParticleSystem, body_attractors, engine_exhaust, explosion};
+use star::{StarRenderer, StarSpec};
+use planet::{Planet, random_system};
 
 pub struct Uniforms {
     model_matrix: Mat4,
@@ -36,17 +72,15 @@ pub struct Uniforms {
     projection_matrix: Mat4,
     viewport_matrix: Mat4,
     time: u32,
-    noise: FastNoiseLite
 }
 
 struct Scene {
     objects: Vec<Object>, // Lista de objetos en la escena
-}
-
-fn create_cloud_noise() -> FastNoiseLite {
-    let mut noise = FastNoiseLite::with_seed(1337);
-    noise.set_noise_type(Some(NoiseType::OpenSimplex2));
-    noise
+    stars: Vec<StarRenderer>, // Estrellas, con superficie, corona y protuberancias propias
+    comets: Vec<CometTail>, // Colas de los cometas, dibujadas sobre los objetos
+    particles: ParticleSystem, // Efectos sueltos, como explosiones
+    textures: TextureRegistry, // Imágenes cargadas, compartidas por los materiales
+    skybox: Skybox, // Cielo de fondo, en el infinito
 }
 
 fn create_model_matrix(translation: Vec3, scale: f32, rotation: Vec3) -> Mat4 {
@@ -110,34 +144,8 @@ fn create_viewport_matrix(width: f32, height: f32) -> Mat4 {
     )
 }
 
-// Función para dibujar una órbita
-fn draw_orbit(framebuffer: &mut Framebuffer, radius: f32, aspect_ratio: f32) {
-    let num_points = (radius).max(100.0) as usize; // Ajustar según el radio
-
-    for i in 0..num_points {
-        // Calcular ángulos
-        let angle1 = 2.0 * std::f32::consts::PI * (i as f32) / (num_points as f32);
-        let angle2 = 2.0 * std::f32::consts::PI * ((i + 1) as f32) / (num_points as f32);
-
-        // Puntos de la órbita escalados y ajustados
-        let x1 = radius * angle1.cos() * aspect_ratio;
-        let z1 = radius * angle1.sin() / aspect_ratio;
-        let x2 = radius * angle2.cos() * aspect_ratio;
-        let z2 = radius * angle2.sin() / aspect_ratio;
-
-        // Transformar a coordenadas de pantalla
-        let screen_x1 = (x1 + framebuffer.width as f32 / 2.0) as usize;
-        let screen_z1 = (z1 + framebuffer.height as f32 / 2.0) as usize;
-        let screen_x2 = (x2 + framebuffer.width as f32 / 2.0) as usize;
-        let screen_z2 = (z2 + framebuffer.height as f32 / 2.0) as usize;
-
-        // Dibujar línea entre los puntos
-        framebuffer.draw_line(screen_x1, screen_z1, screen_x2, screen_z2, 0x888888); // Color de la órbita
-    }
-}
-
 fn render(framebuffer: &mut Framebuffer, uniforms: &Uniforms, vertex_array: &[Vertex], 
-    fragment_shader: fn(&Fragment, &Uniforms) -> Color) {
+    fragment_shader: impl Fn(&Fragment, &Uniforms) -> LinearColor) {
     // Vertex Shader Stage
     let mut transformed_vertices = Vec::with_capacity(vertex_array.len());
     for vertex in vertex_array {
@@ -149,18 +157,22 @@ fn render(framebuffer: &mut Framebuffer, uniforms: &Uniforms, vertex_array: &[Ve
     let mut triangles = Vec::new();
     for i in (0..transformed_vertices.len()).step_by(3) {
         if i + 2 < transformed_vertices.len() {
-            triangles.push([
-                transformed_vertices[i].clone(),
-                transformed_vertices[i + 1].clone(),
-                transformed_vertices[i + 2].clone(),
-            ]);
+            // Los triángulos que cruzan el plano cercano se recortan en lugar de descartarse,
+            // así la geometría pegada a la cámara (un planeta al aterrizar) no desaparece
+            let depth_range = -1.0..=1.0;
+            let vertices = &transformed_vertices[i..i + 3];
+            if vertices.iter().all(|v| depth_range.contains(&v.transformed_position.z)) {
+                triangles.push([vertices[0].clone(), vertices[1].clone(), vertices[2].clone()]);
+            } else {
+                triangles.extend(clip_near(vertices, uniforms));
+            }
         }
     }
 
     // Rasterization Stage
     let mut fragments = Vec::new();
     for tri in &triangles {
-        fragments.extend(triangle(&tri[0], &tri[1], &tri[2]));
+        fragments.extend(triangle(&tri[0], &tri[1], &tri[2], framebuffer.width, framebuffer.height));
     }
 
     // Fragment Processing Stage
@@ -168,13 +180,47 @@ fn render(framebuffer: &mut Framebuffer, uniforms: &Uniforms, vertex_array: &[Ve
         let x = fragment.position.x as usize;
         let y = fragment.position.y as usize;
         if x < framebuffer.width && y < framebuffer.height {
-            // Apply fragment shader
-            let shaded_color = fragment_shader(&fragment, &uniforms);
-            let color = shaded_color.to_hex();
-            framebuffer.set_current_color(color);
-            framebuffer.point(x, y, fragment.depth);
+            // Apply fragment shader; the result is in linear light and gets encoded to
+            // sRGB here. Translucent fragments blend over what is already drawn.
+            let shaded_color = fragment_shader(&fragment, uniforms);
+            if shaded_color.a >= 1.0 {
+                framebuffer.set_current_color(shaded_color.to_hex());
+                framebuffer.point(x, y, fragment.depth);
+            } else {
+                framebuffer.blend_point(x as isize, y as isize, fragment.depth, shaded_color, shaded_color.a);
+            }
+        }
+    }
+}
+
+// Recorta un triángulo contra el plano cercano (z = -w en espacio de recorte) y lo
+// devuelve como cero, uno o dos triángulos con sus atributos interpolados. Se descarta
+// entero si queda más allá del plano lejano.
+fn clip_near(vertices: &[Vertex], uniforms: &Uniforms) -> Vec<[Vertex; 3]> {
+    let view_projection = uniforms.projection_matrix * uniforms.view_matrix;
+    let clip = |vertex: &Vertex| view_projection * vertex.world_position.push(1.0);
+    let clipped: Vec<Vec4> = vertices.iter().map(clip).collect();
+    if clipped.iter().all(|c| c.z > c.w) {
+        return Vec::new();
+    }
+
+    let mut polygon: Vec<Vertex> = Vec::with_capacity(4);
+    for i in 0..3 {
+        let next = (i + 1) % 3;
+        let (d0, d1) = (clipped[i].z + clipped[i].w, clipped[next].z + clipped[next].w);
+        if d0 >= 0.0 {
+            polygon.push(vertices[i].clone());
+        }
+        if (d0 >= 0.0) != (d1 >= 0.0) {
+            let mut vertex = vertices[i].lerp(&vertices[next], d0 / (d0 - d1));
+            vertex.transformed_position = to_screen(uniforms, &clip(&vertex));
+            polygon.push(vertex);
         }
     }
+
+    (1..polygon.len().saturating_sub(1))
+        .map(|i| [polygon[0].clone(), polygon[i].clone(), polygon[i + 1].clone()])
+        .collect()
 }
 
 fn render_scene(
@@ -197,9 +243,9 @@ fn render_scene(
             ShaderType::BlackAndWhite => black_and_white,
             ShaderType::Dalmata => lava_shader,
             ShaderType::Cloud => cloud_shader,
-            ShaderType::Cellular => blue_green_shader,
+            ShaderType::Cellular => cellular_shader,
             ShaderType::Lava => solar_shader,
-            ShaderType::BlueGreen => fragment_shader,
+            ShaderType::BlueGreen => blue_green_shader,
         };
 
         // actualizar la matriz modelo
@@ -208,8 +254,51 @@ fn render_scene(
         // Obtener los vértices del objeto
         let vertex_array = object.model.get_vertex_array();
 
-        // Renderizar el objeto
-        render(framebuffer, &uniforms, &vertex_array, shader_function);
+        // Renderizar el objeto con sus texturas o su aspecto procedural, si los tiene
+        if object.material.is_textured() {
+            render(framebuffer, uniforms, &vertex_array, |fragment, uniforms| object.material.shade(fragment, uniforms, &scene.textures));
+        } else if let Some(planet) = &object.planet {
+            render(framebuffer, uniforms, &vertex_array, |fragment, uniforms| planet.shade(fragment, uniforms));
+        } else {
+            render(framebuffer, uniforms, &vertex_array, shader_function);
+        }
+    }
+
+    // El cielo ocupa solo los píxeles donde no se dibujó nada; la nave y las mallas de
+    // las rocas ya están dibujadas cuando se llama a esta función
+    scene.skybox.render(framebuffer, uniforms);
+
+    // Las estrellas, las partículas y las coronas se suman al color y no escriben
+    // profundidad, así que van después de la geometría opaca
+    for star in &scene.stars {
+        star.render(framebuffer, uniforms);
+    }
+    for comet in &scene.comets {
+        comet.render(framebuffer, uniforms);
+    }
+    scene.particles.render(framebuffer, uniforms);
+}
+
+fn render_ship(framebuffer: &mut Framebuffer, ship: &Ship, position: Vec3, uniforms: &mut Uniforms) {
+    uniforms.model_matrix = create_model_matrix(position, ship.scale, ship.euler_rotation());
+    let vertex_array = ship.model.get_vertex_array();
+    render(framebuffer, uniforms, &vertex_array, ship_shader);
+}
+
+// Rocas cercanas como mallas compartidas por todas las instancias
+fn render_asteroids(framebuffer: &mut Framebuffer, belt: &AsteroidBelt, uniforms: &mut Uniforms) {
+    for rock in &belt.meshes {
+        uniforms.model_matrix = create_model_matrix(rock.position, rock.scale, rock.rotation);
+        render(framebuffer, uniforms, &belt.shapes[rock.shape], rock_shader);
+    }
+}
+
+// Rocas lejanas como puntos; se mezclan sin escribir profundidad, así que deben ir
+// después del cielo, que si no las taparía al rellenar los píxeles sin profundidad
+fn render_asteroid_points(framebuffer: &mut Framebuffer, belt: &AsteroidBelt, uniforms: &Uniforms) {
+    for &(position, brightness) in &belt.points {
+        let color = LinearColor::from(Color::new(150, 140, 130)) * brightness;
+        draw_point_3d(framebuffer, uniforms, position, &LineStyle::new(color).with_width(1.5));
     }
 }
 
@@ -233,10 +322,48 @@ fn main() {
 
     framebuffer.set_background_color(0x333355);
 
-    // Variables para la luna
-    let orbit_radius = 0.75; // Distancia de la luna al planeta
-    let moon_speed = 0.05;      // Velocidad de órbita
-    let mut time = 0;         // Tiempo actual (simulado para cálculo dinámico)
+    let args: Vec<String> = std::env::args().collect();
+
+    // Semilla de la generación procedural: la misma semilla produce siempre el mismo resultado
+    let seed: u64 = arg_value(&args, "--seed")
+        .map(|seed| seed.parse().expect("Invalid --seed"))
+        .unwrap_or(1);
+
+    // Sistema solar real a partir de los elementos orbitales, o uno inventado a partir
+    // de la semilla (--random-system)
+    let mut system = if args.iter().any(|arg| arg == "--random-system") {
+        random_system(seed)
+    } else {
+        let ephemeris_path = arg_value(&args, "--ephemeris")
+            .unwrap_or_else(|| "assets/ephemeris/solar_system.csv".to_string());
+        SolarSystem::load(&ephemeris_path).expect("Failed to load ephemeris")
+    };
+
+    // Época de la simulación (fecha de calendario) y días simulados por cuadro
+    let epoch = arg_value(&args, "--epoch")
+        .map(|date| parse_date(&date).expect("Invalid --epoch, expected YYYY-MM-DD[THH:MM]"))
+        .unwrap_or_else(julian_day_now);
+    let mut days_per_frame: f64 = arg_value(&args, "--days-per-frame")
+        .map(|days| days.parse().expect("Invalid --days-per-frame"))
+        .unwrap_or(0.2);
+    let display_scale = if args.iter().any(|arg| arg == "--true-scale") {
+        DisplayScale::true_scale(10.0)
+    } else {
+        DisplayScale::compressed()
+    };
+
+    // Planetas procedurales con relieve real: sus vértices se desplazan sobre una esfera
+    // subdividida N veces (--displace N, como mucho MAX_SUBDIVISIONS) en lugar de usar sphere.obj
+    let displacement: Option<u32> = arg_value(&args, "--displace")
+        .map(|subdivisions| subdivisions.parse().expect("Invalid --displace"))
+        .map(|subdivisions: u32| {
+            if subdivisions > MAX_SUBDIVISIONS {
+                eprintln!("Warning: --displace {} is too fine, using {}", subdivisions, MAX_SUBDIVISIONS);
+            }
+            subdivisions.min(MAX_SUBDIVISIONS)
+        });
+
+    let mut time = 0;         // Tiempo actual (cuadros desde el inicio)
 
     // model position
     let translation = Vec3::new(0.0, 0.0, 0.0);
@@ -245,103 +372,161 @@ fn main() {
 
     // camera parameters
     let mut camera = Camera::new(
-        Vec3::new(-2.0, 10.0, 10.0),
-        Vec3::new(-6.0, 0.0, 0.0),
+        Vec3::new(-2.0, 20.0, 30.0),
+        Vec3::new(0.0, 0.0, 0.0),
         Vec3::new(0.0, 1.0, 0.0)
     );
 
-    // Posiciones de los planetas en el eje X
-    let positions = [
-        -4.0, -3.0, -2.0, -1.0, 0.0, 2.0,
-    ];
-
-    // Escalas de los planetas
-    let scales = [
-        0.3, 0.9, 0.6, 1.0, 0.4, 0.5
-    ];
-
-    // Shaders para cada planeta
-    let shaders = [
-        ShaderType::BlackAndWhite,
-        ShaderType::Dalmata,
-        ShaderType::Cloud,
-        ShaderType::Cellular,
-        ShaderType::RandomColor,
-        ShaderType::BlueGreen,
-    ];
-
-
-    // Velocidades de órbita (en radianes por unidad de tiempo)
-    let orbit_speeds = [0.01, 0.015, 0.004, 0.025, 0.003, 0.035, 0.02, 0.06];
-
-    // Distancias al planeta central
-    let orbit_radii = [2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 10.0];
-    let orbit_draw = [66.0, 99.0, 132.0, 165.0, 223.0, 260.0];
-
-    // Planeta central
-    let mut objects: Vec<Object> = vec![Object {
-        model: Obj::load("assets/models/sphere.obj").expect("Failed to load obj"),
-        transform: Transform {
-            position: Vec3::new(-6.0, 0.0, 0.0), // Centro de la órbita
-            scale: 2.0,                  // Tamaño del planeta central
-            rotation: Vec3::new(0.0, 0.0, 0.0),
-        },
-        shader: ShaderType::Lava,
-    }];
-
-    //Añadir los planetas orbitantes
-    positions
-        .iter()
-        .zip(shaders.iter())
-        .zip(scales.iter())
-        .zip(orbit_speeds.iter())
-        .zip(orbit_radii.iter())
-        .enumerate()
-        .for_each(|(index, ((((&x, &shader), &scale), &speed), &radius))| {
+    // Órbitas visibles (tecla O) y su desvanecimiento con la distancia
+    let mut show_orbits = true;
+    let orbit_fade = OrbitFade::default();
+
+    // Estelas de los cuerpos (tecla T)
+    let mut show_trails = true;
+
+    // Ejes de referencia en el centro de la cámara (tecla G)
+    let mut show_axes = false;
+
+    // Un objeto por cuerpo, con su tamaño relativo real (comprimido si aplica)
+    let positions = system.scene_positions(epoch, &display_scale);
+    let mut objects: Vec<Object> = Vec::new();
+    let mut textures = TextureRegistry::new();
+    for (index, body) in system.bodies.iter().enumerate() {
+        // Las estrellas no usan malla: las dibuja StarRenderer
+        if body.temperature_k.is_some() {
+            continue;
+        }
+
+        // sphere.obj tiene radio 0.5
+        let scale = 2.0 * system.display_radius(index, &display_scale);
+
+        let planet = body.archetype.map(|archetype| Planet::generate(archetype, seed ^ index as u64));
+        let model = match (&planet, displacement) {
+            (Some(planet), Some(subdivisions)) if planet.has_relief() => planet.mesh(subdivisions),
+            _ => Obj::load("assets/models/sphere.obj").expect("Failed to load obj"),
+        };
+
+        objects.push(Object {
+            model,
+            transform: Transform {
+                position: positions[index],
+                scale,
+                rotation: Vec3::new(0.0, 0.0, 0.0),
+            },
+            shader: body.shader,
+            body: Some(index),
+            trail: (body.trail_length > 0).then(|| Trail::new(body.trail_length)),
+            planet,
+            material: Material::load(&body.maps, &mut textures),
+        });
+
+        // Añadir anillos a los planetas que los tienen
+        if body.rings {
             objects.push(Object {
-                model: Obj::load("assets/models/sphere.obj").expect("Failed to load obj"),
+                model: Obj::load("assets/models/rings.obj").expect("Failed to load rings obj"),
                 transform: Transform {
-                    position: Vec3::new(x, 0.0, 0.0), // Se ajustará dinámicamente
-                    scale,
+                    position: positions[index], // Centrado en el planeta
+                    scale: 0.3 * scale,         // Escala proporcional
                     rotation: Vec3::new(0.0, 0.0, 0.0),
                 },
-                shader,
+                shader: ShaderType::RingShader, // Shader para los anillos
+                body: Some(index),
+                trail: None,
+                planet: None,
+                material: Material::default(),
             });
+        }
+    }
 
-            // Añadir luna al cuarto planeta
-            if index == 3 {
-                objects.push(Object {
-                    model: Obj::load("assets/models/sphere.obj").expect("Failed to load moon obj"),
-                    transform: Transform {
-                        position: Vec3::new(x + 1.5 * scale, 0.5, 0.0), // Posición relativa al planeta
-                        scale: 0.3 * scale,                            // Escala proporcional
-                        rotation: Vec3::new(0.0, 0.0, 0.0),
-                    },
-                    shader: ShaderType::MoonShader, // Shader para la luna
-                });
-            }
+    // Nave del jugador en órbita baja alrededor de la Tierra (o del primer cuerpo con órbita)
+    let home = system.find("Earth")
+        .or_else(|| system.bodies.iter().position(|body| body.orbit.is_some()))
+        .unwrap_or(0);
+    let mut ship = Ship::in_circular_orbit(
+        Obj::load("assets/models/ship.obj").expect("Failed to load ship obj"),
+        ShipSpec::default(),
+        &system,
+        home,
+        400.0,
+        epoch,
+    );
 
-            // Añadir anillos al último planeta
-            if index == positions.len() - 1 {
-                objects.push(Object {
-                    model: Obj::load("assets/models/rings.obj").expect("Failed to load rings obj"),
-                    transform: Transform {
-                        position: Vec3::new(x, 0.0, 0.0), // Centrado en el planeta
-                        scale: 0.3 * scale,               // Escala proporcional
-                        rotation: Vec3::new(0.0, 0.0, 0.0),
-                    },
-                    shader: ShaderType::RingShader, // Shader para los anillos
-                });
-            }
-        });
+    // Modo de vuelo (tecla F): la cámara sigue a la nave y el tiempo corre a 1 s por cuadro
+    let mut flying = false;
+    let mut chase_distance = 0.25;
+    let mut saved_days_per_frame = days_per_frame;
+
+    // Vista de mapa (B en modo de vuelo): cámara cenital sobre el cuerpo en cuya esfera de influencia está la nave
+    let mut map_view = false;
+    let mut map_zoom = 1.0;
+
+    // Órbita prevista de la nave con cónicas enlazadas, recalculada al acelerar o cada medio segundo
+    let mut prediction = Prediction::default();
+
+    // Colisiones de la nave contra la malla con la que se dibuja cada cuerpo (con su
+    // relieve si se usa --displace); las estrellas, que no tienen malla, usan la esfera
+    let sphere = Obj::load("assets/models/sphere.obj").expect("Failed to load obj");
+    let surfaces: Vec<SurfaceMesh> = (0..system.bodies.len())
+        .map(|index| {
+            let model = objects.iter().find(|object| object.body == Some(index)).map_or(&sphere, |object| &object.model);
+            SurfaceMesh::new(model)
+        })
+        .collect();
+
+    // Planificador de transferencias: destino ([ y ]), plan (H o L) y piloto automático (P)
+    let mut target = system.find("Mars")
+        .filter(|&index| index != home)
+        .unwrap_or_else(|| next_target(&system, home, 1));
+    let mut plan: Option<TransferPlan> = None;
+    let mut autopilot: Option<Autopilot> = None;
+
+    // Estrellas reales de un catálogo en el formato de HYG (--star-catalog), hasta una
+    // magnitud límite (--magnitude-limit); si no se puede leer, estrellas al azar a
+    // partir de la semilla
+    let catalog_path = arg_value(&args, "--star-catalog")
+        .unwrap_or_else(|| "assets/stars/hyg_bright.csv".to_string());
+    let magnitude_limit = arg_value(&args, "--magnitude-limit")
+        .map(|limit| limit.parse().expect("Invalid --magnitude-limit"))
+        .unwrap_or(MAGNITUDE_LIMIT);
+    let stars = Skybox::from_catalog(&catalog_path, magnitude_limit).unwrap_or_else(|err| {
+        eprintln!("Warning: could not load star catalog {}: {}", catalog_path, err);
+        Skybox::new(5000, seed)
+    });
+
+    // Imagen del cielo detrás de las estrellas (--sky): un panorama equirectangular o una
+    // carpeta con las seis caras de un cubemap, con su brillo (--sky-brightness). Si no
+    // se puede leer quedan solo las estrellas.
+    let mut skybox = stars.with_brightness(
+        arg_value(&args, "--sky-brightness")
+            .map(|brightness| brightness.parse().expect("Invalid --sky-brightness"))
+            .unwrap_or(1.0),
+    );
+    if let Some(path) = arg_value(&args, "--sky") {
+        match SkyImage::load(&path) {
+            Ok(image) => skybox = skybox.with_background(image),
+            Err(err) => eprintln!("Warning: could not load sky image {}: {}", path, err),
+        }
+    }
 
+    // Nebulosas y banda galáctica procedurales bajo las estrellas, con la semilla, su
+    // densidad (--nebula-density, 0 deja solo la banda) y su paleta (--nebula-palette)
+    let mut nebula = NebulaSpec { seed, ..NebulaSpec::default() };
+    if let Some(density) = arg_value(&args, "--nebula-density") {
+        nebula.density = density.parse().expect("Invalid --nebula-density");
+    }
+    if let Some(path) = arg_value(&args, "--nebula-palette") {
+        match ColorRamp::load(&path) {
+            Ok(palette) => nebula.palette = palette,
+            Err(err) => eprintln!("Warning: could not load nebula palette {}: {}", path, err),
+        }
+    }
+    let skybox = skybox.with_nebula(Nebula::new(nebula));
 
-    let skybox = Skybox::new(5000);
+    // Cinturón de asteroides entre Marte y Júpiter, igual para la misma semilla (--seed)
+    let sun = system.bodies.iter().position(|body| body.parent.is_none()).unwrap_or(0);
+    let mut belt = AsteroidBelt::new(&system, sun, &BeltSpec { seed, ..BeltSpec::default() });
 
-    init_texture("assets/texture/planet.png").expect("Failed To load texture");
-    init_normal_map("assets/texture/planet_nm.png").expect("Failed To load normal map");
 
-    let noise = create_cloud_noise();
     let projection_matrix = create_perspective_matrix(window_width as f32, window_height as f32);
     let viewport_matrix = create_viewport_matrix(framebuffer_width as f32, framebuffer_height as f32);
     let mut uniforms = Uniforms { 
@@ -349,13 +534,38 @@ fn main() {
         view_matrix: Mat4::identity(), 
         projection_matrix, 
         viewport_matrix, 
-        time: 0, 
-        noise
+        time: 0,
     };
 
+    let comets = system.bodies.iter()
+        .enumerate()
+        .filter(|(_, body)| body.comet)
+        .map(|(index, _)| CometTail::new(index, seed ^ index as u64))
+        .collect();
+    let stars = system.bodies.iter()
+        .enumerate()
+        .filter_map(|(index, body)| {
+            let temperature_k = body.temperature_k? as f32;
+            Some(StarRenderer::new(index, StarSpec { temperature_k, seed: seed ^ index as u64, ..StarSpec::default() }))
+        })
+        .collect();
     let mut scene = Scene {
         objects,
-    };    
+        stars,
+        comets,
+        particles: ParticleSystem::default(),
+        textures,
+        skybox,
+    };
+
+    // Escape del motor, más intenso cuanto mayor es el empuje
+    let mut exhaust = Emitter::new(engine_exhaust(ship.scale), Vec3::zeros(), -ship.forward(), seed);
+    let mut explosion_at: Option<usize> = None;
+
+    // Avisos en pantalla: teclas, planes, fases del piloto automático y colisiones
+    let mut messages = MessageLog::default();
+
+    let mut jd = epoch;
 
     while window.is_open() {
         if window.is_key_down(Key::Escape) {
@@ -364,73 +574,274 @@ fn main() {
 
         time += 1;
 
-        let mut planet_ax = 0.0;
-        let mut planet_az = 0.0;
-        let mut planet_bx = 0.0;
-        let mut planet_bz = 0.0;
-        let aspect_ratio = framebuffer.width as f32 / framebuffer.height as f32;
-
-        // Actualizar posiciones orbitales de los planetas
-        scene.objects.iter_mut().enumerate().for_each(|(index, obj)| {
-            // Ignorar la luna o los anillos que no deben orbitar
-            if matches!(obj.shader, ShaderType::MoonShader | ShaderType::RingShader | ShaderType::Lava) {
-                return;
+        if window.is_key_pressed(Key::F, KeyRepeat::No) {
+            flying = !flying;
+            if flying {
+                saved_days_per_frame = days_per_frame;
+                days_per_frame = 1.0 / SECONDS_PER_DAY;
+            } else {
+                days_per_frame = saved_days_per_frame;
+            }
+        }
+
+        // Aceleración del tiempo: la coma lo reduce a la mitad y el punto lo duplica
+        if window.is_key_pressed(Key::Comma, KeyRepeat::No) {
+            days_per_frame = (days_per_frame * 0.5).max(1.0 / SECONDS_PER_DAY);
+        }
+        if window.is_key_pressed(Key::Period, KeyRepeat::No) {
+            days_per_frame = (days_per_frame * 2.0).min(10.0);
+        }
+
+        if window.is_key_pressed(Key::N, KeyRepeat::No) {
+            ship.flight_model = match ship.flight_model {
+                FlightModel::PatchedConics => FlightModel::NBody,
+                FlightModel::NBody => FlightModel::PatchedConics,
+            };
+            prediction = Prediction::default();
+            messages.post(format!("Flight model: {}", ship.flight_model), time);
+        }
+
+        if window.is_key_pressed(Key::K, KeyRepeat::No) {
+            ship.collision_response = ship.collision_response.next();
+            messages.post(format!("Collision response: {}", ship.collision_response), time);
+        }
+
+        if window.is_key_pressed(Key::LeftBracket, KeyRepeat::No) {
+            target = next_target(&system, target, -1);
+            messages.post(format!("Target: {}", system.bodies[target].name), time);
+        }
+        if window.is_key_pressed(Key::RightBracket, KeyRepeat::No) {
+            target = next_target(&system, target, 1);
+            messages.post(format!("Target: {}", system.bodies[target].name), time);
+        }
+
+        let requested_plan = if window.is_key_pressed(Key::H, KeyRepeat::No) {
+            Some(TransferKind::Hohmann)
+        } else if window.is_key_pressed(Key::L, KeyRepeat::No) {
+            Some(TransferKind::Lambert)
+        } else {
+            None
+        };
+        if let Some(kind) = requested_plan {
+            match plan_transfer(&system, &ship, target, jd, kind) {
+                Ok(new_plan) => {
+                    messages.post(new_plan.summary(&system), time);
+                    plan = Some(new_plan);
+                    autopilot = None;
+                }
+                Err(message) => messages.post(message, time),
             }
+        }
 
-            // Calcular la posición orbital solo si no es el planeta central
-            if index != 0 {
-                let radius = orbit_radii[index - 1];
-                let speed = orbit_speeds[index - 1];
-                let angle = time as f32 * speed;
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            if autopilot.take().is_some() {
+                ship.throttle = 0.0;
+                messages.post("Autopilot off", time);
+            } else if let Some(plan) = &plan {
+                autopilot = Some(Autopilot::new(plan.clone()));
+                messages.post("Autopilot on", time);
+            }
+        }
 
-                // Actualizar la posición orbital
-                obj.transform.position.x = -6.0 + radius * angle.cos();
-                obj.transform.position.z = radius * angle.sin();
+        // El piloto automático limita el paso de tiempo para no saltarse ninguna maniobra
+        let frame_days = autopilot
+            .as_ref()
+            .map_or(days_per_frame, |pilot| pilot.time_step(&system, &ship, jd, days_per_frame));
+        if let Some(pilot) = &mut autopilot {
+            let phase = pilot.phase;
+            pilot.update(&system, &mut ship, jd, frame_days);
+            if pilot.phase != phase {
+                messages.post(format!("Autopilot: {}", pilot.phase), time);
+            }
+            if !pilot.is_active() {
+                autopilot = None;
+            }
+        }
 
-                if index == 3 {
-                    planet_ax = obj.transform.position.x;
-                    planet_az = obj.transform.position.z;
+        // La nave se integra desde el instante actual hasta el siguiente cuadro
+        let previous_position = ship.position;
+        ship.update(&system, jd, frame_days);
+        if let Some(event) = collide_ship(&mut ship, &system, &surfaces, &previous_position, jd, jd + frame_days) {
+            let name = &system.bodies[event.body].name;
+            match event.response {
+                CollisionResponse::Stop => messages.post(format!("Landed on {} at {:.1} m/s", name, event.speed), time),
+                CollisionResponse::Bounce => messages.post(format!("Bounced off {} at {:.1} m/s", name, event.speed), time),
+                CollisionResponse::Crash => {
+                    messages.post(format!("Crashed into {} at {:.0} m/s", name, event.speed), time);
+                    explosion_at = Some(event.body);
+                }
+                CollisionResponse::Merge => messages.post(format!("Absorbed by {}", name), time),
+            }
+            if !ship.is_flying() && autopilot.take().is_some() {
+                messages.post("Autopilot off", time);
+            }
+        }
+        jd += frame_days;
+
+        // Choques entre cuerpos en el modelo de n cuerpos: el más pesado absorbe al otro
+        if ship.flight_model == FlightModel::NBody {
+            for merge in collide_bodies(&mut system, jd) {
+                messages.post(format!("{} absorbed {}", system.bodies[merge.absorber].name, system.bodies[merge.absorbed].name), time);
+                absorb_body(&mut scene, &mut ship, &system, &merge, &display_scale);
+                if plan.as_ref().is_some_and(|plan| plan.target == merge.absorbed || plan.origin == Some(merge.absorbed)) {
+                    plan = None;
+                    autopilot = None;
                 }
-                if index == 6 {
-                    planet_bx = obj.transform.position.x;
-                    planet_bz = obj.transform.position.z;
+                if target == merge.absorbed {
+                    target = next_target(&system, target, 1);
                 }
             }
+        }
+
+        if !ship.is_flying() {
+            prediction = Prediction::default();
+        } else if ship.throttle > 0.0 || time % 30 == 0 || prediction.current().is_none_or(|segment| jd >= segment.end_jd) {
+            prediction = Prediction::new(&system, &ship.position, &ship.velocity, jd, 4);
+        }
+
+        // Actualizar posiciones orbitales a partir de las efemérides
+        let positions = system.scene_positions(jd, &display_scale);
+        scene.objects.iter_mut().for_each(|obj| {
+            if let Some(body) = obj.body {
+                obj.transform.position = positions[body];
+            }
+            if let Some(trail) = &mut obj.trail {
+                trail.push(obj.transform.position);
+            }
         });
+        for star in &mut scene.stars {
+            star.update(positions[star.body], system.display_radius(star.body, &display_scale), jd);
+        }
+        for comet in &mut scene.comets {
+            comet.update(&system, jd, &positions);
+        }
 
-        // Actualizar la posición dinámica de la luna
-        let moon_angle = time as f32 * moon_speed; // Ángulo basado en el tiempo
-        let moon_x = orbit_radius * moon_angle.cos();
-        let moon_z = orbit_radius * moon_angle.sin();
+        let ship_position = ship.scene_position(&system, jd, &display_scale);
 
-        // Actualizar la posición de la luna en la escena
-        if let Some(moon) = scene.objects.iter_mut().find(|obj| matches!(obj.shader, ShaderType::MoonShader)) {
-            moon.transform.position = Vec3::new(planet_ax + moon_x, 0.5, planet_az + moon_z); // Relativa al planeta central
+        // Partículas: los restos de una explosión caen hacia los cuerpos cercanos
+        if let Some(body) = explosion_at.take() {
+            let up = (ship_position - positions[body]).normalize();
+            scene.particles.add(Emitter::new(explosion(ship.scale), ship_position, up, seed ^ time as u64));
         }
-        if let Some(rings) = scene.objects.iter_mut().find(|obj| matches!(obj.shader, ShaderType::RingShader)) {
-            rings.transform.position = Vec3::new(planet_bx, 0.0, planet_bz); // Relativa al planeta central
+        scene.particles.update(&body_attractors(&system, &positions, &display_scale));
+        exhaust.position = ship_position - ship.forward() * (1.25 * ship.scale);
+        exhaust.direction = -ship.forward();
+        exhaust.intensity = if ship.is_flying() && ship.fuel > 0.0 { ship.throttle } else { 0.0 };
+        exhaust.update(&[]);
+        if flying {
+            if window.is_key_pressed(Key::B, KeyRepeat::No) {
+                map_view = !map_view;
+            }
+            handle_ship_input(&window, &mut ship, if map_view { &mut map_zoom } else { &mut chase_distance });
+
+            match prediction.current() {
+                Some(segment) if map_view => {
+                    let center = prediction.anchor(&system, &display_scale, 0, jd);
+                    camera.bird_view(center, map_zoom * map_height(&system, segment, &display_scale));
+                }
+                _ => (camera.eye, camera.center, camera.up) = ship.camera_view(ship_position, chase_distance),
+            }
+        } else {
+            handle_input(&window, &mut camera);
         }
 
-        handle_input(&window, &mut camera);
+        // La cámara nunca entra en los cuerpos
+        camera.eye = keep_outside(camera.eye, &scene_spheres(&system, &positions, &display_scale), 0.15);
 
-        framebuffer.clear();
+        belt.update(&system, jd, &display_scale, &positions, camera.eye);
 
-        skybox.render(&mut framebuffer, &uniforms, camera.eye);
+        framebuffer.clear();
 
         uniforms.model_matrix = create_model_matrix(translation, scale, rotation);
         uniforms.view_matrix = create_view_matrix(camera.eye, camera.center, camera.up);
         uniforms.time = time;
         framebuffer.set_current_color(0xFFDDDD);
 
-        if camera.eye == Vec3::new(-4.5, 15.00, 0.00){
-            // Dibujar órbitas como líneas
-            orbit_draw.iter().for_each(|&radius| {
-                draw_orbit(&mut framebuffer, radius, aspect_ratio);
-            });
+        // La nave y las mallas de las rocas van antes que la escena para que el cielo no
+        // las cubra y las partículas, que no escriben profundidad, queden detrás de ellas
+        let cockpit = flying && !map_view && ship.camera == ShipCamera::Cockpit;
+        if !cockpit && !matches!(ship.status, ShipStatus::Merged { .. }) {
+            render_ship(&mut framebuffer, &ship, ship_position, &mut uniforms);
         }
+        render_asteroids(&mut framebuffer, &belt, &mut uniforms);
 
-        // Renderizar la escena completa
+        // Renderizar la escena completa; el fondo solo se recalcula si la vista giró
+        scene.skybox.update(&uniforms, framebuffer.width, framebuffer.height);
         render_scene(&mut framebuffer, &scene, &mut uniforms);
+        render_asteroid_points(&mut framebuffer, &belt, &uniforms);
+        exhaust.render(&mut framebuffer, &uniforms);
+
+        if window.is_key_pressed(Key::O, KeyRepeat::No) {
+            show_orbits = !show_orbits;
+        }
+
+        // Dibujar órbitas en 3D después de la geometría para que se oculten detrás de los planetas
+        if show_orbits {
+            for (index, body) in system.bodies.iter().enumerate().filter(|(_, body)| body.merged_into.is_none()) {
+                let path = OrbitPath {
+                    points: system.orbit_path(index, jd, &positions, &display_scale, 256),
+                    color: body.orbit_color,
+                };
+                render_orbit(&mut framebuffer, &uniforms, &path, camera.eye, &orbit_fade);
+            }
+        }
+
+        // Trayectoria prevista de la transferencia planificada
+        if let Some(plan) = &plan {
+            render_transfer(&mut framebuffer, &uniforms, &system, plan, &positions, &display_scale);
+        }
+
+        // Órbita prevista de la nave, con periapsis, apoapsis y cambios de esfera de influencia
+        if show_orbits {
+            render_prediction(&mut framebuffer, &uniforms, &system, &prediction, jd, &display_scale);
+        }
+
+        if window.is_key_pressed(Key::T, KeyRepeat::No) {
+            show_trails = !show_trails;
+        }
+
+        // Estelas con las posiciones recientes de cada cuerpo
+        if show_trails {
+            for object in &scene.objects {
+                if let (Some(trail), Some(body)) = (&object.trail, object.body) {
+                    let style = LineStyle::new(system.bodies[body].orbit_color.into()).with_width(1.5);
+                    render_trail(&mut framebuffer, &uniforms, trail, &style);
+                }
+            }
+        }
+
+        if window.is_key_pressed(Key::G, KeyRepeat::No) {
+            show_axes = !show_axes;
+        }
+        if show_axes {
+            draw_axes(&mut framebuffer, &uniforms, camera.center, 2.0);
+        }
+
+        render_orbit_hud(&mut framebuffer, &uniforms, &system, &prediction, jd, &display_scale);
+        messages.render(&mut framebuffer, time);
+
+        // Estado de la nave en el título de la ventana
+        if time % 15 == 0 {
+            let positions = system.heliocentric_positions(jd);
+            let reference = system.soi_body(&ship.position, &positions);
+            let relative_velocity = ship.velocity - system.heliocentric_velocity(reference, jd);
+            let speed = relative_velocity.magnitude() * KM_PER_AU / SECONDS_PER_DAY;
+            let pilot = match &autopilot {
+                Some(pilot) => format!(" | autopilot: {}", pilot.phase),
+                None => String::new(),
+            };
+            window.set_title(&format!(
+                "Space Travel - fuel {:.0}% | throttle {:.0}% | {:.2} km/s rel. {} | dv {:.0} m/s | warp {:.0} s/frame | target {}{}",
+                100.0 * ship.fuel / ship.spec.fuel_capacity,
+                100.0 * ship.throttle,
+                speed,
+                system.bodies[reference].name,
+                ship.delta_v(),
+                days_per_frame * SECONDS_PER_DAY,
+                system.bodies[target].name,
+                pilot,
+            ));
+        }
 
         window
             .update_with_buffer(&framebuffer.buffer, framebuffer_width, framebuffer_height)
@@ -438,6 +849,56 @@ fn main() {
     }
 }
 
+// Siguiente cuerpo con órbita en la dirección indicada (1 o -1), para elegir el destino
+fn next_target(system: &SolarSystem, current: usize, direction: isize) -> usize {
+    let count = system.bodies.len() as isize;
+    (1..=count)
+        .map(|step| (current as isize + direction * step).rem_euclid(count) as usize)
+        .find(|&index| system.bodies[index].orbit.is_some() && system.bodies[index].merged_into.is_none())
+        .unwrap_or(current)
+}
+
+// Quita de la escena el cuerpo absorbido y agranda el que lo absorbió. Una nave posada
+// sobre el cuerpo absorbido termina dentro del otro.
+fn absorb_body(scene: &mut Scene, ship: &mut Ship, system: &SolarSystem, merge: &BodyMerge, scale: &DisplayScale) {
+    let growth = scale.radius(system.bodies[merge.absorber].radius_km) / scale.radius(merge.radius_before_km);
+    scene.objects.retain(|object| object.body != Some(merge.absorbed));
+    for object in scene.objects.iter_mut().filter(|object| object.body == Some(merge.absorber)) {
+        object.transform.scale *= growth;
+    }
+    scene.stars.retain(|star| star.body != merge.absorbed);
+    scene.comets.retain(|comet| comet.body != merge.absorbed);
+
+    let on_absorbed = match ship.status {
+        ShipStatus::Landed { body, .. } | ShipStatus::Crashed { body, .. } | ShipStatus::Merged { body } => body == merge.absorbed,
+        ShipStatus::Flying => false,
+    };
+    if on_absorbed {
+        ship.status = ShipStatus::Merged { body: merge.absorber };
+    }
+}
+
+// Altura de la vista de mapa para que quepa la órbita actual, o su parte dentro de la
+// esfera de influencia si es abierta
+fn map_height(system: &SolarSystem, segment: &Segment, scale: &DisplayScale) -> f32 {
+    let conic = &segment.conic;
+    let radius = conic.position.magnitude();
+    let extent = conic.apoapsis()
+        .unwrap_or(f64::INFINITY)
+        .min(system.sphere_of_influence(conic.body))
+        .min(8.0 * radius)
+        .max(radius);
+    3.0 * system.offset_from(conic.body, &DVec3::new(extent, 0.0, 0.0), scale).magnitude()
+}
+
+// Valor de una opción de línea de comandos, p. ej. `--epoch 2024-03-20`
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+        .cloned()
+}
+
 fn handle_input(window: &Window, camera: &mut Camera) {
     let movement_speed = 1.0;
     let rotation_speed = PI/50.0;
@@ -484,9 +945,46 @@ fn handle_input(window: &Window, camera: &mut Camera) {
         camera.zoom(-zoom_speed);
     }
     if window.is_key_down(Key::B) {
-        camera.eye = Vec3::new(-4.5, 15.00, 0.00);
-        camera.center = Vec3::new(-6.0, 0.0, 0.0);
-        camera.up = Vec3::new(0.0, 1.0, 0.0);
+        camera.bird_view(Vec3::new(0.0, 0.0, 0.0), 150.0);
     }
 
 }
+
+fn handle_ship_input(window: &Window, ship: &mut Ship, chase_distance: &mut f32) {
+    // Control de actitud con los propulsores RCS
+    let axis = |positive: Key, negative: Key| {
+        window.is_key_down(positive) as i32 as f32 - window.is_key_down(negative) as i32 as f32
+    };
+    ship.rotate(axis(Key::W, Key::S), axis(Key::A, Key::D), axis(Key::E, Key::Q));
+
+    // Potencia del motor principal
+    if window.is_key_down(Key::LeftShift) {
+        ship.throttle = (ship.throttle + 0.02).min(1.0);
+    }
+    if window.is_key_down(Key::LeftCtrl) {
+        ship.throttle = (ship.throttle - 0.02).max(0.0);
+    }
+    if window.is_key_pressed(Key::Z, KeyRepeat::No) {
+        ship.throttle = 1.0;
+    }
+    if window.is_key_pressed(Key::X, KeyRepeat::No) {
+        ship.throttle = 0.0;
+    }
+
+    // Estabilizador (SAS) y cámara de persecución o de cabina
+    if window.is_key_pressed(Key::R, KeyRepeat::No) {
+        ship.stabilize = !ship.stabilize;
+    }
+    if window.is_key_pressed(Key::C, KeyRepeat::No) {
+        ship.camera = match ship.camera {
+            ShipCamera::Chase => ShipCamera::Cockpit,
+            ShipCamera::Cockpit => ShipCamera::Chase,
+        };
+    }
+    if window.is_key_down(Key::Up) {
+        *chase_distance = (*chase_distance * 0.97).max(0.1);
+    }
+    if window.is_key_down(Key::Down) {
+        *chase_distance = (*chase_distance * 1.03).min(5.0);
+    }
+}
@@ -0,0 +1,132 @@
+// material.rs
+use nalgebra_glm::{Vec2, Vec3, Mat4, mat4_to_mat3};
+use crate::color::LinearColor;
+use crate::fragment::Fragment;
+use crate::normal_map::{sample_normal, height_normal, parallax_occlusion, tangent_frame};
+use crate::texture::{TextureHandle, TextureRegistry, TextureSpace, WrapMode, Filter};
+use crate::Uniforms;
+
+// Same ambient term as the rasterizer
+const AMBIENT: f32 = 0.03;
+const LIGHT_DIRECTION: Vec3 = Vec3::new(0.0, 0.0, 1.0);
+// Blinn-Phong exponent for the highlight where the specular map is bright
+const SHININESS: f32 = 32.0;
+// Turns per frame of the cloud layer around the planet, in texture widths
+const CLOUD_DRIFT: f32 = 0.00005;
+// How far the lowest point of a height map lies under its highest, in texture
+// coordinates, and the mip level past which its relief is smaller than a pixel and
+// parallax is skipped, so only close views pay for it
+const RELIEF_DEPTH: f32 = 0.02;
+const PARALLAX_MAX_LOD: f32 = 2.0;
+
+// Image file of one material layer and how it is sampled
+#[derive(Clone, Debug, Default)]
+pub struct LayerPath {
+    pub path: String,
+    pub wrap: WrapMode,
+    pub filter: Filter,
+}
+
+// Image files for each layer of a body's material, as written in the elements file
+#[derive(Clone, Debug, Default)]
+pub struct MaterialPaths {
+    pub albedo: Option<LayerPath>,
+    pub normal: Option<LayerPath>,
+    pub specular: Option<LayerPath>,
+    pub emissive: Option<LayerPath>,
+    pub clouds: Option<LayerPath>,
+    pub height: Option<LayerPath>,
+}
+
+// Textures bound to an object. The albedo gives the surface color; the normal map
+// bends the lighting, the specular map sets where the surface shines, the emissive
+// map glows on its own (city lights, say) and the cloud layer drifts on top. The
+// height map shifts every other layer by parallax, so bumps hide what lies behind
+// them, and shades the relief itself when there is no normal map.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Material {
+    pub albedo: Option<TextureHandle>,
+    pub normal: Option<TextureHandle>,
+    pub specular: Option<TextureHandle>,
+    pub emissive: Option<TextureHandle>,
+    pub clouds: Option<TextureHandle>,
+    pub height: Option<TextureHandle>,
+}
+
+impl Material {
+    // Loads every layer into the registry. A layer whose image can't be read is left
+    // out with a warning, so a missing file never stops the program.
+    pub fn load(paths: &MaterialPaths, textures: &mut TextureRegistry) -> Self {
+        let mut load = |layer: &Option<LayerPath>, space: TextureSpace| {
+            let layer = layer.as_ref().filter(|layer| !layer.path.is_empty())?;
+            textures.load(&layer.path, space, layer.wrap, layer.filter)
+                .map_err(|err| eprintln!("Warning: could not load texture {}: {}", layer.path, err))
+                .ok()
+        };
+
+        Material {
+            albedo: load(&paths.albedo, TextureSpace::Srgb),
+            normal: load(&paths.normal, TextureSpace::Linear),
+            specular: load(&paths.specular, TextureSpace::Linear),
+            emissive: load(&paths.emissive, TextureSpace::Srgb),
+            clouds: load(&paths.clouds, TextureSpace::Srgb),
+            height: load(&paths.height, TextureSpace::Linear),
+        }
+    }
+
+    // Without an albedo the object keeps its procedural shader
+    pub fn is_textured(&self) -> bool {
+        self.albedo.is_some()
+    }
+
+    pub fn shade(&self, fragment: &Fragment, uniforms: &Uniforms, textures: &TextureRegistry) -> LinearColor {
+        let (dx, dy) = (fragment.tex_dx, fragment.tex_dy);
+        let frame = tangent_frame(&fragment.world_normal, &fragment.world_tangent, fragment.bitangent_sign);
+        let toward_eye = (camera_position(&uniforms.view_matrix) - fragment.world_position)
+            .try_normalize(1e-6)
+            .unwrap_or(LIGHT_DIRECTION);
+
+        let uv = match self.height.map(|handle| textures.get(handle)) {
+            Some(height) if height.level_of_detail(dx, dy) < PARALLAX_MAX_LOD => {
+                parallax_occlusion(height, fragment.tex_coords, dx, dy, &(frame.transpose() * toward_eye), RELIEF_DEPTH)
+            }
+            _ => fragment.tex_coords,
+        };
+        let sample = |handle: TextureHandle| textures.get(handle).sample_grad(uv, dx, dy);
+        let albedo = self.albedo.map_or(LinearColor::WHITE, sample);
+
+        let normal = match (self.normal, self.height) {
+            (Some(handle), _) => (frame * sample_normal(textures.get(handle), uv, dx, dy)).normalize(),
+            (None, Some(handle)) => (frame * height_normal(textures.get(handle), uv, dx, dy, RELIEF_DEPTH)).normalize(),
+            (None, None) => fragment.world_normal.normalize(),
+        };
+        let diffuse = normal.dot(&LIGHT_DIRECTION).max(0.0);
+        let mut color = albedo * (AMBIENT + diffuse);
+
+        if let Some(handle) = self.specular {
+            let half = (toward_eye + LIGHT_DIRECTION).normalize();
+            let strength = sample(handle).r;
+            let highlight = normal.dot(&half).max(0.0).powf(SHININESS) * strength;
+            color += LinearColor::WHITE * (highlight * diffuse.min(1.0));
+        }
+
+        // Emission shows mostly on the night side
+        if let Some(handle) = self.emissive {
+            color += sample(handle) * (1.0 - diffuse.min(1.0));
+        }
+
+        if let Some(handle) = self.clouds {
+            let drifted = uv + Vec2::new(uniforms.time as f32 * CLOUD_DRIFT, 0.0);
+            let cloud = textures.get(handle).sample_grad(drifted, dx, dy);
+            let cover = cloud.a.min(cloud.luminance().sqrt());
+            color = color.lerp(&(cloud * (AMBIENT + diffuse)), cover);
+        }
+
+        color.with_alpha(1.0)
+    }
+}
+
+// Where the camera is in the world, undoing the view matrix's rotation of its translation
+fn camera_position(view: &Mat4) -> Vec3 {
+    -(mat4_to_mat3(view).transpose() * Vec3::new(view[(0, 3)], view[(1, 3)], view[(2, 3)]))
+}
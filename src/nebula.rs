@@ -0,0 +1,155 @@
+// nebula.rs
+use nalgebra_glm::{Mat3, Vec3, mat4_to_mat3};
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use crate::color::LinearColor;
+use crate::noise::Noise;
+use crate::ramp::ColorRamp;
+use crate::skybox::{celestial_direction, sky_direction, sky_unprojection, SKY_DEPTH};
+use crate::{Framebuffer, Uniforms};
+
+// Galactic north pole and galactic center, as right ascension (hours) and declination
+// (degrees), so the band lines up with the catalog stars
+const GALACTIC_POLE: (f64, f64) = (12.857, 27.128);
+const GALACTIC_CENTER: (f64, f64) = (17.761, -28.936);
+// Sine of the galactic latitude where the band has faded to a third, and where the
+// dust lanes along its middle have
+const BAND_WIDTH: f32 = 0.2;
+const DUST_WIDTH: f32 = 0.06;
+// Brightest the clouds and the band get, in linear light; the background stays faint
+// so the stars stand out
+const CLOUD_BRIGHTNESS: f32 = 0.2;
+const BAND_BRIGHTNESS: f32 = 0.04;
+// Screen pixels between two evaluations of the noise. The pass is smooth, so it is
+// computed on this coarser grid and stretched over the screen.
+const DOWNSAMPLE: usize = 4;
+
+// Look of the deep-space background. `density` is the share of the sky under clouds,
+// from none at 0 to all of it at 1; `band` scales the galactic band, 0 hiding it. The
+// same seed always gives the same clouds.
+#[derive(Clone, Debug)]
+pub struct NebulaSpec {
+    pub seed: u64,
+    pub density: f32,
+    pub palette: ColorRamp,
+    pub band: f32,
+}
+
+impl Default for NebulaSpec {
+    fn default() -> Self {
+        NebulaSpec {
+            seed: 1,
+            density: 0.3,
+            palette: ColorRamp::from_json(include_str!("../assets/palettes/nebula.json")).expect("Invalid nebula palette"),
+            band: 1.0,
+        }
+    }
+}
+
+// Colored gas clouds and the dusty band of the galaxy, evaluated along every view
+// direction. The coarse grid is kept and only recomputed when the view turns.
+pub struct Nebula {
+    pub spec: NebulaSpec,
+    clouds: Noise,
+    tint: Noise,
+    dust: Noise,
+    pole: Vec3,
+    center: Vec3,
+    grid: Vec<LinearColor>,
+    columns: usize,
+    view: Option<(Mat3, usize, usize)>, // rotation and screen size the grid was made for
+}
+
+impl Nebula {
+    pub fn new(spec: NebulaSpec) -> Self {
+        let mut rng = StdRng::seed_from_u64(spec.seed);
+        Nebula {
+            clouds: Noise::new(rng.gen()).with_frequency(1.8).with_octaves(5),
+            tint: Noise::new(rng.gen()).with_frequency(1.2).with_octaves(3),
+            dust: Noise::new(rng.gen()).with_frequency(4.0).with_octaves(4),
+            pole: celestial_direction(GALACTIC_POLE.0, GALACTIC_POLE.1),
+            center: celestial_direction(GALACTIC_CENTER.0, GALACTIC_CENTER.1),
+            grid: Vec::new(),
+            columns: 0,
+            view: None,
+            spec,
+        }
+    }
+
+    // Light of the background in a direction
+    pub fn sample(&self, direction: &Vec3) -> LinearColor {
+        // Swirled fBm, kept only above a threshold set by the density. The sum stays
+        // mostly within a quarter of its middle, so that is the range the threshold spans.
+        let shape = 0.5 + 0.5 * self.clouds.fbm(&self.clouds.warp(direction, 0.5));
+        let threshold = 0.75 - 0.5 * self.spec.density;
+        let cover = smoothstep(threshold, threshold + 0.25, shape);
+        let tint = (0.5 + 0.8 * self.tint.fbm(direction)).clamp(0.0, 1.0);
+        let mut color = self.spec.palette.sample(tint) * (cover * CLOUD_BRIGHTNESS);
+
+        if self.spec.band > 0.0 {
+            // Glow around the galactic plane, brighter towards the center, mottled, and
+            // split down the middle by dark lanes of dust
+            let latitude = direction.dot(&self.pole);
+            let glow = (-(latitude / BAND_WIDTH).powi(2)).exp();
+            let bulge = 1.0 + 1.5 * direction.dot(&self.center).max(0.0).powi(4);
+            let mottling = 0.6 + 0.4 * self.dust.fbm(direction);
+            let lanes = 1.0 - 0.8 * (-(latitude / DUST_WIDTH).powi(2)).exp() * self.dust.ridged(direction);
+            let light = self.spec.palette.sample(0.9).lerp(&LinearColor::rgb(1.0, 0.85, 0.65), 0.7);
+            color += light * (glow * bulge * mottling * lanes * BAND_BRIGHTNESS * self.spec.band);
+        }
+
+        color.with_alpha(1.0)
+    }
+
+    // Evaluates the coarse grid for this frame's view, unless it already matches it
+    pub fn update(&mut self, uniforms: &Uniforms, width: usize, height: usize) {
+        let rotation = mat4_to_mat3(&uniforms.view_matrix);
+        if let Some((previous, w, h)) = &self.view {
+            if (w, h) == (&width, &height) && (rotation - previous).abs().max() < 1e-6 {
+                return;
+            }
+        }
+        self.view = Some((rotation, width, height));
+
+        let Some(unproject) = sky_unprojection(uniforms) else {
+            return;
+        };
+        self.columns = width / DOWNSAMPLE + 2;
+        let rows = height / DOWNSAMPLE + 2;
+        self.grid = (0..rows)
+            .flat_map(|row| (0..self.columns).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let point = ((column * DOWNSAMPLE) as f32, (row * DOWNSAMPLE) as f32);
+                sky_direction(&unproject, point, width, height).map_or(LinearColor::BLACK, |direction| self.sample(&direction))
+            })
+            .collect();
+    }
+
+    // Adds the grid, blended between its points, to every pixel nothing was drawn on
+    pub fn render(&self, framebuffer: &mut Framebuffer) {
+        if self.grid.is_empty() {
+            return;
+        }
+
+        for y in 0..framebuffer.height {
+            let row = y as f32 / DOWNSAMPLE as f32;
+            let (top, fy) = (row as usize, row.fract());
+            for x in 0..framebuffer.width {
+                if !framebuffer.is_empty(x, y) {
+                    continue;
+                }
+                let column = x as f32 / DOWNSAMPLE as f32;
+                let (left, fx) = (column as usize, column.fract());
+                let at = |c: usize, r: usize| self.grid[r * self.columns + c];
+                let upper = at(left, top).lerp(&at(left + 1, top), fx);
+                let lower = at(left, top + 1).lerp(&at(left + 1, top + 1), fx);
+                framebuffer.add_point(x as isize, y as isize, SKY_DEPTH, upper.lerp(&lower, fy));
+            }
+        }
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
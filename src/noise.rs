@@ -0,0 +1,162 @@
+// noise.rs
+use nalgebra_glm::Vec3;
+use fastnoise_lite::{FastNoiseLite, NoiseType, FractalType};
+
+// Offsets between the three fields of a domain warp, far enough apart that they
+// don't look alike
+const WARP_OFFSETS: [Vec3; 3] = [
+    Vec3::new(0.0, 0.0, 0.0),
+    Vec3::new(31.4, 17.2, -8.9),
+    Vec3::new(-12.7, 45.3, 23.1),
+];
+
+// Seeded 3D noise with its own frequency and octave set. Every helper sums octaves of
+// the same OpenSimplex2 field, each `lacunarity` times finer and `gain` times weaker
+// than the one before.
+pub struct Noise {
+    base: FastNoiseLite,
+    seed: i32,
+    pub frequency: f32,
+    pub octaves: u32,
+    pub lacunarity: f32,
+    pub gain: f32,
+}
+
+// Distances from a point to the nearest and second nearest feature points of a Worley
+// field, in cells, and a value in [0, 1] that is the same all over the nearest cell
+#[derive(Clone, Copy, Debug)]
+pub struct Cell {
+    pub f1: f32,
+    pub f2: f32,
+    pub value: f32,
+}
+
+impl Noise {
+    pub fn new(seed: i32) -> Self {
+        let mut base = FastNoiseLite::with_seed(seed);
+        base.set_noise_type(Some(NoiseType::OpenSimplex2));
+        base.set_fractal_type(Some(FractalType::None));
+        base.set_frequency(Some(1.0));
+        Noise { base, seed, frequency: 1.0, octaves: 4, lacunarity: 2.0, gain: 0.5 }
+    }
+
+    pub fn with_frequency(mut self, frequency: f32) -> Self {
+        self.frequency = frequency;
+        self
+    }
+
+    pub fn with_octaves(mut self, octaves: u32) -> Self {
+        self.octaves = octaves.max(1);
+        self
+    }
+
+    pub fn with_lacunarity(mut self, lacunarity: f32) -> Self {
+        self.lacunarity = lacunarity;
+        self
+    }
+
+    pub fn with_gain(mut self, gain: f32) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    // A single octave, in [-1, 1]
+    pub fn sample(&self, point: &Vec3) -> f32 {
+        let p = point * self.frequency;
+        self.base.get_noise_3d(p.x, p.y, p.z)
+    }
+
+    // Fractal Brownian motion: plain octaves added up, in [-1, 1]
+    pub fn fbm(&self, point: &Vec3) -> f32 {
+        self.octaves(point, |value| value)
+    }
+
+    // Octaves folded around zero into rounded lumps, like cumulus clouds, in [-1, 1]
+    pub fn billow(&self, point: &Vec3) -> f32 {
+        self.octaves(point, |value| 2.0 * value.abs() - 1.0)
+    }
+
+    // Absolute values of the octaves added up, with creases where each one crosses
+    // zero, in [0, 1]
+    pub fn turbulence(&self, point: &Vec3) -> f32 {
+        self.octaves(point, |value| value.abs())
+    }
+
+    // Ridged multifractal: octaves turned into sharp crests, each one weighted by the
+    // crest below it so detail gathers along the ridges and valleys stay smooth, in [0, 1]
+    pub fn ridged(&self, point: &Vec3) -> f32 {
+        let mut weight = 1.0;
+        self.octaves(point, |value| {
+            let crest = (1.0 - value.abs()).powi(2) * weight;
+            weight = crest.clamp(0.0, 1.0);
+            crest
+        })
+    }
+
+    // The point pushed around by three fBm fields, `strength` units at most. Sampling
+    // another helper at the result swirls its pattern.
+    pub fn warp(&self, point: &Vec3, strength: f32) -> Vec3 {
+        let offset = Vec3::new(
+            self.fbm(&(point + WARP_OFFSETS[0])),
+            self.fbm(&(point + WARP_OFFSETS[1])),
+            self.fbm(&(point + WARP_OFFSETS[2])),
+        );
+        point + offset * strength
+    }
+
+    // Worley (cellular) noise with one jittered feature point per unit cell
+    pub fn worley(&self, point: &Vec3) -> Cell {
+        let p = point * self.frequency;
+        let cell = p.map(f32::floor);
+        let (mut f1, mut f2, mut nearest) = (f32::MAX, f32::MAX, 0);
+
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let corner = cell + Vec3::new(dx as f32, dy as f32, dz as f32);
+                    let hash = self.hash(&corner);
+                    let jitter = Vec3::new(unit(hash), unit(hash.rotate_left(11)), unit(hash.rotate_left(22)));
+                    let distance = (corner + jitter - p).magnitude();
+                    if distance < f1 {
+                        f2 = f1;
+                        f1 = distance;
+                        nearest = hash;
+                    } else if distance < f2 {
+                        f2 = distance;
+                    }
+                }
+            }
+        }
+
+        Cell { f1, f2, value: unit(nearest.wrapping_mul(0x9E37_79B9)) }
+    }
+
+    // Adds up the octaves after `shape` and divides by the total weight, so the range
+    // doesn't depend on the octave count
+    fn octaves(&self, point: &Vec3, mut shape: impl FnMut(f32) -> f32) -> f32 {
+        let mut p = point * self.frequency;
+        let (mut total, mut weight, mut amplitude) = (0.0, 0.0, 1.0);
+        for _ in 0..self.octaves {
+            let value = self.base.get_noise_3d(p.x, p.y, p.z);
+            total += shape(value) * amplitude;
+            weight += amplitude;
+            amplitude *= self.gain;
+            p *= self.lacunarity;
+        }
+        total / weight
+    }
+
+    fn hash(&self, cell: &Vec3) -> u32 {
+        let mut h = self.seed as u32;
+        for coordinate in [cell.x, cell.y, cell.z] {
+            h ^= (coordinate as i32) as u32;
+            h = h.wrapping_mul(0x27D4_EB2D);
+            h ^= h >> 15;
+        }
+        h.wrapping_mul(0x2C1B_3C6D) ^ (h >> 13)
+    }
+}
+
+fn unit(hash: u32) -> f32 {
+    (hash & 0xFF_FFFF) as f32 / 0xFF_FFFF as f32
+}
@@ -1,57 +1,88 @@
-use std::sync::Arc;
-use once_cell::sync::OnceCell;
-use nalgebra_glm::Vec3;
+// normal_map.rs
+use nalgebra_glm::{Vec2, Vec3, Mat3};
+use crate::texture::Texture;
 
-static NORMAL_MAP: OnceCell<Arc<NormalMap>> = OnceCell::new();
+// Fewest and most steps parallax occlusion takes through the height field, for a ray
+// looking straight down and one grazing the surface
+const MIN_PARALLAX_STEPS: f32 = 8.0;
+const MAX_PARALLAX_STEPS: f32 = 32.0;
 
-#[derive(Clone, Debug)]
-pub struct NormalMap {
-    width: u32,
-    height: u32,
-    data: Vec<Vec3>,
+// Tangent-space normal stored in a texture loaded as linear data, from [0, 1] per
+// channel to [-1, 1], filtered like any other texture for a pixel whose coordinates
+// change by `dx` and `dy`. Maps are expected with green pointing up the image, while
+// our v runs down it, so green is flipped to follow v.
+pub fn sample_normal(texture: &Texture, uv: Vec2, dx: Vec2, dy: Vec2) -> Vec3 {
+    let texel = texture.sample_grad(uv, dx, dy);
+    let normal = Vec3::new(texel.r, 1.0 - texel.g, texel.b) * 2.0 - Vec3::new(1.0, 1.0, 1.0);
+    normal.try_normalize(1e-6).unwrap_or(Vec3::new(0.0, 0.0, 1.0))
 }
 
-impl NormalMap {
-    pub fn new(path: &str) -> Result<Self, image::ImageError> {
-        let img = image::open(path)?.to_rgba8();
-        let (width, height) = img.dimensions();
-        
-        // Convert RGB colors to normal vectors (assuming tangent-space normal map)
-        let data = img.pixels()
-            .map(|p| {
-                // Convert from [0,255] to [-1,1] range
-                let x = (p[0] as f32 / 255.0) * 2.0 - 1.0;
-                let y = (p[1] as f32 / 255.0) * 2.0 - 1.0;
-                let z = (p[2] as f32 / 255.0) * 2.0 - 1.0;
-                Vec3::new(x, y, z).normalize()
-            })
-            .collect();
-
-        Ok(NormalMap { width, height, data })
-    }
+// Columns are the directions in which u and v grow along the surface and the normal,
+// from the mesh's tangent and bitangent sign. Multiplying carries tangent-space
+// vectors onto the surface; the transpose brings world vectors into tangent space.
+// Interpolation bends the tangent off the surface, so it is straightened against the
+// normal first; models without texture coordinates fall back to a tangent built from
+// the world's up axis.
+pub fn tangent_frame(normal: &Vec3, tangent: &Vec3, bitangent_sign: f32) -> Mat3 {
+    let normal = normal.normalize();
+    let tangent = (tangent - normal * normal.dot(tangent))
+        .try_normalize(1e-6)
+        .unwrap_or_else(|| fallback_tangent(&normal));
+    let bitangent = normal.cross(&tangent) * bitangent_sign;
 
-    pub fn sample(&self, u: f32, v: f32) -> Vec3 {
-        let u = u.fract().abs();
-        let v = v.fract().abs();
-        
-        let x = (u * (self.width as f32)) as u32;
-        let y = (v * (self.height as f32)) as u32;
-        
-        let index = (y * self.width + x) as usize;
-        self.data[index]
-    }
+    Mat3::from_columns(&[tangent, bitangent, normal])
 }
 
-pub fn init_normal_map(path: &str) -> Result<(), image::ImageError> {
-    let normal_map = NormalMap::new(path)?;
-    NORMAL_MAP.set(Arc::new(normal_map))
-        .expect("Normal map already initialized");
-    Ok(())
+// Height of a height map (white is high) at `uv`, in [0, 1]
+fn sample_height(texture: &Texture, uv: Vec2, dx: Vec2, dy: Vec2) -> f32 {
+    texture.sample_grad(uv, dx, dy).r
+}
+
+// Normal of the height field itself, for surfaces with a height map but no normal
+// map. Slopes are measured a pixel's footprint (at least a texel) to each side, with
+// the lowest point `depth` texture coordinates under the highest.
+pub fn height_normal(texture: &Texture, uv: Vec2, dx: Vec2, dy: Vec2, depth: f32) -> Vec3 {
+    let step_u = (dx.x.abs() + dy.x.abs()).max(1.0 / texture.width() as f32);
+    let step_v = (dx.y.abs() + dy.y.abs()).max(1.0 / texture.height() as f32);
+    let height = |offset: Vec2| sample_height(texture, uv + offset, dx, dy);
+
+    let slope_u = (height(Vec2::new(step_u, 0.0)) - height(Vec2::new(-step_u, 0.0))) / (2.0 * step_u);
+    let slope_v = (height(Vec2::new(0.0, step_v)) - height(Vec2::new(0.0, -step_v))) / (2.0 * step_v);
+    Vec3::new(-slope_u * depth, -slope_v * depth, 1.0).normalize()
 }
 
-pub fn with_normal_map(f: impl FnOnce(&NormalMap) -> Vec3) -> Vec3 {
-    let normal_map = NORMAL_MAP.get()
-        .expect("Normal map not initialized");
-    f(normal_map)
+// Parallax occlusion mapping: follows the ray from the eye down into the height field
+// under `uv`, in even steps from the top of the relief, and returns the texture
+// coordinates where it first meets the surface, so nearer bumps hide the ones behind
+// them. `view` points from the surface towards the eye in tangent space; `depth` is
+// how far the lowest point lies under the highest, in texture coordinates.
+pub fn parallax_occlusion(texture: &Texture, uv: Vec2, dx: Vec2, dy: Vec2, view: &Vec3, depth: f32) -> Vec2 {
+    // Rays almost along the surface would travel across the whole texture
+    let facing = view.z.max(0.1);
+    let steps = MAX_PARALLAX_STEPS + (MIN_PARALLAX_STEPS - MAX_PARALLAX_STEPS) * facing;
+    let layer = 1.0 / steps.round();
+    let shift = Vec2::new(view.x, view.y) / facing * depth * layer;
+
+    let (mut current, mut ray) = (uv, 0.0);
+    let mut surface = 1.0 - sample_height(texture, current, dx, dy);
+    let (mut previous, mut previous_gap) = (current, surface);
+    while ray < surface && ray < 1.0 {
+        (previous, previous_gap) = (current, surface - ray);
+        current -= shift;
+        ray += layer;
+        surface = 1.0 - sample_height(texture, current, dx, dy);
+    }
+
+    // Where the ray crossed the surface between the last two steps
+    let gap = surface - ray;
+    if gap >= 0.0 || previous_gap - gap <= 0.0 {
+        return current;
+    }
+    let weight = -gap / (previous_gap - gap);
+    previous * weight + current * (1.0 - weight)
 }
 
+fn fallback_tangent(normal: &Vec3) -> Vec3 {
+    let up = if normal.y.abs() < 0.999 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(0.0, 0.0, 1.0) };
+    up.cross(normal).normalize()
+}
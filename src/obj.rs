@@ -1,7 +1,12 @@
+use std::collections::HashMap;
 use tobj;
-use nalgebra_glm::{Vec2, Vec3};
+use nalgebra_glm::{Vec2, Vec3, Vec4};
 use crate::vertex::Vertex;
 
+// Deepest subdivision `Obj::sphere` is asked for: 7 already gives 20 * 4^7 (about
+// 330,000) triangles per body, and every extra level quadruples that
+pub const MAX_SUBDIVISIONS: u32 = 7;
+
 pub struct Obj {
     meshes: Vec<Mesh>,
 }
@@ -10,6 +15,7 @@ struct Mesh {
     vertices: Vec<Vec3>,
     normals: Vec<Vec3>,
     texcoords: Vec<Vec2>,
+    tangents: Vec<Vec4>, // along +u, with the sign of the bitangent (+v) in w
     indices: Vec<u32>,
 }
 
@@ -23,7 +29,7 @@ impl Obj {
 
         let meshes = models.into_iter().map(|model| {
             let mesh = model.mesh;
-            Mesh {
+            let mut mesh = Mesh {
                 vertices: mesh.positions.chunks(3)
                     .map(|v| Vec3::new(v[0], v[1], v[2]))
                     .collect(),
@@ -33,13 +39,125 @@ impl Obj {
                 texcoords: mesh.texcoords.chunks(2)
                     .map(|t| Vec2::new(t[0], 1.0 - t[1]))
                     .collect(),
+                tangents: Vec::new(),
                 indices: mesh.indices,
-            }
+            };
+            mesh.tangents = mesh.compute_tangents();
+            mesh
         }).collect();
 
         Ok(Obj { meshes })
     }
 
+    // Flat-shaded mesh from triangle corners, for generated geometry. Faces are wound
+    // so their normals point away from the origin.
+    pub fn from_triangles(triangles: &[[Vec3; 3]]) -> Self {
+        let mut mesh = Mesh {
+            vertices: Vec::with_capacity(triangles.len() * 3),
+            normals: Vec::with_capacity(triangles.len() * 3),
+            texcoords: Vec::new(),
+            tangents: Vec::new(),
+            indices: Vec::with_capacity(triangles.len() * 3),
+        };
+
+        for &[a, b, c] in triangles {
+            let mut normal = (b - a).cross(&(c - a)).normalize();
+            let corners = if normal.dot(&(a + b + c)) < 0.0 {
+                normal = -normal;
+                [a, c, b]
+            } else {
+                [a, b, c]
+            };
+            for corner in corners {
+                mesh.indices.push(mesh.vertices.len() as u32);
+                mesh.vertices.push(corner);
+                mesh.normals.push(normal);
+            }
+        }
+
+        Obj { meshes: vec![mesh] }
+    }
+
+    // Smooth icosphere the size of sphere.obj (radius 0.5), each face of the
+    // icosahedron split in four `subdivisions` times, for bodies whose surface is
+    // moved by `displace`. It has no texture coordinates.
+    pub fn sphere(subdivisions: u32) -> Self {
+        let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+        let mut vertices: Vec<Vec3> = [
+            (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+            (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+            (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+        ]
+        .iter()
+        .map(|&(x, y, z)| Vec3::new(x, y, z).normalize())
+        .collect();
+        let mut faces: Vec<[u32; 3]> = vec![
+            [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+            [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+            [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+            [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+        ];
+
+        for _ in 0..subdivisions {
+            // Edges are shared by two faces, which must share the new vertex too
+            let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+            let mut midpoint = |a: u32, b: u32, vertices: &mut Vec<Vec3>| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    vertices.push((vertices[a as usize] + vertices[b as usize]).normalize());
+                    (vertices.len() - 1) as u32
+                })
+            };
+            faces = faces
+                .iter()
+                .flat_map(|&[a, b, c]| {
+                    let ab = midpoint(a, b, &mut vertices);
+                    let bc = midpoint(b, c, &mut vertices);
+                    let ca = midpoint(c, a, &mut vertices);
+                    [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        let mesh = Mesh {
+            normals: vertices.clone(),
+            vertices: vertices.iter().map(|v| v * 0.5).collect(),
+            texcoords: Vec::new(),
+            tangents: Vec::new(),
+            indices: faces.into_iter().flatten().collect(),
+        };
+        Obj { meshes: vec![mesh] }
+    }
+
+    // Moves every vertex along the line from the center, to `radius(direction)` times
+    // its distance, where `direction` is the unit vector towards the vertex. Normals are
+    // left as they were, so shaders that already tilt the lighting by the terrain's
+    // slope don't count it twice; the new shape shows in the silhouette.
+    pub fn displace(&mut self, radius: impl Fn(&Vec3) -> f32) {
+        for mesh in &mut self.meshes {
+            for vertex in &mut mesh.vertices {
+                if let Some(direction) = vertex.try_normalize(1e-6) {
+                    *vertex *= radius(&direction);
+                }
+            }
+        }
+    }
+
+    // Triangle corners in model space, for ray tests against the mesh
+    pub fn triangles(&self) -> Vec<[Vec3; 3]> {
+        self.meshes
+            .iter()
+            .flat_map(|mesh| {
+                mesh.indices.chunks_exact(3).map(|face| {
+                    [
+                        mesh.vertices[face[0] as usize],
+                        mesh.vertices[face[1] as usize],
+                        mesh.vertices[face[2] as usize],
+                    ]
+                })
+            })
+            .collect()
+    }
+
     pub fn get_vertex_array(&self) -> Vec<Vertex> {
         let mut vertices = Vec::new();
 
@@ -52,11 +170,70 @@ impl Obj {
                 let tex_coords = mesh.texcoords.get(index as usize)
                     .cloned()
                     .unwrap_or(Vec2::new(0.0, 0.0));
+                let tangent = mesh.tangents.get(index as usize)
+                    .cloned()
+                    .unwrap_or(Vec4::zeros());
 
-                vertices.push(Vertex::new(position, normal, tex_coords));
+                vertices.push(Vertex::new(position, normal, tex_coords).with_tangent(tangent));
             }
         }
 
         vertices
     }
 }
+
+impl Mesh {
+    // Per-vertex tangent frames that follow the texture, in the spirit of MikkTSpace:
+    // every face adds the directions in which u and v grow across it to its corners,
+    // weighted by the corner's angle, and each vertex's sum is then made perpendicular
+    // to its normal. Vertices split along UV seams get their own frame on each side.
+    fn compute_tangents(&self) -> Vec<Vec4> {
+        let count = self.vertices.len();
+        if self.texcoords.len() != count || self.normals.len() != count {
+            return Vec::new();
+        }
+
+        let mut tangents = vec![Vec3::zeros(); count];
+        let mut bitangents = vec![Vec3::zeros(); count];
+        for face in self.indices.chunks_exact(3) {
+            let corners = [face[0] as usize, face[1] as usize, face[2] as usize];
+            let [p0, p1, p2] = corners.map(|i| self.vertices[i]);
+            let [t0, t1, t2] = corners.map(|i| self.texcoords[i]);
+
+            let (edge1, edge2) = (p1 - p0, p2 - p0);
+            let (duv1, duv2) = (t1 - t0, t2 - t0);
+            let determinant = duv1.x * duv2.y - duv2.x * duv1.y;
+            if determinant.abs() < 1e-12 {
+                continue;
+            }
+
+            let tangent = (edge1 * duv2.y - edge2 * duv1.y) / determinant;
+            let bitangent = (edge2 * duv1.x - edge1 * duv2.x) / determinant;
+            let (Some(tangent), Some(bitangent)) = (tangent.try_normalize(1e-12), bitangent.try_normalize(1e-12)) else {
+                continue;
+            };
+
+            for (k, &i) in corners.iter().enumerate() {
+                let (a, b) = (self.vertices[corners[(k + 1) % 3]] - self.vertices[i], self.vertices[corners[(k + 2) % 3]] - self.vertices[i]);
+                let angle = a.angle(&b);
+                tangents[i] += tangent * angle;
+                bitangents[i] += bitangent * angle;
+            }
+        }
+
+        (0..count)
+            .map(|i| {
+                let normal = self.normals[i].normalize();
+                let tangent = tangents[i] - normal * normal.dot(&tangents[i]);
+                let tangent = tangent.try_normalize(1e-6).unwrap_or_else(|| any_perpendicular(&normal));
+                let sign = if normal.cross(&tangent).dot(&bitangents[i]) < 0.0 { -1.0 } else { 1.0 };
+                Vec4::new(tangent.x, tangent.y, tangent.z, sign)
+            })
+            .collect()
+    }
+}
+
+fn any_perpendicular(normal: &Vec3) -> Vec3 {
+    let axis = if normal.y.abs() < 0.999 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(0.0, 0.0, 1.0) };
+    axis.cross(normal).normalize()
+}
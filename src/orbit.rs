@@ -0,0 +1,47 @@
+// orbit.rs
+use nalgebra_glm::Vec3;
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+use crate::line::{LineStyle, draw_line_3d};
+use crate::Uniforms;
+
+// Polyline following a body's orbit, in world coordinates
+pub struct OrbitPath {
+    pub points: Vec<Vec3>,
+    pub color: Color,
+}
+
+// Orbit segments fade from full opacity at `near` to `min_alpha` at `far`,
+// measured as the distance from the camera
+#[derive(Clone, Copy)]
+pub struct OrbitFade {
+    pub near: f32,
+    pub far: f32,
+    pub min_alpha: f32,
+}
+
+impl Default for OrbitFade {
+    fn default() -> Self {
+        OrbitFade {
+            near: 20.0,
+            far: 150.0,
+            min_alpha: 0.15,
+        }
+    }
+}
+
+impl OrbitFade {
+    pub fn alpha(&self, distance: f32) -> f32 {
+        let t = ((distance - self.near) / (self.far - self.near)).clamp(0.0, 1.0);
+        1.0 - t * (1.0 - self.min_alpha)
+    }
+}
+
+pub fn render_orbit(framebuffer: &mut Framebuffer, uniforms: &Uniforms, path: &OrbitPath, camera_position: Vec3, fade: &OrbitFade) {
+    for segment in path.points.windows(2) {
+        let midpoint = (segment[0] + segment[1]) * 0.5;
+        let style = LineStyle::new(path.color.into()).with_alpha(fade.alpha((midpoint - camera_position).magnitude()));
+
+        draw_line_3d(framebuffer, uniforms, segment[0], segment[1], &style);
+    }
+}
@@ -0,0 +1,375 @@
+// particle.rs
+use nalgebra_glm::Vec3;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use crate::color::{Color, LinearColor};
+use crate::ephemeris::{SolarSystem, DisplayScale};
+use crate::framebuffer::Framebuffer;
+use crate::line::project_point;
+use crate::Uniforms;
+
+// Pull at a body's display surface in scene units per frame^2, per unit of display
+// radius, so particles fall towards planets in proportion to how big they look
+const SURFACE_GRAVITY: f32 = 0.0002;
+
+// Values interpolated linearly between keys, such as at a normalized age in [0, 1]
+pub trait Lerp: Copy {
+    fn lerp(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for LinearColor {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        LinearColor::lerp(self, other, t)
+    }
+}
+
+// Value over a parameter in [0, 1], like a particle's life or a terrain height. Keys are
+// sorted and the ends are held.
+#[derive(Clone, Debug)]
+pub struct Curve<T: Lerp> {
+    keys: Vec<(f32, T)>,
+}
+
+impl<T: Lerp> Curve<T> {
+    // Panics without keys, since there would be no value to sample
+    pub fn new(keys: &[(f32, T)]) -> Self {
+        assert!(!keys.is_empty(), "curve without keys");
+        let mut keys = keys.to_vec();
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Curve { keys }
+    }
+
+    pub fn constant(value: T) -> Self {
+        Curve { keys: vec![(0.0, value)] }
+    }
+
+    // From `start` at birth to `end` at death
+    pub fn linear(start: T, end: T) -> Self {
+        Curve { keys: vec![(0.0, start), (1.0, end)] }
+    }
+
+    pub fn sample(&self, t: f32) -> T {
+        let next = self.keys.partition_point(|key| key.0 <= t);
+        if next == 0 {
+            return self.keys[0].1;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1;
+        }
+
+        let (start, end) = (&self.keys[next - 1], &self.keys[next]);
+        start.1.lerp(&end.1, (t - start.0) / (end.0 - start.0))
+    }
+}
+
+// Points keep `size` as a radius in pixels, billboards as a radius in scene units so
+// they shrink with distance
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParticleShape {
+    Point,
+    Billboard,
+}
+
+// Additive particles brighten where they overlap, like fire and glowing gas.
+// Alpha-blended ones cover what is behind them, like smoke and dust.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    Additive,
+    Alpha,
+}
+
+// How an emitter spawns and draws its particles. Times are in frames and speeds in
+// scene units per frame, both multiplied by the emitter's `scale`.
+#[derive(Clone, Debug)]
+pub struct EmitterSpec {
+    pub rate: f32,       // particles per frame
+    pub burst: usize,    // particles emitted at once on the first update
+    pub lifetime: (u32, u32),
+    pub speed: (f32, f32),
+    pub spread: f32,     // random share of the speed in any direction, 1.0 is roughly 45°
+    pub speed_curve: Curve<f32>,
+    pub color: Curve<LinearColor>,
+    pub size: Curve<f32>,
+    pub alpha: Curve<f32>,
+    pub drag: f32,       // share of the velocity lost every frame
+    pub gravity: f32,    // multiplier of the attractors' pull, 0.0 ignores them
+    pub shape: ParticleShape,
+    pub blend: BlendMode,
+}
+
+// Body pulling particles towards it, in scene units
+#[derive(Clone, Copy, Debug)]
+pub struct Attractor {
+    pub position: Vec3,
+    pub radius: f32,
+    pub strength: f32,
+}
+
+struct Particle {
+    position: Vec3,
+    velocity: Vec3,
+    acceleration: Vec3,
+    age: u32,
+    lifetime: u32,
+    intensity: f32,
+}
+
+// Spawns particles at `position` along `direction`. `acceleration` is a constant push
+// given to every new particle, such as sunlight on dust. Local particles are stored
+// relative to the emitter and move along with it.
+pub struct Emitter {
+    pub spec: EmitterSpec,
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub acceleration: Vec3,
+    pub scale: f32,
+    pub intensity: f32, // scales the rate and the alpha, 0.0 stops emitting
+    pub local: bool,
+    particles: Vec<Particle>,
+    pending: f32,
+    burst_done: bool,
+    rng: StdRng,
+}
+
+// Emitters that live on their own, like explosions, removed once they are done
+#[derive(Default)]
+pub struct ParticleSystem {
+    pub emitters: Vec<Emitter>,
+}
+
+impl Emitter {
+    pub fn new(spec: EmitterSpec, position: Vec3, direction: Vec3, seed: u64) -> Self {
+        Emitter {
+            spec,
+            position,
+            direction,
+            acceleration: Vec3::zeros(),
+            scale: 1.0,
+            intensity: 1.0,
+            local: false,
+            particles: Vec::new(),
+            pending: 0.0,
+            burst_done: false,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn with_local(mut self, local: bool) -> Self {
+        self.local = local;
+        self
+    }
+
+    // A burst that has played out, with no particles left and none to come
+    pub fn is_finished(&self) -> bool {
+        self.burst_done && self.spec.rate <= 0.0 && self.particles.is_empty()
+    }
+
+    // Advances every particle by one frame, then emits this frame's new ones
+    pub fn update(&mut self, attractors: &[Attractor]) {
+        let origin = if self.local { self.position } else { Vec3::zeros() };
+        let spec = &self.spec;
+        self.particles.retain_mut(|particle| {
+            particle.age += 1;
+            let mut acceleration = particle.acceleration;
+            if spec.gravity != 0.0 {
+                acceleration += gravity(attractors, &(origin + particle.position)) * spec.gravity;
+            }
+            particle.velocity = (particle.velocity + acceleration) * (1.0 - spec.drag);
+
+            let t = particle.age as f32 / particle.lifetime as f32;
+            particle.position += particle.velocity * spec.speed_curve.sample(t);
+            particle.age < particle.lifetime
+        });
+
+        let mut count = 0;
+        if !self.burst_done {
+            count += self.spec.burst;
+            self.burst_done = true;
+        }
+        self.pending += self.spec.rate * self.intensity;
+        count += self.pending.floor() as usize;
+        self.pending = self.pending.fract();
+
+        for _ in 0..count {
+            self.emit();
+        }
+    }
+
+    fn emit(&mut self) {
+        let direction = self.direction.try_normalize(1e-6).unwrap_or(Vec3::new(0.0, 1.0, 0.0));
+        let offset = random_in_ball(&mut self.rng) * self.spec.spread;
+
+        let (low, high) = self.spec.speed;
+        let speed = if high > low { self.rng.gen_range(low..high) } else { low };
+        let (shortest, longest) = self.spec.lifetime;
+        let lifetime = if longest > shortest { self.rng.gen_range(shortest..longest) } else { shortest };
+
+        self.particles.push(Particle {
+            position: if self.local { Vec3::zeros() } else { self.position },
+            velocity: (direction + offset) * (speed * self.scale),
+            acceleration: self.acceleration * self.scale,
+            age: 0,
+            lifetime: lifetime.max(1),
+            intensity: self.intensity,
+        });
+    }
+
+    // Soft round splats, depth-tested against the scene and never writing depth, so
+    // draw them after opaque geometry. Alpha-blended particles go back to front.
+    pub fn render(&self, framebuffer: &mut Framebuffer, uniforms: &Uniforms) {
+        let origin = if self.local { self.position } else { Vec3::zeros() };
+        let view = &uniforms.view_matrix;
+        let camera_right = Vec3::new(view[(0, 0)], view[(0, 1)], view[(0, 2)]);
+
+        let mut splats: Vec<(Vec3, f32, LinearColor, f32)> = Vec::with_capacity(self.particles.len());
+        for particle in &self.particles {
+            let position = origin + particle.position;
+            let Some(screen) = project_point(uniforms, position) else {
+                continue;
+            };
+
+            let t = particle.age as f32 / particle.lifetime as f32;
+            let size = self.spec.size.sample(t);
+            let radius = match self.spec.shape {
+                ParticleShape::Point => size,
+                ParticleShape::Billboard => match project_point(uniforms, position + camera_right * size) {
+                    Some(edge) => (edge.x - screen.x).hypot(edge.y - screen.y),
+                    None => continue,
+                },
+            };
+            let alpha = self.spec.alpha.sample(t) * particle.intensity;
+            splats.push((screen, radius.max(0.5), self.spec.color.sample(t), alpha));
+        }
+
+        if self.spec.blend == BlendMode::Alpha {
+            splats.sort_by(|a, b| b.0.z.total_cmp(&a.0.z));
+        }
+
+        for (screen, radius, color, alpha) in splats {
+            splat(framebuffer, &screen, radius, color, alpha, self.spec.blend);
+        }
+    }
+}
+
+impl ParticleSystem {
+    pub fn add(&mut self, emitter: Emitter) {
+        self.emitters.push(emitter);
+    }
+
+    pub fn update(&mut self, attractors: &[Attractor]) {
+        for emitter in &mut self.emitters {
+            emitter.update(attractors);
+        }
+        self.emitters.retain(|emitter| !emitter.is_finished());
+    }
+
+    pub fn render(&self, framebuffer: &mut Framebuffer, uniforms: &Uniforms) {
+        for emitter in &self.emitters {
+            emitter.render(framebuffer, uniforms);
+        }
+    }
+}
+
+// Soft round spot with a Gaussian falloff of `radius` pixels around a screen position,
+// depth-tested at its center's depth and never writing depth
+pub fn splat(framebuffer: &mut Framebuffer, screen: &Vec3, radius: f32, color: LinearColor, alpha: f32, blend: BlendMode) {
+    let reach = (2.0 * radius).ceil() as isize;
+    let (cx, cy) = (screen.x as isize, screen.y as isize);
+    for y in cy - reach..=cy + reach {
+        for x in cx - reach..=cx + reach {
+            let dx = x as f32 + 0.5 - screen.x;
+            let dy = y as f32 + 0.5 - screen.y;
+            let falloff = (-(dx * dx + dy * dy) / (radius * radius)).exp();
+            match blend {
+                BlendMode::Additive => framebuffer.add_point(x, y, screen.z, color * (alpha * falloff)),
+                BlendMode::Alpha => framebuffer.blend_point(x, y, screen.z, color, (alpha * falloff).min(1.0)),
+            }
+        }
+    }
+}
+
+// One attractor per body, at its display position and size
+pub fn body_attractors(system: &SolarSystem, scene_positions: &[Vec3], scale: &DisplayScale) -> Vec<Attractor> {
+    scene_positions
+        .iter()
+        .enumerate()
+        .map(|(index, &position)| {
+            let radius = system.display_radius(index, scale);
+            Attractor { position, radius, strength: SURFACE_GRAVITY * radius * radius * radius }
+        })
+        .collect()
+}
+
+// Uniformly distributed point inside the unit ball
+fn random_in_ball(rng: &mut StdRng) -> Vec3 {
+    loop {
+        let point = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+        if point.magnitude_squared() <= 1.0 {
+            return point;
+        }
+    }
+}
+
+// Inverse-square pull of every attractor, held constant inside the bodies
+fn gravity(attractors: &[Attractor], point: &Vec3) -> Vec3 {
+    attractors
+        .iter()
+        .map(|attractor| {
+            let offset = attractor.position - point;
+            let distance = offset.magnitude().max(attractor.radius);
+            offset * (attractor.strength / (distance * distance * distance))
+        })
+        .fold(Vec3::zeros(), |total, pull| total + pull)
+}
+
+// Fiery burst that cools from white to dark red, for explosions
+pub fn explosion(scale: f32) -> EmitterSpec {
+    EmitterSpec {
+        rate: 0.0,
+        burst: 200,
+        lifetime: (30, 70),
+        speed: (0.05 * scale, 0.15 * scale),
+        spread: 1.5,
+        speed_curve: Curve::constant(1.0),
+        color: Curve::new(&[
+            (0.0, Color::new(255, 250, 220).into()),
+            (0.25, Color::new(255, 160, 40).into()),
+            (1.0, Color::new(120, 20, 10).into()),
+        ]),
+        size: Curve::linear(0.2 * scale, 0.6 * scale),
+        alpha: Curve::linear(0.8, 0.0),
+        drag: 0.06,
+        gravity: 1.0,
+        shape: ParticleShape::Billboard,
+        blend: BlendMode::Additive,
+    }
+}
+
+// Hot blue-white exhaust fading through orange, `scale` being the nozzle's size
+pub fn engine_exhaust(scale: f32) -> EmitterSpec {
+    EmitterSpec {
+        rate: 6.0,
+        burst: 0,
+        lifetime: (10, 20),
+        speed: (0.15 * scale, 0.25 * scale),
+        spread: 0.12,
+        speed_curve: Curve::linear(1.0, 0.4),
+        color: Curve::new(&[
+            (0.0, Color::new(200, 220, 255).into()),
+            (0.3, Color::new(255, 180, 90).into()),
+            (1.0, Color::new(90, 40, 20).into()),
+        ]),
+        size: Curve::linear(0.15 * scale, 0.5 * scale),
+        alpha: Curve::linear(0.7, 0.0),
+        drag: 0.0,
+        gravity: 0.0,
+        shape: ParticleShape::Billboard,
+        blend: BlendMode::Additive,
+    }
+}
@@ -0,0 +1,435 @@
+// planet.rs
+use std::f32::consts::PI;
+use nalgebra_glm::{Vec3, mat4_to_mat3};
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use serde::Deserialize;
+use crate::color::{Color, LinearColor};
+use crate::ephemeris::{SolarSystem, Body, Orbit, OrbitalElements, DAYS_PER_CENTURY, KM_PER_AU, SECONDS_PER_DAY};
+use crate::fragment::Fragment;
+use crate::material::MaterialPaths;
+use crate::noise::Noise;
+use crate::obj::Obj;
+use crate::ramp::ColorRamp;
+use crate::renderer::ShaderType;
+use crate::star::SUN_TEMPERATURE;
+use crate::Uniforms;
+
+// Same ambient term as the rasterizer, so generated planets sit among the others
+const AMBIENT: f32 = 0.03;
+// Spreads the summed noise, which clusters around its middle, over the whole ramp
+const CONTRAST: f32 = 1.6;
+// Step on the unit sphere for the terrain slope, and the height of the tallest peak
+// over the deepest point in planet radii, as the shading sees it
+const SLOPE_STEP: f32 = 0.01;
+const HEIGHT_RANGE: f32 = 0.08;
+
+const GRAVITATIONAL_CONSTANT: f64 = 6.674e-20; // km^3/(kg s^2)
+const SUN_RADIUS_KM: f64 = 695_700.0;
+const SUN_GM: f64 = 132_712_440_018.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Archetype {
+    Rocky,
+    Desert,
+    Ocean,
+    Ice,
+    GasGiant,
+    Lava,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseKind {
+    Fbm,    // rolling hills and continents
+    Ridged, // sharp crests, dunes and cracks
+    Cells,  // round basins, crater-like
+}
+
+// One noise field on the unit sphere, added to the others with its amplitude
+#[derive(Clone, Copy, Debug)]
+pub struct NoiseLayer {
+    pub kind: NoiseKind,
+    pub seed: i32,
+    pub frequency: f32,
+    pub octaves: u32,
+    pub amplitude: f32,
+}
+
+// Height is normalized to [0, 1]. Everything under `sea_level` is a flat liquid;
+// `relief` sets how strongly slopes shade the land. Gas giants set `bands`, the
+// number of latitude bands, and use the layers only to stir them.
+#[derive(Clone, Debug)]
+pub struct Terrain {
+    pub layers: Vec<NoiseLayer>,
+    pub sea_level: f32,
+    pub relief: f32,
+    pub bands: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CloudCover {
+    pub seed: i32,
+    pub coverage: f32, // share of the sky covered
+    pub frequency: f32,
+    pub opacity: f32,
+    pub color: Color,
+    pub drift: f32,    // radians per frame
+}
+
+// Everything that makes a planet look the way it does. The same archetype and seed
+// always give the same planet.
+#[derive(Clone, Debug)]
+pub struct PlanetSpec {
+    pub archetype: Archetype,
+    pub seed: u64,
+    pub ramp: ColorRamp, // color by height
+    pub terrain: Terrain,
+    pub clouds: Option<CloudCover>,
+    pub liquid_glow: f32,   // share of the liquid's color that shines unlit, for lava
+}
+
+// A spec with its noise generators built, shading fragments of the sphere model
+pub struct Planet {
+    pub spec: PlanetSpec,
+    layers: Vec<Noise>,
+    clouds: Option<Noise>,
+}
+
+impl Archetype {
+    // Radius range in km and bulk density in kg/m^3
+    fn size(self) -> ((f64, f64), f64) {
+        match self {
+            Archetype::Rocky => ((1500.0, 6500.0), 5000.0),
+            Archetype::Desert => ((2500.0, 7000.0), 4800.0),
+            Archetype::Ocean => ((4500.0, 9000.0), 5200.0),
+            Archetype::Ice => ((1200.0, 5000.0), 2000.0),
+            Archetype::GasGiant => ((22000.0, 75000.0), 1200.0),
+            Archetype::Lava => ((2000.0, 7000.0), 5500.0),
+        }
+    }
+
+    // Likely archetypes for a planet with the given equilibrium temperature in kelvin
+    fn for_temperature(kelvin: f64, rng: &mut StdRng) -> Self {
+        let choices: &[Archetype] = match kelvin {
+            k if k > 700.0 => &[Archetype::Lava, Archetype::Lava, Archetype::Rocky],
+            k if k > 330.0 => &[Archetype::Desert, Archetype::Rocky, Archetype::Lava],
+            k if k > 230.0 => &[Archetype::Ocean, Archetype::Ocean, Archetype::Desert, Archetype::Rocky],
+            k if k > 140.0 => &[Archetype::Rocky, Archetype::Ice, Archetype::GasGiant],
+            _ => &[Archetype::GasGiant, Archetype::GasGiant, Archetype::Ice],
+        };
+        choices[rng.gen_range(0..choices.len())]
+    }
+}
+
+impl NoiseLayer {
+    fn generator(&self) -> Noise {
+        Noise::new(self.seed).with_frequency(self.frequency).with_octaves(self.octaves)
+    }
+
+    // This layer's field at a point, in [-1, 1]
+    fn sample(&self, noise: &Noise, point: &Vec3) -> f32 {
+        match self.kind {
+            NoiseKind::Fbm => noise.fbm(point),
+            NoiseKind::Ridged => 2.0 * noise.ridged(point) - 1.0,
+            NoiseKind::Cells => 2.0 * noise.worley(point).f1.min(1.0) - 1.0,
+        }
+    }
+}
+
+impl PlanetSpec {
+    pub fn generate(archetype: Archetype, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let layer_seed = rng.gen::<i32>();
+        let layer = |kind: NoiseKind, frequency, octaves, amplitude| {
+            NoiseLayer { kind, seed: layer_seed.wrapping_add(kind as i32), frequency, octaves, amplitude }
+        };
+
+        let (ramp, terrain, clouds, liquid_glow) = match archetype {
+            Archetype::Rocky => {
+                let tint = [0x8A5A44, 0x777777, 0x7A6A50, 0x5E6A72][rng.gen_range(0..4)];
+                let ramp = palette(&mut rng, &[(0.0, 0x3B3530), (0.45, 0x6E645A), (0.7, 0x8C8278), (1.0, 0xC8C0B8)], tint, 0.4);
+                let layers = vec![
+                    layer(NoiseKind::Fbm, rng.gen_range(1.2..2.2), 5, 1.0),
+                    layer(NoiseKind::Cells, rng.gen_range(2.5..4.5), 1, 0.35),
+                ];
+                let clouds = rng.gen_bool(0.2).then(|| cloud_cover(&mut rng, 0.1..0.25, 0xDDD8D0, 0.5));
+                (ramp, Terrain { layers, sea_level: 0.0, relief: rng.gen_range(0.8..1.5), bands: 0.0 }, clouds, 0.0)
+            }
+            Archetype::Desert => {
+                let tint = [0xC08A50, 0xB0603A, 0xD0B080][rng.gen_range(0..3)];
+                let ramp = palette(&mut rng, &[(0.0, 0x8A5A32), (0.4, 0xC08A50), (0.7, 0xD9B07A), (1.0, 0xF0DCB0)], tint, 0.3);
+                let layers = vec![
+                    layer(NoiseKind::Fbm, rng.gen_range(1.0..1.8), 5, 1.0),
+                    layer(NoiseKind::Ridged, rng.gen_range(2.5..4.0), 4, 0.6),
+                ];
+                let clouds = Some(cloud_cover(&mut rng, 0.1..0.25, 0xF0E6D2, 0.5));
+                (ramp, Terrain { layers, sea_level: 0.0, relief: 0.6, bands: 0.0 }, clouds, 0.0)
+            }
+            Archetype::Ocean => {
+                let sea = rng.gen_range(0.5..0.65);
+                let land = [0x4A7A3A, 0x6A8A3A, 0x3A6A4A][rng.gen_range(0..3)];
+                let ramp = palette(&mut rng, &[
+                    (0.0, 0x0A1E50),
+                    (sea - 0.08, 0x1A4A8A),
+                    (sea, 0x3A78B8),
+                    (sea + 0.01, 0xC8B88A),
+                    (sea + 0.08, land),
+                    (0.85, 0x6A5A40),
+                    (1.0, 0xF0F0F0),
+                ], land, 0.0);
+                let layers = vec![
+                    layer(NoiseKind::Fbm, rng.gen_range(0.8..1.4), 6, 1.0),
+                    layer(NoiseKind::Ridged, rng.gen_range(2.0..3.0), 4, 0.3),
+                ];
+                let clouds = Some(cloud_cover(&mut rng, 0.3..0.45, 0xFFFFFF, 0.9));
+                (ramp, Terrain { layers, sea_level: sea, relief: 1.0, bands: 0.0 }, clouds, 0.0)
+            }
+            Archetype::Ice => {
+                let tint = [0xB8D0E0, 0xD0D8E8, 0xA8C8D0][rng.gen_range(0..3)];
+                let ramp = palette(&mut rng, &[(0.0, 0x6A8CA8), (0.5, 0xB8D0E0), (0.8, 0xE4EEF4), (1.0, 0xFFFFFF)], tint, 0.3);
+                let layers = vec![
+                    layer(NoiseKind::Fbm, rng.gen_range(1.0..1.8), 5, 1.0),
+                    layer(NoiseKind::Ridged, rng.gen_range(1.5..3.0), 3, 0.5),
+                ];
+                let clouds = rng.gen_bool(0.3).then(|| cloud_cover(&mut rng, 0.1..0.2, 0xF4F8FF, 0.5));
+                (ramp, Terrain { layers, sea_level: 0.0, relief: 0.5, bands: 0.0 }, clouds, 0.0)
+            }
+            Archetype::GasGiant => {
+                let families: [[u32; 4]; 3] = [
+                    [0xA0602E, 0xC8A070, 0xE8D8B8, 0xF0E8D0],
+                    [0x2A4A9A, 0x4A78C8, 0x8AB8E8, 0xC8E0F0],
+                    [0x8A7A50, 0xB8A878, 0xD8C8A0, 0xE8E0C8],
+                ];
+                let family = families[rng.gen_range(0..families.len())];
+                let stops: Vec<(f32, u32)> = (0..=6)
+                    .map(|step| (step as f32 / 6.0, family[rng.gen_range(0..family.len())]))
+                    .collect();
+                let ramp = palette(&mut rng, &stops, family[1], 0.0);
+                let layers = vec![layer(NoiseKind::Fbm, rng.gen_range(2.0..3.0), 4, 1.0)];
+                (ramp, Terrain { layers, sea_level: 0.0, relief: 0.0, bands: rng.gen_range(4.0..10.0) }, None, 0.0)
+            }
+            Archetype::Lava => {
+                let sea = rng.gen_range(0.25..0.4);
+                let ramp = palette(&mut rng, &[
+                    (0.0, 0xFFE060),
+                    (sea - 0.1, 0xFF7A1A),
+                    (sea, 0xB02A08),
+                    (sea + 0.02, 0x2A1A16),
+                    (0.7, 0x3C302A),
+                    (1.0, 0x5A4A40),
+                ], 0x3C302A, 0.0);
+                let layers = vec![
+                    layer(NoiseKind::Fbm, rng.gen_range(1.2..2.0), 5, 1.0),
+                    layer(NoiseKind::Cells, rng.gen_range(2.0..3.5), 1, 0.4),
+                ];
+                (ramp, Terrain { layers, sea_level: sea, relief: 1.0, bands: 0.0 }, None, 1.0)
+            }
+        };
+
+        PlanetSpec { archetype, seed, ramp, terrain, clouds, liquid_glow }
+    }
+}
+
+impl Planet {
+    pub fn new(spec: PlanetSpec) -> Self {
+        let layers = spec.terrain.layers.iter().map(NoiseLayer::generator).collect();
+        let clouds = spec.clouds.map(|cover| {
+            NoiseLayer { kind: NoiseKind::Fbm, seed: cover.seed, frequency: cover.frequency, octaves: 5, amplitude: 1.0 }.generator()
+        });
+        Planet { spec, layers, clouds }
+    }
+
+    pub fn generate(archetype: Archetype, seed: u64) -> Self {
+        Planet::new(PlanetSpec::generate(archetype, seed))
+    }
+
+    // Terrain height in [0, 1] at a point on the unit sphere
+    pub fn height(&self, point: &Vec3) -> f32 {
+        let terrain = &self.spec.terrain;
+        let (mut total, mut weight) = (0.0, 0.0);
+        for (layer, noise) in terrain.layers.iter().zip(&self.layers) {
+            total += layer.amplitude * layer.sample(noise, point);
+            weight += layer.amplitude;
+        }
+        let value = if weight > 0.0 { total / weight } else { 0.0 };
+
+        if terrain.bands > 0.0 {
+            0.5 + 0.5 * (point.y * terrain.bands * PI + 3.0 * value).sin()
+        } else {
+            (0.5 + 0.5 * CONTRAST * value).clamp(0.0, 1.0)
+        }
+    }
+
+    // Gas giants and featureless bodies stay round
+    pub fn has_relief(&self) -> bool {
+        self.spec.terrain.relief > 0.0 && self.spec.terrain.bands <= 0.0
+    }
+
+    // Distance of the surface from the center in the direction of a point on the unit
+    // sphere, in radii of the undisplaced sphere, with the same relief the shading
+    // draws and any liquid flat at sea level
+    pub fn surface_radius(&self, point: &Vec3) -> f32 {
+        if !self.has_relief() {
+            return 1.0;
+        }
+        let terrain = &self.spec.terrain;
+        let height = self.height(point).max(terrain.sea_level);
+        1.0 + (height - 0.5) * terrain.relief * HEIGHT_RANGE
+    }
+
+    // Sphere subdivided `subdivisions` times with its vertices moved to the terrain, so
+    // mountains stand out on the limb
+    pub fn mesh(&self, subdivisions: u32) -> Obj {
+        let mut sphere = Obj::sphere(subdivisions);
+        sphere.displace(|direction| self.surface_radius(direction));
+        sphere
+    }
+
+    // Color from the ramp, lit by a normal tilted along the terrain slope, with the
+    // liquid flat and optionally glowing, under drifting clouds
+    pub fn shade(&self, fragment: &Fragment, uniforms: &Uniforms) -> LinearColor {
+        let point = fragment.surface_point();
+        let height = self.height(&point);
+        let terrain = &self.spec.terrain;
+        let base = self.spec.ramp.sample(height);
+        let liquid = height < terrain.sea_level;
+
+        let mut normal = fragment.world_normal;
+        if terrain.relief > 0.0 && !liquid {
+            let helper = if point.y.abs() < 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+            let east = point.cross(&helper).normalize();
+            let north = east.cross(&point);
+            let rise_east = self.height(&(point + east * SLOPE_STEP).normalize()) - height;
+            let rise_north = self.height(&(point + north * SLOPE_STEP).normalize()) - height;
+            let slope = (east * rise_east + north * rise_north) * (terrain.relief * HEIGHT_RANGE / SLOPE_STEP);
+            // The slope is measured on the model, so tilt there and carry the result
+            // into the world; bodies are only rotated and uniformly scaled
+            let tilted = fragment.object_normal - slope;
+            normal = (mat4_to_mat3(&uniforms.model_matrix) * tilted).try_normalize(1e-6).unwrap_or(normal);
+        }
+
+        let light = AMBIENT + normal.dot(&Vec3::new(0.0, 0.0, 1.0)).max(0.0);
+        let glow = if liquid { self.spec.liquid_glow } else { 0.0 };
+        let mut color = base * (light * (1.0 - glow)) + base * glow;
+
+        if let (Some(cover), Some(noise)) = (&self.spec.clouds, &self.clouds) {
+            let angle = uniforms.time as f32 * cover.drift;
+            let (sin, cos) = angle.sin_cos();
+            let drifted = Vec3::new(point.x * cos - point.z * sin, point.y, point.x * sin + point.z * cos);
+            let value = noise.fbm(&drifted);
+            let threshold = 1.0 - 2.0 * cover.coverage;
+            let density = smoothstep(threshold - 0.2, threshold + 0.2, value) * cover.opacity;
+            color = color.lerp(&(LinearColor::from(cover.color) * fragment.intensity), density);
+        }
+
+        color
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Ramp from hex stops pulled `amount` towards a tint, with every stop nudged a little
+// so no two planets of an archetype share exactly the same colors
+fn palette(rng: &mut StdRng, stops: &[(f32, u32)], tint: u32, amount: f32) -> ColorRamp {
+    let tint = Color::from_hex(tint);
+    let keys: Vec<(f32, Color)> = stops
+        .iter()
+        .map(|&(at, hex)| {
+            let color = Color::from_hex(hex).lerp(&tint, amount);
+            let jitter = Color::from_hex(0xFFFFFF) * rng.gen_range(0.0..0.06);
+            let color = if rng.gen_bool(0.5) { color + jitter } else { color.blend_subtract(&jitter) };
+            (at, color)
+        })
+        .collect();
+    ColorRamp::new(&keys)
+}
+
+fn cloud_cover(rng: &mut StdRng, coverage: std::ops::Range<f32>, color: u32, opacity: f32) -> CloudCover {
+    CloudCover {
+        seed: rng.gen(),
+        coverage: rng.gen_range(coverage),
+        frequency: rng.gen_range(2.0..4.0),
+        opacity,
+        color: Color::from_hex(color),
+        drift: rng.gen_range(0.001..0.004),
+    }
+}
+
+// A star with four to eight planets on near-circular orbits, each one's archetype
+// chosen by how warm its orbit is. Body `index` looks like `Planet::generate` with
+// seed `seed ^ index`.
+pub fn random_system(seed: u64) -> SolarSystem {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let temperature = rng.gen_range(3800.0..8500.0);
+    let warmth = temperature / SUN_TEMPERATURE as f64;
+    let star_name = format!("HD {}", rng.gen_range(10000..100000));
+    let star_radius_km = SUN_RADIUS_KM * warmth.powf(0.8);
+    let star_gm = SUN_GM * warmth * warmth;
+
+    let mut bodies = vec![Body {
+        name: star_name.clone(),
+        parent: None,
+        orbit: None,
+        radius_km: star_radius_km,
+        gm: star_gm,
+        shader: ShaderType::Lava,
+        rings: false,
+        orbit_color: Color::from_hex(0xFFFFFF),
+        trail_length: 0,
+        comet: false,
+        temperature_k: Some(temperature),
+        archetype: None,
+        maps: MaterialPaths::default(),
+        merged_into: None,
+    }];
+
+    let mu = star_gm * SECONDS_PER_DAY * SECONDS_PER_DAY / KM_PER_AU.powi(3);
+    let mut a = rng.gen_range(0.25..0.45);
+    let count = rng.gen_range(4..=8);
+    for letter in (b'b'..).take(count) {
+        let equilibrium = temperature * (star_radius_km / KM_PER_AU / (2.0 * a)).sqrt();
+        let archetype = Archetype::for_temperature(equilibrium, &mut rng);
+        let ((smallest, largest), density) = archetype.size();
+        let radius_km: f64 = rng.gen_range(smallest..largest);
+        let mass = density * 1e9 * 4.0 / 3.0 * std::f64::consts::PI * radius_km.powi(3);
+
+        let long_node = rng.gen_range(0.0..360.0);
+        let elements = OrbitalElements {
+            a,
+            e: rng.gen_range(0.0..0.08),
+            i: rng.gen_range(0.0..3.0),
+            l: rng.gen_range(0.0..360.0),
+            long_peri: long_node + rng.gen_range(0.0..360.0),
+            long_node,
+        };
+        let mean_motion = (mu / (a * a * a)).sqrt().to_degrees() * DAYS_PER_CENTURY;
+        let rates = OrbitalElements { a: 0.0, e: 0.0, i: 0.0, l: mean_motion, long_peri: 0.0, long_node: 0.0 };
+
+        let index = bodies.len();
+        let look = PlanetSpec::generate(archetype, seed ^ index as u64);
+        bodies.push(Body {
+            name: format!("{} {}", star_name, letter as char),
+            parent: Some(0),
+            orbit: Some(Orbit { elements, rates }),
+            radius_km,
+            gm: GRAVITATIONAL_CONSTANT * mass,
+            shader: ShaderType::MoonShader,
+            rings: archetype == Archetype::GasGiant && rng.gen_bool(0.4),
+            orbit_color: look.ramp.sample(0.7).to_color(),
+            trail_length: 200,
+            comet: false,
+            temperature_k: None,
+            archetype: Some(archetype),
+            maps: MaterialPaths::default(),
+            merged_into: None,
+        });
+
+        a *= rng.gen_range(1.4..2.0);
+    }
+
+    SolarSystem { bodies }
+}
@@ -1,7 +1,11 @@
 use crate::obj::Obj;
+use crate::planet::Planet;
+use crate::material::Material;
+use crate::trail::Trail;
 use nalgebra_glm::Vec3;
+use serde::Deserialize;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Deserialize)]
 pub enum ShaderType {
     RandomColor,
     BlackAndWhite,
@@ -17,7 +21,11 @@ pub enum ShaderType {
 pub struct Object {
     pub model: Obj,         // La geometría del objeto
     pub transform: Transform, // Transformaciones (posición, rotación, escala)
-    pub shader: ShaderType
+    pub shader: ShaderType,
+    pub body: Option<usize>,  // Cuerpo del sistema solar que sigue el objeto
+    pub trail: Option<Trail>, // Posiciones recientes del objeto
+    pub planet: Option<Planet>, // Aspecto procedural, que reemplaza al shader
+    pub material: Material,     // Texturas del objeto; con albedo reemplazan al shader
 }
 
 pub struct Transform {
@@ -1,13 +1,38 @@
-use nalgebra_glm::{Vec3, Vec4, Mat3, mat4_to_mat3, dot, cross};
+use nalgebra_glm::{Vec3, Vec4, Mat3, mat4_to_mat3};
 use crate::vertex::Vertex;
 use crate::Uniforms;
 use crate::fragment::Fragment;
-use crate::color::Color;
+use crate::color::{Color, LinearColor};
 use rand::Rng;
 use rand::SeedableRng;
 use rand::rngs::StdRng;
-use crate::texture::{Texture, with_texture};
-use crate::normal_map::{NormalMap, with_normal_map};
+use crate::noise::Noise;
+use crate::ramp::ColorRamp;
+use once_cell::sync::Lazy;
+
+// Cada shader tiene sus propios generadores de ruido, con semilla y octavas propias
+static RING_NOISE: Lazy<Noise> = Lazy::new(|| Noise::new(101).with_octaves(3));
+// Dos octavas: relieve grande (60 %) y rugosidad cinco veces más fina (40 %)
+static MOON_NOISE: Lazy<Noise> = Lazy::new(|| Noise::new(102).with_frequency(1.5).with_octaves(2).with_lacunarity(5.0).with_gain(2.0 / 3.0));
+static ROCKY_NOISE: Lazy<Noise> = Lazy::new(|| Noise::new(103).with_frequency(1.5).with_octaves(2).with_lacunarity(5.0).with_gain(2.0 / 3.0));
+static GAS_NOISE: Lazy<Noise> = Lazy::new(|| Noise::new(104).with_frequency(1.0).with_octaves(3).with_gain(0.6));
+static SOLAR_NOISE: Lazy<Noise> = Lazy::new(|| Noise::new(105).with_frequency(8.0).with_octaves(3));
+static PATCH_NOISE: Lazy<Noise> = Lazy::new(|| Noise::new(106).with_frequency(0.5).with_octaves(3));
+static SPOT_NOISE: Lazy<Noise> = Lazy::new(|| Noise::new(107).with_frequency(1.0).with_octaves(2));
+static CLOUD_NOISE: Lazy<Noise> = Lazy::new(|| Noise::new(108).with_frequency(1.0).with_octaves(5));
+static CELL_NOISE: Lazy<Noise> = Lazy::new(|| Noise::new(109).with_frequency(3.0));
+static LAVA_NOISE: Lazy<Noise> = Lazy::new(|| Noise::new(110).with_frequency(4.0).with_octaves(4));
+static PANEL_NOISE: Lazy<Noise> = Lazy::new(|| Noise::new(111).with_frequency(0.5));
+static ROCK_NOISE: Lazy<Noise> = Lazy::new(|| Noise::new(112).with_frequency(1.2).with_octaves(3));
+
+// Paletas de assets/palettes, incrustadas al compilar: la del gigante gaseoso (sombra,
+// bandas blanca y dorada y nubes) y los verdes de las células, un tono por célula
+static GAS_PALETTE: Lazy<ColorRamp> = Lazy::new(|| {
+  ColorRamp::from_json(include_str!("../assets/palettes/gas_giant.json")).expect("Invalid gas giant palette")
+});
+static CELL_PALETTE: Lazy<ColorRamp> = Lazy::new(|| {
+  ColorRamp::from_json(include_str!("../assets/palettes/cellular.json")).expect("Invalid cellular palette")
+});
 
 pub fn vertex_shader(vertex: &Vertex, uniforms: &Uniforms) -> Vertex {
   
@@ -18,7 +43,8 @@ pub fn vertex_shader(vertex: &Vertex, uniforms: &Uniforms) -> Vertex {
     vertex.position.z,
     1.0
   );
-  let transformed = uniforms.projection_matrix * uniforms.view_matrix * uniforms.model_matrix * position;
+  let world_position = uniforms.model_matrix * position;
+  let transformed = uniforms.projection_matrix * uniforms.view_matrix * world_position;
 
   // Perform perspective division
   let w = transformed.w;
@@ -38,37 +64,39 @@ pub fn vertex_shader(vertex: &Vertex, uniforms: &Uniforms) -> Vertex {
 
   let transformed_normal = normal_matrix * vertex.normal;
 
+  // The tangent lies on the surface, so it follows the model matrix itself
+  let tangent = model_mat3 * vertex.tangent.xyz();
+  let transformed_tangent = Vec4::new(tangent.x, tangent.y, tangent.z, vertex.tangent.w);
+
   // Create a new Vertex with transformed attributes
   Vertex {
     position: vertex.position,
     normal: vertex.normal,
     tex_coords: vertex.tex_coords,
+    tangent: vertex.tangent,
     color: vertex.color,
     transformed_position: Vec3::new(screen_position.x, screen_position.y, screen_position.z),
     transformed_normal,
+    transformed_tangent,
+    world_position: world_position.xyz(),
   }
 }
 
-pub fn ring_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
+pub fn ring_shader(fragment: &Fragment, uniforms: &Uniforms) -> LinearColor {
   // Colores base para los anillos
   let base_color = Color::new(163, 138, 67);
   let stripe_color = Color::new(166, 109, 51);
 
   // Coordenadas de posición del fragmento
-  let x = fragment.vertex_position.x;
-  let z = fragment.vertex_position.z;
+  let x = fragment.object_position.x;
+  let z = fragment.object_position.z;
 
   // Parámetros para el ruido
-  let zoom = 100.0;
-  let t = uniforms.time as f32 * 0.01; // Movimiento suave para animación
+  let t = uniforms.time as f32 * 0.0001; // Movimiento suave para animación
   let ring_pattern = ((x * 10.0).sin() + (z * 10.0).cos()).abs(); // Patrón basado en seno y coseno
 
   // Variaciones suaves con ruido
-  let noise_variation = uniforms.noise.get_noise_3d(
-      x * zoom,
-      z * zoom,
-      t,
-  );
+  let noise_variation = RING_NOISE.fbm(&Vec3::new(x, z, t));
 
   // Alternar colores según el patrón de bandas
   let color = if (ring_pattern + noise_variation) % 1.0 > 0.5 {
@@ -78,41 +106,23 @@ pub fn ring_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
   };
 
   // Aplicar la intensidad lumínica
-  color * fragment.intensity
+  LinearColor::from(color) * fragment.intensity
 }
 
-pub fn moon_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
+pub fn moon_shader(fragment: &Fragment, _uniforms: &Uniforms) -> LinearColor {
   // Colores base para un planeta rocoso
   let base_color = Color::new(139, 149, 151); 
   let highlight_color = Color::new(120, 180, 140); // Resaltados claros
   let shadow_color = Color::new(139, 149, 151); // Sombras profundas
 
-  // Coordenadas de posición del fragmento
-  let x = fragment.vertex_position.x;
-  let y = fragment.vertex_position.y;
-  let depth = fragment.depth;
+  // Punto de la esfera unitaria bajo el fragmento, fijo sobre la superficie
+  let point = fragment.surface_point();
 
   // Parámetros para el ruido
-  let zoom = 150.0;
   let bumpiness = 0.3;
-  let t = uniforms.time as f32 * 0.005; // Variación temporal mínima
-
-  // Detalles grandes (montañas, valles)
-  let terrain_noise = uniforms.noise.get_noise_3d(
-      x * zoom,
-      y * zoom,
-      depth * zoom,
-  );
-
-  // Detalles pequeños (rugosidad)
-  let fine_detail_noise = uniforms.noise.get_noise_3d(
-      x * zoom * 5.0,
-      y * zoom * 5.0,
-      depth * zoom * 5.0,
-  );
 
-  // Combinar los niveles de ruido
-  let combined_noise = (terrain_noise * 0.6 + fine_detail_noise * 0.4) * bumpiness;
+  // Detalles grandes (montañas, valles) con rugosidad encima
+  let combined_noise = MOON_NOISE.fbm(&point) * bumpiness;
 
   // Selección de colores según el ruido
   let color = if combined_noise > 0.4 {
@@ -124,42 +134,25 @@ pub fn moon_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
   };
 
   // Aplicar la intensidad lumínica
-  color * fragment.intensity
+  LinearColor::from(color) * fragment.intensity
 }
 
 
-pub fn rocky_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
+pub fn rocky_shader(fragment: &Fragment, _uniforms: &Uniforms) -> LinearColor {
   // Colores base para un planeta rocoso
   let base_color = Color::new(139, 69, 19);      // Marrón rojizo (típico de Marte)
   let highlight_color = Color::new(210, 180, 140); // Color claro para resaltar montañas y bordes
   let shadow_color = Color::new(50, 25, 0);       // Sombra para simular cráteres y profundidad
 
-  // Coordenadas de posición del fragmento
-  let x = fragment.vertex_position.x;
-  let y = fragment.vertex_position.y;
-  let depth = fragment.depth;
-
-  // Parámetros para el efecto de ruido
-  let zoom = 150.0;                // Factor de zoom para el ruido, define el tamaño de los cráteres y montañas
-  let bumpiness = 0.3;             // Ajuste de rugosidad para dar más textura a la superficie
-  let t = uniforms.time as f32 * 0.005; // Variación temporal mínima para animar pequeñas partículas en suspensión
-
-  // Primer nivel de ruido para simular detalles grandes de la superficie (montañas, valles)
-  let terrain_noise = uniforms.noise.get_noise_3d(
-      x * zoom, 
-      y * zoom, 
-      depth * zoom
-  );
+  // Punto de la esfera unitaria bajo el fragmento, fijo sobre la superficie
+  let point = fragment.surface_point();
 
-  // Segundo nivel de ruido, de menor escala, para añadir detalles más pequeños (rugosidad de la superficie)
-  let fine_detail_noise = uniforms.noise.get_noise_3d(
-      x * zoom * 5.0, 
-      y * zoom * 5.0, 
-      depth * zoom * 5.0
-  );
+  // Ajuste de rugosidad para dar más textura a la superficie
+  let bumpiness = 0.3;
 
-  // Combinación de ruidos para obtener una superficie rugosa y con variaciones
-  let combined_noise = (terrain_noise * 0.6 + fine_detail_noise * 0.4) * bumpiness;
+  // Detalles grandes de la superficie (montañas, valles) y, cinco veces más finos,
+  // detalles pequeños (rugosidad de la superficie)
+  let combined_noise = ROCKY_NOISE.fbm(&point) * bumpiness;
 
   // Selección de color en función del valor de ruido para crear efecto de montañas y sombras
   let color = if combined_noise > 0.4 {
@@ -171,70 +164,38 @@ pub fn rocky_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
   };
 
   // Ajustar la intensidad para efectos de luz y sombra
-  color * fragment.intensity
+  LinearColor::from(color) * fragment.intensity
 }
 
 
-pub fn gaseous_giant_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
-  // Colores base para la atmósfera de un gigante gaseoso (pueden ajustarse para simular diferentes planetas)
-  let cloud_color = Color::new(232, 220, 77);  
-  let band_color1 = Color::new(255, 255, 255); 
-  let band_color2 = Color::new(255, 215, 0);   
-  let shadow_color = Color::new(245, 212, 122); 
+pub fn gaseous_giant_shader(fragment: &Fragment, uniforms: &Uniforms) -> LinearColor {
+  // Punto de la esfera unitaria bajo el fragmento, fijo sobre la superficie
+  let point = fragment.surface_point();
+  let (x, y, z) = (point.x, point.y, point.z);
 
-  // Coordenadas de posición del fragmento
-  let x = fragment.vertex_position.x;
-  let y = fragment.vertex_position.y;
-  let depth = fragment.depth;
-
-  // Parámetros de desplazamiento y movimiento de las capas de nubes
-  let t = uniforms.time as f32 * 0.02;           // Tiempo para animar el movimiento de nubes
-  let zoom = 200.0;                              // Factor de zoom para el ruido
-  let speed_factor = 0.1;                        // Controla la velocidad de desplazamiento de bandas
-
-  // Primer nivel de ruido para la capa de nubes
-  let noise1 = uniforms.noise.get_noise_3d(
-      x * zoom + t * speed_factor, 
-      y * zoom, 
-      depth * zoom
-  );
+  // Desplazamiento de las capas de nubes con el tiempo
+  let drift = uniforms.time as f32 * 0.00002;
 
-  // Segundo nivel de ruido para más detalle
-  let noise2 = uniforms.noise.get_noise_3d(
-      x * zoom * 0.5 + t * speed_factor * 0.5, 
-      y * zoom * 0.5, 
-      depth * zoom * 0.5
-  );
+  // Capa de nubes con detalle en varias octavas, plegada en torno a cero
+  let combined_noise = GAS_NOISE.fbm(&Vec3::new(x + drift, y, z)).abs() * 1.5;
 
-  // Mezclar los dos niveles de ruido
-  let combined_noise = (noise1 * 0.6 + noise2 * 0.4).abs();
-
-  // Calcular colores basados en el valor del ruido combinado para crear bandas atmosféricas
-  let color = if combined_noise > 0.6 {
-      cloud_color
-  } else if combined_noise > 0.3 {
-      band_color1.lerp(&band_color2, combined_noise)
-  } else {
-      shadow_color
-  };
+  // Bandas atmosféricas según el valor del ruido combinado (la paleta puede ajustarse
+  // para simular diferentes planetas)
+  let color = GAS_PALETTE.sample(combined_noise);
 
   // Ajustar la intensidad para simular efectos de luz y sombra
   color * fragment.intensity
 }
 
 
-pub fn solar_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
+pub fn solar_shader(fragment: &Fragment, uniforms: &Uniforms) -> LinearColor {
   // Colores base para la textura de "estrella" o "sol"
   let core_color = Color::new(255, 69, 0); // Naranja rojizo intenso
   let outer_glow_color = Color::new(255, 179, 0); // Naranja rojizo intenso
   let dark_spot_color = Color::new(255, 200, 0);      // Amarillo brillante
 
-  // Obtener la posición del fragmento
-  let position = Vec3::new(
-      fragment.vertex_position.x,
-      fragment.vertex_position.y,
-      fragment.depth,
-  );
+  // Punto de la esfera unitaria bajo el fragmento, fijo sobre la superficie
+  let position = fragment.surface_point();
 
   // Parámetros de pulsación
   let base_frequency = 0.3;
@@ -244,21 +205,8 @@ pub fn solar_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
   // Efecto de pulsación en el eje z
   let pulsate = (t * base_frequency).sin() * pulsate_amplitude;
 
-  // Zoom para detalles de ruido
-  let zoom = 800.0; // Factor de zoom para controlar el tamaño de las "llamas" y "manchas"
-  let noise_value1 = uniforms.noise.get_noise_3d(
-      position.x * zoom,
-      position.y * zoom,
-      (position.z + pulsate) * zoom,
-  );
-  let noise_value2 = uniforms.noise.get_noise_3d(
-      (position.x + 1200.0) * zoom,
-      (position.y + 1200.0) * zoom,
-      (position.z + 1200.0 + pulsate) * zoom,
-  );
-
-  // Combinación de ruido para suavizar la transición y agregar complejidad
-  let noise_value = (noise_value1 + noise_value2) * 0.5;
+  // Ruido fractal para las "llamas" y "manchas", que late en el eje z
+  let noise_value = SOLAR_NOISE.fbm(&(position + Vec3::new(0.0, 0.0, pulsate)));
 
   // Definir colores basados en el valor de ruido y umbrales para simular actividad solar
   let color = if noise_value > 0.4 {
@@ -269,27 +217,21 @@ pub fn solar_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
       dark_spot_color
   };
 
-  color * fragment.intensity
+  LinearColor::from(color) * fragment.intensity
 }
 
 
-pub fn blue_green_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
+pub fn blue_green_shader(fragment: &Fragment, uniforms: &Uniforms) -> LinearColor {
   // Define los colores base para azul y verde
   let blue_color = Color::new(0, 0, 255);    // Azul
   let green_color = Color::new(0, 255, 0);   // Verde
 
-  // Ajusta estos valores para controlar el tamaño y la repetición de los patrones de color
-  let zoom = 50.0;
-  let ox = 100.0;
-  let oy = 100.0;
-
-  // Coordenadas de ruido
-  let x = fragment.vertex_position.x;
-  let y = fragment.vertex_position.y;
-  let t = uniforms.time as f32 * 0.1;  // Suaviza la variación en el tiempo
+  // Coordenadas de ruido sobre la esfera unitaria
+  let point = fragment.surface_point();
+  let t = uniforms.time as f32 * 0.002;  // Suaviza la variación en el tiempo
 
-  // Calcula un valor de ruido en 2D
-  let noise_value = uniforms.noise.get_noise_2d(x * zoom + ox + t, y * zoom + oy);
+  // Calcula un valor de ruido en 3D
+  let noise_value = PATCH_NOISE.fbm(&Vec3::new(point.x + t, point.y, point.z));
 
   // Umbral para decidir el color, ajustado para obtener una distribución variada
   let color = if noise_value > 0.0 {
@@ -299,27 +241,21 @@ pub fn blue_green_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
   };
 
   // Multiplica por intensidad para aplicar efectos de iluminación
-  color * fragment.intensity
+  LinearColor::from(color) * fragment.intensity
 }
 
-pub fn tri_color_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
+pub fn tri_color_shader(fragment: &Fragment, uniforms: &Uniforms) -> LinearColor {
   // Define los colores para cada capa
   let blue_color = Color::new(255, 0, 255);    
   let green_color = Color::new(100, 205, 50);     
   let cyan_color = Color::new(183, 25, 155);
 
-  // Configura valores para el tamaño y la repetición del patrón
-  let zoom = 50.0;
-  let ox = 100.0;
-  let oy = 100.0;
+  // Coordenadas de ruido sobre la esfera unitaria y variación en el tiempo
+  let point = fragment.surface_point();
+  let t = uniforms.time as f32 * 0.002; // Suaviza la variación temporal del ruido
 
-  // Coordenadas de ruido y variación en el tiempo
-  let x = fragment.vertex_position.x;
-  let y = fragment.vertex_position.y;
-  let t = uniforms.time as f32 * 0.1; // Suaviza la variación temporal del ruido
-
-  // Calcula el valor de ruido en 2D
-  let noise_value = uniforms.noise.get_noise_2d(x * zoom + ox + t, y * zoom + oy);
+  // Calcula el valor de ruido en 3D
+  let noise_value = PATCH_NOISE.fbm(&Vec3::new(point.x + t, point.y, point.z)) * 2.0;
 
   // Define umbrales para decidir el color basado en el valor de ruido
   let color = if noise_value > 0.5 {
@@ -331,11 +267,11 @@ pub fn tri_color_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
   };
 
   // Ajusta el color según la intensidad de iluminación del fragmento
-  color * fragment.intensity
+  LinearColor::from(color) * fragment.intensity
 }
 
 
-pub fn random_color_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
+pub fn random_color_shader(fragment: &Fragment, uniforms: &Uniforms) -> LinearColor {
   let seed = uniforms.time as u64;
 
   let mut rng = StdRng::seed_from_u64(seed);
@@ -346,11 +282,11 @@ pub fn random_color_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
 
   let random_color = Color::new(r, g, b);
 
-  random_color * fragment.intensity
+  LinearColor::from(random_color) * fragment.intensity
 }
 
-pub fn black_and_white(fragment: &Fragment, uniforms: &Uniforms) -> Color {
-  let seed = uniforms.time as f32 * fragment.vertex_position.y * fragment.vertex_position.x;
+pub fn black_and_white(fragment: &Fragment, uniforms: &Uniforms) -> LinearColor {
+  let seed = uniforms.time as f32 * fragment.object_position.y * fragment.object_position.x;
 
   let mut rng = StdRng::seed_from_u64(seed.abs() as u64);
 
@@ -362,20 +298,13 @@ pub fn black_and_white(fragment: &Fragment, uniforms: &Uniforms) -> Color {
     Color::new(255, 255, 255)
   };
 
-  black_or_white * fragment.intensity
+  LinearColor::from(black_or_white) * fragment.intensity
 }
 
-pub fn dalmata_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
-  let zoom = 100.0;
-  let ox = 0.0;
-  let oy = 0.0;
-  let x = fragment.vertex_position.x;
-  let y = fragment.vertex_position.y;
+pub fn dalmata_shader(fragment: &Fragment, _uniforms: &Uniforms) -> LinearColor {
+  let point = fragment.surface_point();
 
-  let noise_value = uniforms.noise.get_noise_2d(
-    (x + ox) * zoom,
-    (y + oy) * zoom,
-  );
+  let noise_value = SPOT_NOISE.fbm(&point);
 
   let spot_threshold = 0.5;
   let spot_color = Color::new(255, 255, 255); // White
@@ -387,21 +316,18 @@ pub fn dalmata_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
     base_color
   };
 
-  noise_color * fragment.intensity
+  LinearColor::from(noise_color) * fragment.intensity
 }
 
-pub fn cloud_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
-  let zoom = 100.0;  // to move our values 
-  let ox = 100.0; // offset x in the noise map
-  let oy = 100.0;
-  let x = fragment.vertex_position.x;
-  let y = fragment.vertex_position.y;
-  let t = uniforms.time as f32 * 0.5;
+pub fn cloud_shader(fragment: &Fragment, uniforms: &Uniforms) -> LinearColor {
+  let point = fragment.surface_point();
+  let t = uniforms.time as f32 * 0.005;
 
-  let noise_value = uniforms.noise.get_noise_2d(x * zoom + ox + t, y * zoom + oy);
+  // Billowing noise gives the clouds rounded edges
+  let noise_value = CLOUD_NOISE.billow(&Vec3::new(point.x + t, point.y, point.z));
 
   // Define cloud threshold and colors
-  let cloud_threshold = 0.5; // Adjust this value to change cloud density
+  let cloud_threshold = 0.0; // Adjust this value to change cloud density
   let cloud_color = Color::new(255, 255, 255); // White for clouds
   let sky_color = Color::new(30, 97, 145); // Sky blue
 
@@ -412,51 +338,32 @@ pub fn cloud_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
     sky_color
   };
 
-  noise_color * fragment.intensity
+  LinearColor::from(noise_color) * fragment.intensity
 }
 
-pub fn cellular_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
-  let zoom = 30.0;  // Zoom factor to adjust the scale of the cell pattern
-  let ox = 50.0;    // Offset x in the noise map
-  let oy = 50.0;    // Offset y in the noise map
-  let x = fragment.vertex_position.x;
-  let y = fragment.vertex_position.y;
-
-  // Use a cellular noise function to create the plant cell pattern
-  let cell_noise_value = uniforms.noise.get_noise_2d(x * zoom + ox, y * zoom + oy).abs();
-
-  // Define different shades of green for the plant cells
-  let cell_color_1 = Color::new(85, 107, 47);   // Dark olive green
-  let cell_color_2 = Color::new(124, 252, 0);   // Light green
-  let cell_color_3 = Color::new(34, 139, 34);   // Forest green
-  let cell_color_4 = Color::new(173, 255, 47);  // Yellow green
-
-  // Use the noise value to assign a different color to each cell
-  let final_color = if cell_noise_value < 0.15 {
-    cell_color_1
-  } else if cell_noise_value < 0.7 {
-    cell_color_2
-  } else if cell_noise_value < 0.75 {
-    cell_color_3
-  } else {
-    cell_color_4
-  };
+pub fn cellular_shader(fragment: &Fragment, _uniforms: &Uniforms) -> LinearColor {
+  let point = fragment.surface_point();
+
+  // Use Worley noise to create the plant cell pattern
+  let cell = CELL_NOISE.worley(&point);
+
+  // Use the cell's value to assign a different shade of green to each cell
+  let final_color = CELL_PALETTE.sample(cell.value);
+
+  // Darken the walls where two cells meet
+  let wall = ((cell.f2 - cell.f1) * 8.0).min(1.0);
 
   // Adjust intensity to simulate lighting effects (optional)
-  final_color * fragment.intensity
+  final_color * (fragment.intensity * (0.4 + 0.6 * wall))
 }
 
-pub fn lava_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
+pub fn lava_shader(fragment: &Fragment, uniforms: &Uniforms) -> LinearColor {
   // Base colors for the lava effect
   let bright_color = Color::new(255, 240, 0); // Bright orange (lava-like)
   let dark_color = Color::new(130, 20, 0);   // Darker red-orange
 
-  // Get fragment position
-  let position = Vec3::new(
-    fragment.vertex_position.x,
-    fragment.vertex_position.y,
-    fragment.depth
-  );
+  // Point on the unit sphere under the fragment, fixed on the surface
+  let position = fragment.surface_point();
 
   // Base frequency and amplitude for the pulsating effect
   let base_frequency = 0.2;
@@ -466,72 +373,38 @@ pub fn lava_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
   // Pulsate on the z-axis to change spot size
   let pulsate = (t * base_frequency).sin() * pulsate_amplitude;
 
-  // Apply noise to coordinates with subtle pulsating on z-axis
-  let zoom = 1000.0; // Constant zoom factor
-  let noise_value1 = uniforms.noise.get_noise_3d(
-    position.x * zoom,
-    position.y * zoom,
-    (position.z + pulsate) * zoom
-  );
-  let noise_value2 = uniforms.noise.get_noise_3d(
-    (position.x + 1000.0) * zoom,
-    (position.y + 1000.0) * zoom,
-    (position.z + 1000.0 + pulsate) * zoom
-  );
-  let noise_value = (noise_value1 + noise_value2) * 0.5;  // Averaging noise for smoother transitions
+  // Warp the coordinates so the flows swirl, with subtle pulsating on z-axis
+  let warped = LAVA_NOISE.warp(&(position + Vec3::new(0.0, 0.0, pulsate)), 0.15);
+  let noise_value = LAVA_NOISE.turbulence(&warped) * 2.0;
 
   // Use lerp for color blending based on noise value
   let color = dark_color.lerp(&bright_color, noise_value);
 
-  color * fragment.intensity
+  LinearColor::from(color) * fragment.intensity
 }
 
-pub fn fragment_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
-  let intensity = calculate_tangent_lighting(fragment);
-  let texture_color = textured_fragment_shader(fragment, uniforms);
-  texture_color * intensity
-}
+pub fn ship_shader(fragment: &Fragment, _uniforms: &Uniforms) -> LinearColor {
+  // Casco metálico con paneles ligeramente distintos
+  let hull_color = Color::new(170, 175, 185);
+  let panel_color = Color::new(120, 125, 135);
 
-pub fn textured_fragment_shader(fragment: &Fragment, _uniforms: &Uniforms) -> Color {
-    let base_color = with_texture(&|texture: &Texture| {
-        texture.sample(fragment.tex_coords.x, fragment.tex_coords.y)
-    });
-    
-    base_color
+  let position = fragment.object_position * 6.0;
+  let panel_noise = PANEL_NOISE.sample(&position.map(f32::floor));
+
+  let color = if panel_noise > 0.3 { panel_color } else { hull_color };
+
+  // Luz ambiental mínima para que la nave se vea en la sombra
+  LinearColor::from(color) * fragment.intensity.max(0.1)
 }
 
-pub fn calculate_tangent_lighting(fragment: &Fragment) -> f32 {
-  // Sample the normal map (comes in tangent space)
-  let tangent_normal = with_normal_map(|normal_map: &NormalMap| {
-      normal_map.sample(fragment.tex_coords.x, fragment.tex_coords.y)
-  });
-  
-  // Normalizar la normal original
-  let normal = fragment.normal.normalize();
-  
-  // Añadir bias correctamente
-  let bias = 0.001; // Ajusta según sea necesario
-  let biased_normal = (normal + normal * bias).normalize();
-  
-  // Calcular los vectores tangentes y bitangentes
-  let tangent = if biased_normal.y.abs() < 0.999 {
-      cross(&Vec3::new(0.0, 1.0, 0.0), &biased_normal).normalize()
-  } else {
-      cross(&Vec3::new(0.0, 0.0, 1.0), &biased_normal).normalize()
-  };
-  let bitangent = cross(&biased_normal, &tangent).normalize();
-  
-  // Crear la matriz TBN para transformar al espacio mundial
-  let tbn = Mat3::new(
-      tangent.x, bitangent.x, biased_normal.x,
-      tangent.y, bitangent.y, biased_normal.y,
-      tangent.z, bitangent.z, biased_normal.z,
-  );
-  
-  // Transformar la normal del espacio tangente al mundial
-  let world_normal = (tbn * tangent_normal).normalize();
-  
-  // Calcular la iluminación con la normal transformada
-  let light_dir = Vec3::new(0.0, 0.0, 1.0);
-  dot(&world_normal, &light_dir).max(0.0)
+pub fn rock_shader(fragment: &Fragment, _uniforms: &Uniforms) -> LinearColor {
+  // Roca gris parda con manchas más oscuras
+  let rock_color = Color::new(125, 115, 105);
+  let dark_color = Color::new(80, 72, 66);
+
+  let spots = ROCK_NOISE.fbm(&fragment.object_position) * 2.0;
+  let color = rock_color.lerp(&dark_color, spots.clamp(0.0, 1.0));
+
+  // Luz ambiental mínima para que las caras en sombra no desaparezcan
+  LinearColor::from(color) * fragment.intensity.max(0.1)
 }
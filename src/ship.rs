@@ -0,0 +1,351 @@
+// ship.rs
+use std::fmt;
+use nalgebra_glm::{self as glm, DVec3, Mat3, Quat, Vec3};
+use crate::ephemeris::{SolarSystem, DisplayScale, KM_PER_AU, SECONDS_PER_DAY, scene_to_ecliptic, ecliptic_to_scene};
+use crate::obj::Obj;
+use crate::conic::{Conic, SegmentEnd, find_transition, body_state};
+use crate::collision::CollisionResponse;
+
+// Largest fraction of the local orbital time scale covered by one integration step
+const STEP_FRACTION: f64 = 0.01;
+const MAX_SUBSTEPS: usize = 2000;
+// Sphere of influence changes handled within a single update
+const MAX_TRANSITIONS: usize = 16;
+
+// Physical characteristics of a ship. Masses in kg, thrust in N, exhaust velocity in m/s,
+// radius of the bounding sphere in m.
+#[derive(Clone, Copy, Debug)]
+pub struct ShipSpec {
+    pub dry_mass: f64,
+    pub fuel_capacity: f64,
+    pub thrust: f64,
+    pub exhaust_velocity: f64,
+    pub rcs_acceleration: f32, // rad/frame^2
+    pub radius: f64,
+}
+
+impl Default for ShipSpec {
+    fn default() -> Self {
+        ShipSpec {
+            dry_mass: 10_000.0,
+            fuel_capacity: 60_000.0,
+            thrust: 300_000.0,
+            exhaust_velocity: 4_400.0,
+            rcs_acceleration: 0.002,
+            radius: 15.0,
+        }
+    }
+}
+
+// Patched conics follow a single body's two-body orbit exactly, at any time warp.
+// N-body integrates the pull of every body, so orbits drift like real ones.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlightModel {
+    PatchedConics,
+    NBody,
+}
+
+impl fmt::Display for FlightModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FlightModel::PatchedConics => write!(f, "patched conics"),
+            FlightModel::NBody => write!(f, "n-body"),
+        }
+    }
+}
+
+// Landed and crashed ships sit at `offset` from the body's center (ecliptic, au) and
+// move with it. A merged ship has been absorbed by the body.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShipStatus {
+    Flying,
+    Landed { body: usize, offset: DVec3 },
+    Crashed { body: usize, offset: DVec3 },
+    Merged { body: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShipCamera {
+    Chase,
+    Cockpit,
+}
+
+// Position and velocity are heliocentric and ecliptic (au, au/day) so the flight model
+// shares units with the ephemeris. Orientation is kept in scene axes for rendering.
+pub struct Ship {
+    pub model: Obj,
+    pub spec: ShipSpec,
+    pub position: DVec3,
+    pub velocity: DVec3,
+    pub orientation: Quat,
+    pub angular_velocity: Vec3, // body axes, rad/frame
+    pub fuel: f64,
+    pub throttle: f32,
+    pub stabilize: bool,
+    pub flight_model: FlightModel,
+    pub status: ShipStatus,
+    pub collision_response: CollisionResponse,
+    pub camera: ShipCamera,
+    pub scale: f32,
+}
+
+impl Ship {
+    // Ship in a circular, prograde orbit around `body`, facing its direction of motion
+    pub fn in_circular_orbit(model: Obj, spec: ShipSpec, system: &SolarSystem, body: usize, altitude_km: f64, jd: f64) -> Self {
+        let body_position = system.heliocentric_positions(jd)[body];
+        let body_velocity = system.heliocentric_velocity(body, jd);
+
+        let radius = (system.bodies[body].radius_km + altitude_km) / KM_PER_AU;
+        let speed = (system.gm_au(body) / radius).sqrt();
+
+        // Orbit in the plane of the body's own motion
+        let radial = if body_velocity.magnitude() > 0.0 {
+            body_velocity.cross(&DVec3::new(0.0, 0.0, 1.0)).normalize()
+        } else {
+            DVec3::new(1.0, 0.0, 0.0)
+        };
+        let prograde = DVec3::new(0.0, 0.0, 1.0).cross(&radial).normalize();
+
+        let mut ship = Ship {
+            model,
+            spec,
+            position: body_position + radial * radius,
+            velocity: body_velocity + prograde * speed,
+            orientation: Quat::identity(),
+            angular_velocity: Vec3::zeros(),
+            fuel: spec.fuel_capacity,
+            throttle: 0.0,
+            stabilize: true,
+            flight_model: FlightModel::PatchedConics,
+            status: ShipStatus::Flying,
+            collision_response: CollisionResponse::default(),
+            camera: ShipCamera::Chase,
+            scale: 0.04,
+        };
+        ship.face(&ecliptic_to_scene(&prograde.normalize()), &Vec3::new(0.0, 1.0, 0.0));
+        ship
+    }
+
+    pub fn mass(&self) -> f64 {
+        self.spec.dry_mass + self.fuel
+    }
+
+    // Delta-v left in the tanks, in m/s
+    pub fn delta_v(&self) -> f64 {
+        self.spec.exhaust_velocity * (self.mass() / self.spec.dry_mass).ln()
+    }
+
+    // Nose of the model points along +Z, top along +Y
+    pub fn forward(&self) -> Vec3 {
+        glm::quat_rotate_vec3(&self.orientation, &Vec3::new(0.0, 0.0, 1.0))
+    }
+
+    pub fn up(&self) -> Vec3 {
+        glm::quat_rotate_vec3(&self.orientation, &Vec3::new(0.0, 1.0, 0.0))
+    }
+
+    pub fn face(&mut self, forward: &Vec3, up: &Vec3) {
+        // Model +X is the ship's left side
+        let forward = forward.normalize();
+        let left = up.cross(&forward).normalize();
+        let up = forward.cross(&left);
+        let rotation = Mat3::from_columns(&[left, up, forward]);
+        self.orientation = glm::quat_normalize(&glm::mat3_to_quat(&rotation));
+    }
+
+    // RCS input for this frame, each axis in [-1, 1]. Positive pitch lowers the nose,
+    // positive yaw turns left and positive roll banks right.
+    pub fn rotate(&mut self, pitch: f32, yaw: f32, roll: f32) {
+        self.angular_velocity += Vec3::new(pitch, yaw, roll) * self.spec.rcs_acceleration;
+    }
+
+    pub fn update_attitude(&mut self) {
+        if self.stabilize {
+            self.angular_velocity *= 0.85;
+        }
+
+        let angle = self.angular_velocity.magnitude();
+        if angle > 1e-6 {
+            let spin = glm::quat_angle_axis(angle, &(self.angular_velocity / angle));
+            self.orientation = glm::quat_normalize(&(self.orientation * spin));
+        }
+    }
+
+    pub fn is_flying(&self) -> bool {
+        self.status == ShipStatus::Flying
+    }
+
+    // Thrust acceleration in au/day^2 at the current throttle, without burning fuel
+    fn thrust_acceleration(&self) -> DVec3 {
+        if self.throttle <= 0.0 || self.fuel <= 0.0 {
+            return DVec3::zeros();
+        }
+        let acceleration = self.spec.thrust * self.throttle as f64 / self.mass(); // m/s^2
+        scene_to_ecliptic(&self.forward()) * (acceleration * SECONDS_PER_DAY * SECONDS_PER_DAY / (1000.0 * KM_PER_AU))
+    }
+
+    // Thrust acceleration in au/day^2 at the current throttle, burning fuel over `dt` days
+    fn burn(&mut self, dt: f64) -> DVec3 {
+        if self.throttle <= 0.0 || self.fuel <= 0.0 {
+            return DVec3::zeros();
+        }
+
+        let acceleration = self.thrust_acceleration();
+        let mass_flow = self.spec.thrust * self.throttle as f64 / self.spec.exhaust_velocity;
+        let burn_time = (dt * SECONDS_PER_DAY).min(self.fuel / mass_flow);
+        self.fuel = (self.fuel - mass_flow * burn_time).max(0.0);
+
+        // Average over the step when the tank runs dry part-way
+        acceleration * (burn_time / (dt * SECONDS_PER_DAY))
+    }
+
+    // Keeps a ship that is no longer flying on its body at Julian day `jd`. Bodies do
+    // not spin, so it simply rides along with the body's center.
+    pub fn hold(&mut self, system: &SolarSystem, jd: f64) {
+        let (body, offset) = match self.status {
+            ShipStatus::Flying => return,
+            ShipStatus::Landed { body, offset } | ShipStatus::Crashed { body, offset } => (body, offset),
+            ShipStatus::Merged { body } => (body, DVec3::zeros()),
+        };
+        let (body_position, body_velocity) = body_state(system, body, jd);
+        self.position = body_position + offset;
+        self.velocity = body_velocity;
+    }
+
+    // A landed ship lifts off once its thrust pointing away from the surface beats
+    // the body's surface gravity
+    fn lifts_off(&self, system: &SolarSystem) -> bool {
+        let ShipStatus::Landed { body, offset } = self.status else {
+            return false;
+        };
+        let gravity = system.gm_au(body) / offset.magnitude_squared();
+        self.thrust_acceleration().dot(&offset.normalize()) > gravity
+    }
+
+    // Advances the flight model by `dt` days starting at Julian day `jd`
+    pub fn update(&mut self, system: &SolarSystem, jd: f64, dt: f64) {
+        self.update_attitude();
+        if self.lifts_off(system) {
+            self.status = ShipStatus::Flying;
+        }
+        if !self.is_flying() {
+            if !matches!(self.status, ShipStatus::Landed { .. }) {
+                self.throttle = 0.0;
+            }
+            self.hold(system, jd + dt);
+            return;
+        }
+
+        match self.flight_model {
+            FlightModel::PatchedConics if self.throttle > 0.0 && self.fuel > 0.0 => self.burn_on_conic(system, jd, dt),
+            FlightModel::PatchedConics => self.coast_on_conic(system, jd, dt),
+            FlightModel::NBody => self.integrate_n_body(system, jd, dt),
+        }
+    }
+
+    // Orbit around the body whose sphere of influence contains the ship
+    pub fn conic(&self, system: &SolarSystem, jd: f64) -> Conic {
+        Conic::from_state(system, &self.position, &self.velocity, jd)
+    }
+
+    // Coasting moves along the conic analytically, switching conics at every
+    // sphere of influence crossed during the step. At an impact the ship stays where
+    // it hit, moved along with the body, for the collision response to deal with.
+    fn coast_on_conic(&mut self, system: &SolarSystem, jd: f64, dt: f64) {
+        let end_jd = jd + dt;
+        let mut conic = self.conic(system, jd);
+        for _ in 0..MAX_TRANSITIONS {
+            let (stop_jd, end) = find_transition(system, &conic, end_jd);
+            (self.position, self.velocity) = conic.heliocentric_state(system, stop_jd);
+            let body = match end {
+                SegmentEnd::Horizon if stop_jd >= end_jd => return,
+                SegmentEnd::Horizon => conic.body,
+                SegmentEnd::Enter(next) => next,
+                SegmentEnd::Impact => {
+                    let (before, _) = body_state(system, conic.body, stop_jd);
+                    let (after, _) = body_state(system, conic.body, end_jd);
+                    self.position += after - before;
+                    return;
+                }
+            };
+            conic = Conic::around(system, body, &self.position, &self.velocity, stop_jd);
+        }
+    }
+
+    // Burns are integrated in the frame of the sphere of influence body, with that
+    // body's gravity only
+    fn burn_on_conic(&mut self, system: &SolarSystem, jd: f64, dt: f64) {
+        let conic = self.conic(system, jd);
+        let gravity = |position: &DVec3| -position * (conic.mu / position.magnitude().powi(3));
+        let time_scale = (conic.position.magnitude().powi(3) / conic.mu).sqrt();
+
+        let substeps = ((dt / (STEP_FRACTION * time_scale)).ceil() as usize).clamp(1, MAX_SUBSTEPS);
+        let step = dt / substeps as f64;
+
+        let (mut position, mut velocity) = (conic.position, conic.velocity);
+        let mut acceleration = gravity(&position);
+        for _ in 0..substeps {
+            let thrust = self.burn(step);
+            velocity += (acceleration + thrust) * (step * 0.5);
+            position += velocity * step;
+            acceleration = gravity(&position);
+            velocity += (acceleration + thrust) * (step * 0.5);
+        }
+
+        let (body_position, body_velocity) = body_state(system, conic.body, jd + dt);
+        self.position = body_position + position;
+        self.velocity = body_velocity + velocity;
+    }
+
+    // Velocity Verlet under the gravity of every body. Steps are sized from the body
+    // whose sphere of influence holds the ship so low orbits stay stable at any time warp.
+    fn integrate_n_body(&mut self, system: &SolarSystem, jd: f64, dt: f64) {
+        let positions = system.heliocentric_positions(jd);
+        let primary = system.soi_body(&self.position, &positions);
+        let distance = (self.position - positions[primary]).magnitude();
+        let time_scale = (distance.powi(3) / system.gm_au(primary)).sqrt();
+
+        let substeps = ((dt / (STEP_FRACTION * time_scale)).ceil() as usize).clamp(1, MAX_SUBSTEPS);
+        let step = dt / substeps as f64;
+
+        // Thrust is held constant over each step
+        let mut acceleration = system.gravity(&self.position, &positions);
+        for i in 0..substeps {
+            let thrust = self.burn(step);
+            self.velocity += (acceleration + thrust) * (step * 0.5);
+            self.position += self.velocity * step;
+
+            let positions = system.heliocentric_positions(jd + step * (i + 1) as f64);
+            acceleration = system.gravity(&self.position, &positions);
+            self.velocity += (acceleration + thrust) * (step * 0.5);
+        }
+    }
+
+    pub fn scene_position(&self, system: &SolarSystem, jd: f64, scale: &DisplayScale) -> Vec3 {
+        let positions = system.heliocentric_positions(jd);
+        let scene_positions = system.scene_positions(jd, scale);
+        system.scene_point(&self.position, &positions, &scene_positions, scale)
+    }
+
+    // Euler angles matching the Rz * Ry * Rx order of the model matrix
+    pub fn euler_rotation(&self) -> Vec3 {
+        let r = glm::quat_to_mat3(&self.orientation);
+        Vec3::new(r[(2, 1)].atan2(r[(2, 2)]), (-r[(2, 0)]).clamp(-1.0, 1.0).asin(), r[(1, 0)].atan2(r[(0, 0)]))
+    }
+
+    // Camera eye, center and up for the current camera mode
+    pub fn camera_view(&self, scene_position: Vec3, chase_distance: f32) -> (Vec3, Vec3, Vec3) {
+        let forward = self.forward();
+        let up = self.up();
+        match self.camera {
+            ShipCamera::Chase => {
+                let eye = scene_position - forward * chase_distance + up * (chase_distance * 0.3);
+                (eye, scene_position + forward * (chase_distance * 0.5), up)
+            }
+            ShipCamera::Cockpit => {
+                let eye = scene_position + forward * (self.scale * 1.3);
+                (eye, eye + forward, up)
+            }
+        }
+    }
+}
@@ -1,73 +1,235 @@
-use nalgebra_glm::{Vec3, Vec4};
+use nalgebra_glm::{DVec3, Mat4, Vec3, Vec4};
 use rand::prelude::*;
+use rand::rngs::StdRng;
+use serde::Deserialize;
+use std::error::Error;
 use std::f32::consts::PI;
+use std::fs::File;
+use std::path::Path;
+use crate::color::LinearColor;
+use crate::ephemeris::ecliptic_to_scene;
+use crate::nebula::Nebula;
+use crate::star::temperature_color;
+use crate::texture::{Texture, TextureSpace, WrapMode};
 use crate::{Framebuffer, Uniforms};
 
+// Faintest stars kept from a catalog, about what the eye sees on a dark night
+pub const MAGNITUDE_LIMIT: f32 = 6.5;
+// Stars of this magnitude are drawn at full brightness; brighter ones grow instead
+const FULL_BRIGHTNESS_MAGNITUDE: f32 = 1.0;
+// Depth of the sky: behind any geometry, so stars only land where nothing was drawn
+pub const SKY_DEPTH: f32 = f32::MAX;
+// Tilt of the Earth's equator to the ecliptic at J2000, in degrees
+const OBLIQUITY: f64 = 23.439_281;
+
+// Names of the six faces of a cubemap folder, in the order +x, -x, +y, -y, +z, -z
+const CUBE_FACES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+const IMAGE_EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+// Stars are directions: they are so far away that moving the camera never shifts them
 pub struct Star {
-    position: Vec3,
+    direction: Vec3,
     brightness: f32,
     size: u8,
+    color: LinearColor,
 }
 
+// One row of a star catalog in the HYG database's CSV format. Only these columns are
+// read; the rest of the file's are ignored.
+#[derive(Debug, Deserialize)]
+struct CatalogRecord {
+    ra: f64, // hours
+    dec: f64, // degrees
+    #[serde(default)]
+    dist: Option<f64>, // parsecs
+    mag: f32,
+    #[serde(default)]
+    ci: Option<f32>, // B-V
+}
+
+// Picture of the sky, looked up by direction: either six cube faces or a single
+// equirectangular panorama (longitude across, latitude down)
+pub enum SkyImage {
+    Cubemap(Box<[Texture; 6]>),
+    Equirectangular(Texture),
+}
+
+// Point stars in front of a procedural nebula and a sky image scaled by `brightness`,
+// either of which can be left out
 pub struct Skybox {
     stars: Vec<Star>,
+    background: Option<SkyImage>,
+    nebula: Option<Nebula>,
+    pub brightness: f32,
+}
+
+impl SkyImage {
+    // A folder is read as a cubemap with faces px, nx, py, ny, pz and nz (.png or
+    // .jpg); any other path as an equirectangular panorama
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        if !Path::new(path).is_dir() {
+            return Ok(SkyImage::Equirectangular(Texture::load(path, TextureSpace::Srgb)?));
+        }
+
+        let mut faces = Vec::with_capacity(6);
+        for face in CUBE_FACES {
+            let file = IMAGE_EXTENSIONS
+                .iter()
+                .map(|extension| Path::new(path).join(format!("{}.{}", face, extension)))
+                .find(|file| file.exists())
+                .ok_or_else(|| format!("cubemap face '{}' not found in {}", face, path))?;
+            let texture = Texture::load(&file.to_string_lossy(), TextureSpace::Srgb)?;
+            faces.push(texture.with_wrap(WrapMode::Clamp));
+        }
+        let faces: [Texture; 6] = faces.try_into().map_err(|_| "cubemap needs six faces")?;
+        Ok(SkyImage::Cubemap(Box::new(faces)))
+    }
+
+    pub fn sample(&self, direction: &Vec3) -> LinearColor {
+        match self {
+            SkyImage::Equirectangular(texture) => {
+                let u = 0.5 + direction.z.atan2(direction.x) / (2.0 * PI);
+                let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+                texture.sample(u, v)
+            }
+            SkyImage::Cubemap(faces) => {
+                // Face along the largest component, with the usual cubemap orientation
+                let (x, y, z) = (direction.x, direction.y, direction.z);
+                let (face, s, t, major) = if x.abs() >= y.abs() && x.abs() >= z.abs() {
+                    if x > 0.0 { (0, -z, -y, x) } else { (1, z, -y, -x) }
+                } else if y.abs() >= z.abs() {
+                    if y > 0.0 { (2, x, z, y) } else { (3, x, -z, -y) }
+                } else if z > 0.0 {
+                    (4, x, -y, z)
+                } else {
+                    (5, -x, -y, -z)
+                };
+                faces[face].sample(0.5 * (s / major + 1.0), 0.5 * (t / major + 1.0))
+            }
+        }
+    }
 }
 
 impl Skybox {
-    pub fn new(star_count: usize) -> Self {
-        let mut rng = rand::thread_rng();
+    // Random stars spread evenly over the sky. The same seed always gives the same sky.
+    pub fn new(star_count: usize, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
         let mut stars = Vec::with_capacity(star_count);
 
         for _ in 0..star_count {
-            // Generate random spherical coordinates
+            // Uniform on the sphere: the height is uniform, not the polar angle
             let theta = rng.gen::<f32>() * 2.0 * PI;  // Azimuth angle
-            let phi = rng.gen::<f32>() * PI;          // Polar angle
-            let radius = 100.0;  // Fixed radius for all stars
-
-            // Convert spherical to Cartesian coordinates
-            let x = radius * phi.sin() * theta.cos();
-            let y = radius * phi.cos();
-            let z = radius * phi.sin() * theta.sin();
+            let y = rng.gen_range(-1.0..1.0f32);
+            let ring = (1.0 - y * y).sqrt();
 
             // Random brightness between 0.0 and 1.0
             let brightness = rng.gen::<f32>();
             let size : u8 = rng.gen_range(1..=3);
 
             stars.push(Star {
-                position: Vec3::new(x, y, z),
+                direction: Vec3::new(ring * theta.cos(), y, ring * theta.sin()),
                 brightness,
                 size,
+                color: LinearColor::rgb(1.0, 1.0, 1.0),
             });
         }
 
-        Skybox { stars }
+        Skybox { stars, background: None, nebula: None, brightness: 1.0 }
     }
 
-    pub fn render(&self, framebuffer: &mut Framebuffer, uniforms: &Uniforms, camera_position: Vec3) {
-        // let mut rng = rand::thread_rng();
+    // The real sky from a star catalog in the HYG database's CSV format, keeping the
+    // stars at least as bright as `magnitude_limit`
+    pub fn from_catalog(path: &str, magnitude_limit: f32) -> Result<Self, Box<dyn Error>> {
+        let records: Vec<CatalogRecord> = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_reader(File::open(path)?)
+            .deserialize()
+            .collect::<Result<_, _>>()?;
 
-        for star in &self.stars {
-            // Calculate star position relative to camera
-            let position = star.position + camera_position;
+        // The catalog starts with the Sun, at distance zero, which the scene draws itself
+        let stars: Vec<Star> = records
+            .iter()
+            .filter(|record| record.mag <= magnitude_limit && record.dist != Some(0.0))
+            .map(Star::from_record)
+            .collect();
+        if stars.is_empty() {
+            return Err(format!("no stars brighter than magnitude {} in {}", magnitude_limit, path).into());
+        }
+
+        Ok(Skybox { stars, background: None, nebula: None, brightness: 1.0 })
+    }
+
+    pub fn with_background(mut self, background: SkyImage) -> Self {
+        self.background = Some(background);
+        self
+    }
+
+    pub fn with_brightness(mut self, brightness: f32) -> Self {
+        self.brightness = brightness;
+        self
+    }
+
+    pub fn with_nebula(mut self, nebula: Nebula) -> Self {
+        self.nebula = Some(nebula);
+        self
+    }
+
+    // Follows the view for the passes that are cached between frames
+    pub fn update(&mut self, uniforms: &Uniforms, width: usize, height: usize) {
+        if let Some(nebula) = &mut self.nebula {
+            nebula.update(uniforms, width, height);
+        }
+    }
 
-            // Project the star position to screen space
-            let pos_vec4 = Vec4::new(position.x, position.y, position.z, 1.0);
-            let projected = uniforms.projection_matrix * uniforms.view_matrix * pos_vec4;
+    // Fills every pixel nothing has been drawn on with the sky image in its direction.
+    // Only the camera's rotation matters: the image is infinitely far away.
+    fn render_background(&self, background: &SkyImage, framebuffer: &mut Framebuffer, uniforms: &Uniforms) {
+        let Some(unproject) = sky_unprojection(uniforms) else {
+            return;
+        };
 
-            // Perform perspective division
+        for y in 0..framebuffer.height {
+            for x in 0..framebuffer.width {
+                if !framebuffer.is_empty(x, y) {
+                    continue;
+                }
+                let point = (x as f32 + 0.5, y as f32 + 0.5);
+                let Some(direction) = sky_direction(&unproject, point, framebuffer.width, framebuffer.height) else {
+                    continue;
+                };
+                let color = background.sample(&direction) * self.brightness;
+                framebuffer.set_pixel(x, y, color.to_hex());
+            }
+        }
+    }
+
+    // Draws the sky behind the opaque geometry, so it must come after it and before
+    // anything blended on top. Stars are points at infinity, projected with w = 0 so
+    // only the view's rotation applies.
+    pub fn render(&self, framebuffer: &mut Framebuffer, uniforms: &Uniforms) {
+        if let Some(background) = &self.background {
+            self.render_background(background, framebuffer, uniforms);
+        }
+        if let Some(nebula) = &self.nebula {
+            nebula.render(framebuffer);
+        }
+
+        for star in &self.stars {
+            let direction = Vec4::new(star.direction.x, star.direction.y, star.direction.z, 0.0);
+            let projected = uniforms.projection_matrix * uniforms.view_matrix * direction;
+
+            // Perform perspective division, skipping stars behind the camera
             if projected.w <= 0.0 { continue; }
             let ndc = projected / projected.w;
 
             // Apply viewport transform
-            let screen_pos = uniforms.viewport_matrix * Vec4::new(ndc.x, ndc.y, ndc.z, 1.0);
-
-            // Check if star is in front of camera and within screen bounds
-            if screen_pos.z < 0.0 { continue; }
+            let screen_pos = uniforms.viewport_matrix * Vec4::new(ndc.x, ndc.y, 0.0, 1.0);
 
-            let x = screen_pos.x as usize;
-            let y = screen_pos.y as usize;
+            let x = screen_pos.x as isize;
+            let y = screen_pos.y as isize;
 
-            if x < framebuffer.width && y < framebuffer.height {
+            if x >= 0 && y >= 0 && (x as usize) < framebuffer.width && (y as usize) < framebuffer.height {
                 // Random chance for star to blink
                 /*
                 let blink_chance = rng.gen::<f32>();
@@ -78,26 +240,24 @@ impl Skybox {
                 };
                 let adjusted_brightness = (star.brightness + blink_amount).clamp(0.0, 1.0);
                 */
-                let intensity = (star.brightness * 255.0) as u8;
-                let color = (intensity as u32) << 16 | (intensity as u32) << 8 | intensity as u32;
-
-                framebuffer.set_current_color(color);
-                // framebuffer.point(x, y, 1000.0);  // depth is high so things render in front
+                // Stars add their light, so they shine through the sky image behind them
+                let color = star.color * star.brightness;
+                let mut plot = |dx: isize, dy: isize| framebuffer.add_point(x + dx, y + dy, SKY_DEPTH, color);
 
                 match star.size {
-                    1 => framebuffer.point(x, y, 1000.0),
+                    1 => plot(0, 0),
                     2 => {
-                        framebuffer.point(x, y, 1000.0);
-                        framebuffer.point(x + 1, y, 1000.0);
-                        framebuffer.point(x, y + 1, 1000.0);
-                        framebuffer.point(x + 1, y + 1, 1000.0);
+                        plot(0, 0);
+                        plot(1, 0);
+                        plot(0, 1);
+                        plot(1, 1);
                     }
                     3 => {
-                        framebuffer.point(x, y, 1000.0);
-                        framebuffer.point(x - 1, y, 1000.0);
-                        framebuffer.point(x + 1, y, 1000.0);
-                        framebuffer.point(x, y - 1, 1000.0);
-                        framebuffer.point(x, y + 1, 1000.0);
+                        plot(0, 0);
+                        plot(-1, 0);
+                        plot(1, 0);
+                        plot(0, -1);
+                        plot(0, 1);
                     }
                     _ => {}
                 }
@@ -105,3 +265,81 @@ impl Skybox {
         }
     }
 }
+
+impl Star {
+    // Catalog star on the celestial sphere. Right ascension and declination are measured
+    // on the Earth's equator, which is tilted to the ecliptic the scene is built on.
+    fn from_record(record: &CatalogRecord) -> Self {
+        let (brightness, size) = magnitude_appearance(record.mag);
+        let color = record.ci.map_or(LinearColor::rgb(1.0, 1.0, 1.0), |ci| temperature_color(color_index_temperature(ci)).into());
+        Star { direction: celestial_direction(record.ra, record.dec), brightness, size, color }
+    }
+}
+
+// Scene direction of a point of the sky given by its right ascension (hours) and
+// declination (degrees). Both are measured on the Earth's equator, which is tilted to
+// the ecliptic the scene is built on.
+pub fn celestial_direction(ra_hours: f64, dec_degrees: f64) -> Vec3 {
+    let (ra, dec) = ((ra_hours * 15.0).to_radians(), dec_degrees.to_radians());
+    let equatorial = DVec3::new(dec.cos() * ra.cos(), dec.cos() * ra.sin(), dec.sin());
+    let (sin, cos) = OBLIQUITY.to_radians().sin_cos();
+    let ecliptic = DVec3::new(
+        equatorial.x,
+        equatorial.y * cos + equatorial.z * sin,
+        -equatorial.y * sin + equatorial.z * cos,
+    );
+    ecliptic_to_scene(&ecliptic)
+}
+
+// Takes screen positions back to view directions through the projection and the
+// view's rotation alone, as for anything infinitely far away
+pub fn sky_unprojection(uniforms: &Uniforms) -> Option<Mat4> {
+    let mut rotation = uniforms.view_matrix;
+    rotation.fixed_view_mut::<3, 1>(0, 3).fill(0.0);
+    (uniforms.projection_matrix * rotation).try_inverse()
+}
+
+// Direction seen through `point`, in pixels, on a screen `width` by `height` pixels
+pub fn sky_direction(unproject: &Mat4, point: (f32, f32), width: usize, height: usize) -> Option<Vec3> {
+    let ndc_x = 2.0 * point.0 / width as f32 - 1.0;
+    let ndc_y = 1.0 - 2.0 * point.1 / height as f32;
+    let far = unproject * Vec4::new(ndc_x, ndc_y, 1.0, 1.0);
+    (far.xyz() / far.w).try_normalize(1e-6)
+}
+
+// Brightness and size in pixels for an apparent magnitude. Every magnitude is 2.512
+// times fainter than the one before; the square root of that flux keeps faint stars
+// visible, and stars brighter than full white grow instead.
+fn magnitude_appearance(magnitude: f32) -> (f32, u8) {
+    let flux = 10f32.powf(-0.4 * (magnitude - FULL_BRIGHTNESS_MAGNITUDE));
+    let size = match magnitude {
+        m if m < 0.5 => 3,
+        m if m < 2.0 => 2,
+        _ => 1,
+    };
+    (flux.sqrt().min(1.0), size)
+}
+
+// Surface temperature in kelvin for a B-V color index, after Ballesteros (2012)
+fn color_index_temperature(color_index: f32) -> f32 {
+    4600.0 * (1.0 / (0.92 * color_index + 1.7) + 1.0 / (0.92 * color_index + 0.62))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_gives_the_same_sky() {
+        let first = Skybox::new(500, 42);
+        let second = Skybox::new(500, 42);
+        assert_eq!(first.stars.len(), 500);
+        for (a, b) in first.stars.iter().zip(&second.stars) {
+            assert_eq!(a.direction, b.direction);
+            assert_eq!((a.brightness, a.size), (b.brightness, b.size));
+        }
+
+        let other = Skybox::new(500, 43);
+        assert!(first.stars.iter().zip(&other.stars).any(|(a, b)| a.direction != b.direction));
+    }
+}
@@ -0,0 +1,300 @@
+// star.rs
+use std::f32::consts::PI;
+use nalgebra_glm::{Vec3, mat4_to_mat3};
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use crate::color::{Color, LinearColor};
+use crate::framebuffer::Framebuffer;
+use crate::line::{project_point, project_to_screen};
+use crate::noise::Noise;
+use crate::particle::{splat, BlendMode};
+use crate::Uniforms;
+
+// Effective temperature of the Sun, in kelvin, which brightness is measured against
+pub const SUN_TEMPERATURE: f32 = 5772.0;
+
+// Prominences that can be in progress at once, each on its own cycle
+const PROMINENCE_SLOTS: u64 = 5;
+// Share of the cycles that actually raise a prominence
+const PROMINENCE_CHANCE: f32 = 0.7;
+const PROMINENCE_SEGMENTS: usize = 40;
+// Hydrogen-alpha red
+const PROMINENCE_COLOR: u32 = 0xFF5A3C;
+
+// Scales the surface so the center of a Sun-like disc reaches full white
+const EXPOSURE: f32 = 1.2;
+
+// Noise coordinates per star radius for the granulation and the corona streamers
+const GRANULATION_SCALE: f32 = 5.0;
+const STREAMER_SCALE: f32 = 2.5;
+
+#[derive(Clone, Copy, Debug)]
+pub struct StarSpec {
+    pub temperature_k: f32,
+    pub limb_darkening: f32,         // linear coefficient, 0.0 gives a flat disc
+    pub corona_size: f32,            // outer edge of the corona, in star radii
+    pub corona_strength: f32,
+    pub prominence_days: (f64, f64), // how long one prominence lasts
+    pub seed: u64,
+}
+
+impl Default for StarSpec {
+    fn default() -> Self {
+        StarSpec {
+            temperature_k: SUN_TEMPERATURE,
+            limb_darkening: 0.6,
+            corona_size: 3.0,
+            corona_strength: 0.35,
+            prominence_days: (2.0, 8.0),
+            seed: 1,
+        }
+    }
+}
+
+// Draws a star without a mesh: the surface as a limb-darkened disc, the corona as a
+// glow facing the camera and prominences as arcs of glowing gas above the limb
+pub struct StarRenderer {
+    pub body: usize,
+    pub spec: StarSpec,
+    pub position: Vec3,
+    pub radius: f32,
+    jd: f64,
+    noise: Noise,
+}
+
+// Star projected on the screen, with the world axes its disc is drawn in
+struct Disc {
+    center: Vec3, // pixels, with depth
+    right: Vec3,
+    up: Vec3,
+    toward: Vec3,     // from the star to the camera
+    inverse: [f32; 4], // pixels to disc coordinates, in star radii
+    pixel_radius: f32,
+}
+
+impl StarRenderer {
+    pub fn new(body: usize, spec: StarSpec) -> Self {
+        StarRenderer {
+            body,
+            spec,
+            position: Vec3::zeros(),
+            radius: 1.0,
+            jd: 0.0,
+            noise: Noise::new(spec.seed as i32),
+        }
+    }
+
+    // Scene position and radius of the star at Julian day `jd`, which drives the prominences
+    pub fn update(&mut self, position: Vec3, radius: f32, jd: f64) {
+        self.position = position;
+        self.radius = radius;
+        self.jd = jd;
+    }
+
+    pub fn color(&self) -> LinearColor {
+        temperature_color(self.spec.temperature_k).into()
+    }
+
+    // Light given off per unit of surface relative to the Sun (Stefan-Boltzmann), kept
+    // within a range that still reads on screen
+    pub fn brightness(&self) -> f32 {
+        (self.spec.temperature_k / SUN_TEMPERATURE).powi(4).clamp(0.25, 2.0)
+    }
+
+    // The surface writes depth and goes with the opaque geometry; the corona and the
+    // prominences add light on top and are hidden by whatever is in front of the star
+    pub fn render(&self, framebuffer: &mut Framebuffer, uniforms: &Uniforms) {
+        let Some(disc) = self.disc(uniforms) else {
+            return;
+        };
+        self.render_surface(framebuffer, uniforms, &disc);
+        self.render_corona(framebuffer, uniforms, &disc);
+        self.render_prominences(framebuffer, uniforms, &disc);
+    }
+
+    fn disc(&self, uniforms: &Uniforms) -> Option<Disc> {
+        let view = &uniforms.view_matrix;
+        let rotation = mat4_to_mat3(view);
+        let eye = -(rotation.transpose() * Vec3::new(view[(0, 3)], view[(1, 3)], view[(2, 3)]));
+        let camera_up = Vec3::new(view[(1, 0)], view[(1, 1)], view[(1, 2)]);
+
+        let toward = (eye - self.position).try_normalize(1e-6)?;
+        let right = camera_up.cross(&toward).try_normalize(1e-6)?;
+        let up = toward.cross(&right);
+
+        let center = project_to_screen(uniforms, self.position)?;
+        let screen_right = project_to_screen(uniforms, self.position + right * self.radius)? - center;
+        let screen_up = project_to_screen(uniforms, self.position + up * self.radius)? - center;
+        let determinant = screen_right.x * screen_up.y - screen_up.x * screen_right.y;
+        if determinant.abs() < 1e-6 {
+            return None;
+        }
+
+        Some(Disc {
+            center,
+            right,
+            up,
+            toward,
+            inverse: [
+                screen_up.y / determinant, -screen_up.x / determinant,
+                -screen_right.y / determinant, screen_right.x / determinant,
+            ],
+            pixel_radius: screen_right.x.hypot(screen_right.y),
+        })
+    }
+
+    // Every pixel of the disc gets the depth of the sphere below it. The limb is darker
+    // and redder, as it shows higher and cooler layers: with I = 1 - u(1 - mu) and I ∝ T^4
+    // the temperature there is T·I^(1/4).
+    fn render_surface(&self, framebuffer: &mut Framebuffer, uniforms: &Uniforms, disc: &Disc) {
+        let time = uniforms.time as f32 * 0.004;
+        let shade = EXPOSURE * self.brightness().sqrt().min(1.0);
+
+        for (x, y, u, v) in disc_pixels(framebuffer, disc, 1.0) {
+            let r2 = u * u + v * v;
+            if r2 >= 1.0 {
+                continue;
+            }
+
+            let mu = (1.0 - r2).sqrt();
+            let direction = disc.right * u + disc.up * v + disc.toward * mu;
+            let Some(screen) = project_to_screen(uniforms, self.position + direction * self.radius) else {
+                continue;
+            };
+
+            let point = direction * GRANULATION_SCALE;
+            let granulation = self.noise.sample(&Vec3::new(point.x, point.y, point.z + time));
+            let intensity = (1.0 - self.spec.limb_darkening * (1.0 - mu)) * (1.0 + 0.08 * granulation);
+            let color = LinearColor::from(temperature_color(self.spec.temperature_k * intensity.max(0.0).powf(0.25)));
+
+            framebuffer.set_current_color((color * (intensity * shade)).to_hex());
+            framebuffer.point(x as usize, y as usize, screen.z);
+        }
+    }
+
+    // Glow falling off with the cube of the distance out to `corona_size` radii. Noise
+    // around the limb stretches the falloff into streamers.
+    fn render_corona(&self, framebuffer: &mut Framebuffer, uniforms: &Uniforms, disc: &Disc) {
+        let size = self.spec.corona_size;
+        if size <= 1.0 {
+            return;
+        }
+
+        let time = uniforms.time as f32 * 0.002;
+        let strength = self.spec.corona_strength * self.brightness();
+        let color = self.color().lerp(&LinearColor::rgb(1.0, 1.0, 1.0), 0.3);
+
+        for (x, y, u, v) in disc_pixels(framebuffer, disc, size) {
+            let distance = u.hypot(v);
+            if !(1.0..size).contains(&distance) {
+                continue;
+            }
+
+            let (sin, cos) = v.atan2(u).sin_cos();
+            let streamer = self.noise.sample(&Vec3::new(cos * STREAMER_SCALE, sin * STREAMER_SCALE, distance * 0.2 + time));
+            let stretched = distance / (1.0 + 0.35 * streamer);
+            let fade = ((size - distance) / (size - 1.0)).powi(2);
+            let glow = strength * fade / (stretched * stretched * stretched);
+
+            framebuffer.add_point(x, y, disc.center.z, color * glow);
+        }
+    }
+
+    // Each slot runs a cycle of twice a prominence's lifetime on the simulation clock. In
+    // the first half of a cycle a loop of gas may rise between two feet on the surface,
+    // brightening and fading again; the second half is quiet.
+    fn render_prominences(&self, framebuffer: &mut Framebuffer, uniforms: &Uniforms, disc: &Disc) {
+        let (shortest, longest) = self.spec.prominence_days;
+        let base = LinearColor::from_hex(PROMINENCE_COLOR).lerp(&self.color(), 0.3);
+        let pixels_per_unit = disc.pixel_radius / self.radius;
+
+        for slot in 0..PROMINENCE_SLOTS {
+            let mut slot_rng = StdRng::seed_from_u64(self.spec.seed.wrapping_mul(PROMINENCE_SLOTS).wrapping_add(slot));
+            let period = 2.0 * if longest > shortest { slot_rng.gen_range(shortest..longest) } else { shortest.max(0.1) };
+            let cycle = (self.jd + slot_rng.gen_range(0.0..period)) / period;
+            let phase = (2.0 * cycle.fract()) as f32;
+            if phase >= 1.0 {
+                continue;
+            }
+
+            let mut rng = StdRng::seed_from_u64(slot_rng.gen::<u64>() ^ cycle.floor() as u64);
+            if rng.gen::<f32>() > PROMINENCE_CHANCE {
+                continue;
+            }
+
+            let foot = random_direction(&mut rng);
+            let axis = foot.cross(&random_direction(&mut rng)).normalize();
+            let span = rng.gen_range(0.25..0.6);
+            let height = rng.gen_range(0.08..0.25) * (3.0 * phase).min(1.0);
+            let thickness = rng.gen_range(0.015..0.03) * self.radius * pixels_per_unit;
+            let color = base * (0.35 * (PI * phase).sin());
+
+            for segment in 0..=PROMINENCE_SEGMENTS {
+                let s = segment as f32 / PROMINENCE_SEGMENTS as f32;
+                let direction = rotate(&foot, &axis, span * s);
+                let lift = 1.0 + height * (PI * s).sin();
+                if let Some(screen) = project_point(uniforms, self.position + direction * (self.radius * lift)) {
+                    splat(framebuffer, &screen, thickness.max(0.75), color, 1.0, BlendMode::Additive);
+                }
+            }
+        }
+    }
+}
+
+// Screen pixels within `extent` star radii of the disc center, with their disc
+// coordinates along the star's right and up axes
+fn disc_pixels(framebuffer: &Framebuffer, disc: &Disc, extent: f32) -> impl Iterator<Item = (isize, isize, f32, f32)> {
+    let reach = disc.pixel_radius * extent;
+    let clamp_x = |value: f32| value.clamp(0.0, framebuffer.width as f32) as isize;
+    let clamp_y = |value: f32| value.clamp(0.0, framebuffer.height as f32) as isize;
+    let (min_x, max_x) = (clamp_x(disc.center.x - reach), clamp_x(disc.center.x + reach + 1.0));
+    let (min_y, max_y) = (clamp_y(disc.center.y - reach), clamp_y(disc.center.y + reach + 1.0));
+    let (center, inverse) = (disc.center, disc.inverse);
+
+    (min_y..max_y).flat_map(move |y| {
+        (min_x..max_x).map(move |x| {
+            let dx = x as f32 + 0.5 - center.x;
+            let dy = y as f32 + 0.5 - center.y;
+            (x, y, inverse[0] * dx + inverse[1] * dy, inverse[2] * dx + inverse[3] * dy)
+        })
+    })
+}
+
+// Blackbody color of a temperature in kelvin, after Tanner Helland's fit to the CIE
+// color matching functions, good from 1000 K to 40000 K
+pub fn temperature_color(kelvin: f32) -> Color {
+    let t = kelvin.clamp(1000.0, 40000.0) / 100.0;
+
+    let red = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_73 * (t - 60.0).powf(-0.133_204_76)
+    };
+    let green = if t <= 66.0 {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_17 * (t - 60.0).powf(-0.075_514_85)
+    };
+    let blue = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+
+    Color::from_float(red / 255.0, green / 255.0, blue / 255.0)
+}
+
+fn random_direction(rng: &mut StdRng) -> Vec3 {
+    let z: f32 = rng.gen_range(-1.0..1.0);
+    let angle = rng.gen_range(0.0..2.0 * PI);
+    let ring = (1.0 - z * z).sqrt();
+    Vec3::new(ring * angle.cos(), ring * angle.sin(), z)
+}
+
+// Rodrigues' rotation of `vector` by `angle` around the unit `axis`
+fn rotate(vector: &Vec3, axis: &Vec3, angle: f32) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    vector * cos + axis.cross(vector) * sin + axis * (axis.dot(vector) * (1.0 - cos))
+}
@@ -1,53 +1,297 @@
 // texture.rs
-use std::sync::Arc;
-use once_cell::sync::OnceCell;
-use crate::color::Color;
+use std::collections::HashMap;
+use nalgebra_glm::Vec2;
+use serde::Deserialize;
+use crate::color::{Color, LinearColor};
 
-static TEXTURE: OnceCell<Arc<Texture>> = OnceCell::new();
+// How the bytes of an image are read. Color images are stored in sRGB and decoded to
+// linear light; data such as normal or specular maps is used as is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextureSpace {
+    Srgb,
+    Linear,
+}
+
+// What happens to texture coordinates outside [0, 1]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Clamp,  // the edge texels stretch outwards
+    Mirror, // every other repetition is flipped, so there are no seams
+}
+
+// Nearest takes the single closest texel of the full image. Bilinear blends the four
+// closest texels of the mip level that best matches the pixel's footprint, and
+// trilinear also blends between the two nearest levels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    #[default]
+    Trilinear,
+}
 
+// One image of the mip chain
 #[derive(Clone, Debug)]
-pub struct Texture {
+struct Level {
     width: u32,
     height: u32,
-    data: Vec<Color>,
+    data: Vec<LinearColor>,
+}
+
+// An image with its mip chain, each level half the size of the one before down to a
+// single texel, averaged in linear light at load
+#[derive(Clone, Debug)]
+pub struct Texture {
+    levels: Vec<Level>,
+    pub wrap: WrapMode,
+    pub filter: Filter,
 }
 
 impl Texture {
-    pub fn new(path: &str) -> Result<Self, image::ImageError> {
+    pub fn load(path: &str, space: TextureSpace) -> Result<Self, image::ImageError> {
         let img = image::open(path)?.to_rgba8();
         let (width, height) = img.dimensions();
         let data = img.pixels()
-            .map(|p| Color::new(p[0], p[1], p[2]))
+            .map(|p| {
+                let alpha = p[3] as f32 / 255.0;
+                match space {
+                    TextureSpace::Srgb => LinearColor::from(Color::new(p[0], p[1], p[2])).with_alpha(alpha),
+                    TextureSpace::Linear => LinearColor::new(p[0] as f32 / 255.0, p[1] as f32 / 255.0, p[2] as f32 / 255.0, alpha),
+                }
+            })
             .collect();
 
-        Ok(Texture {
-            width,
-            height,
-            data,
-        })
+        Ok(Texture::from_pixels(width, height, data))
+    }
+
+    pub fn from_pixels(width: u32, height: u32, data: Vec<LinearColor>) -> Self {
+        let mut levels = vec![Level { width, height, data }];
+        while let Some(next) = levels.last().and_then(Level::downsample) {
+            levels.push(next);
+        }
+        Texture { levels, wrap: WrapMode::default(), filter: Filter::default() }
+    }
+
+    pub fn with_wrap(mut self, wrap: WrapMode) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
     }
 
-    pub fn sample(&self, u: f32, v: f32) -> Color {
-        let u = u.fract().abs();
-        let v = v.fract().abs();
-        
-        let x = (u * (self.width as f32)) as u32;
-        let y = (v * (self.height as f32)) as u32;
-        
-        let index = (y * self.width + x) as usize;
-        self.data[index]
+    pub fn width(&self) -> u32 {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.levels[0].height
+    }
+
+    // Full-size lookup, for when the pixel's footprint is unknown
+    pub fn sample(&self, u: f32, v: f32) -> LinearColor {
+        match self.filter {
+            Filter::Nearest => self.levels[0].nearest(u, v, self.wrap),
+            Filter::Bilinear | Filter::Trilinear => self.levels[0].bilinear(u, v, self.wrap),
+        }
+    }
+
+    // Lookup at `uv` for a pixel whose texture coordinates change by `dx` and `dy` to
+    // the next pixel along x and y
+    pub fn sample_grad(&self, uv: Vec2, dx: Vec2, dy: Vec2) -> LinearColor {
+        let lod = self.level_of_detail(dx, dy);
+        match self.filter {
+            Filter::Nearest => self.levels[0].nearest(uv.x, uv.y, self.wrap),
+            Filter::Bilinear => self.levels[lod.round() as usize].bilinear(uv.x, uv.y, self.wrap),
+            Filter::Trilinear => {
+                let base = lod.floor() as usize;
+                let fine = self.levels[base].bilinear(uv.x, uv.y, self.wrap);
+                match self.levels.get(base + 1) {
+                    Some(coarse) => fine.lerp(&coarse.bilinear(uv.x, uv.y, self.wrap), lod.fract()),
+                    None => fine,
+                }
+            }
+        }
+    }
+
+    // Mip level whose texels are about one pixel across, from the longer side of the
+    // pixel's footprint in texels of the full image
+    pub fn level_of_detail(&self, dx: Vec2, dy: Vec2) -> f32 {
+        let size = Vec2::new(self.width() as f32, self.height() as f32);
+        let footprint = dx.component_mul(&size).magnitude().max(dy.component_mul(&size).magnitude());
+        if !footprint.is_finite() || footprint <= 1.0 {
+            return 0.0;
+        }
+        footprint.log2().min((self.levels.len() - 1) as f32)
     }
 }
 
-pub fn init_texture(path: &str) -> Result<(), image::ImageError> {
-    let texture = Texture::new(path)?;
-    TEXTURE.set(Arc::new(texture))
-        .expect("Texture already initialized");
-    Ok(())
+impl Level {
+    // Next level of the chain, averaging blocks of two by two texels (or two by one
+    // once a side is down to a single texel). On odd sides the last block also takes
+    // the leftover row or column, so no texel is dropped.
+    fn downsample(&self) -> Option<Level> {
+        if self.width == 1 && self.height == 1 {
+            return None;
+        }
+
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let block = |index: u32, size: u32, source: u32| {
+            let end = if index + 1 == size { source } else { 2 * index + 2 };
+            2 * index..end
+        };
+        let mut data = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let (columns, rows) = (block(x, width, self.width), block(y, height, self.height));
+                let count = (columns.len() * rows.len()) as f32;
+                let sum = rows
+                    .flat_map(|row| columns.clone().map(move |column| (column, row)))
+                    .fold(LinearColor::new(0.0, 0.0, 0.0, 0.0), |sum, (column, row)| sum + self.texel(column, row));
+                data.push(LinearColor::new(sum.r / count, sum.g / count, sum.b / count, sum.a / count));
+            }
+        }
+        Some(Level { width, height, data })
+    }
+
+    fn texel(&self, x: u32, y: u32) -> LinearColor {
+        self.data[(y * self.width + x) as usize]
+    }
+
+    fn wrapped(&self, x: i64, y: i64, wrap: WrapMode) -> LinearColor {
+        self.texel(wrap_index(x, self.width, wrap), wrap_index(y, self.height, wrap))
+    }
+
+    fn nearest(&self, u: f32, v: f32, wrap: WrapMode) -> LinearColor {
+        let x = (u * self.width as f32).floor() as i64;
+        let y = (v * self.height as f32).floor() as i64;
+        self.wrapped(x, y, wrap)
+    }
+
+    // Texel centers sit half a texel in from the edges
+    fn bilinear(&self, u: f32, v: f32, wrap: WrapMode) -> LinearColor {
+        let x = u * self.width as f32 - 0.5;
+        let y = v * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.wrapped(x0, y0, wrap).lerp(&self.wrapped(x0 + 1, y0, wrap), fx);
+        let bottom = self.wrapped(x0, y0 + 1, wrap).lerp(&self.wrapped(x0 + 1, y0 + 1, wrap), fx);
+        top.lerp(&bottom, fy)
+    }
+}
+
+fn wrap_index(index: i64, size: u32, wrap: WrapMode) -> u32 {
+    let size = size as i64;
+    let index = match wrap {
+        WrapMode::Repeat => index.rem_euclid(size),
+        WrapMode::Clamp => index.clamp(0, size - 1),
+        WrapMode::Mirror => {
+            let period = index.rem_euclid(2 * size);
+            if period < size { period } else { 2 * size - 1 - period }
+        }
+    };
+    index as u32
+}
+
+// Index of a texture in a `TextureRegistry`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle(usize);
+
+// Every image the scene uses, loaded once per way of sampling it and shared by handle
+// between the objects that use it
+#[derive(Default)]
+pub struct TextureRegistry {
+    textures: Vec<Texture>,
+    loaded: HashMap<(String, TextureSpace, WrapMode, Filter), TextureHandle>,
+}
+
+impl TextureRegistry {
+    pub fn new() -> Self {
+        TextureRegistry::default()
+    }
+
+    // Loads an image, or returns the handle it already has if it was loaded before
+    // with the same settings
+    pub fn load(&mut self, path: &str, space: TextureSpace, wrap: WrapMode, filter: Filter) -> Result<TextureHandle, image::ImageError> {
+        let key = (path.to_string(), space, wrap, filter);
+        if let Some(&handle) = self.loaded.get(&key) {
+            return Ok(handle);
+        }
+
+        let handle = self.insert(Texture::load(path, space)?.with_wrap(wrap).with_filter(filter));
+        self.loaded.insert(key, handle);
+        Ok(handle)
+    }
+
+    pub fn insert(&mut self, texture: Texture) -> TextureHandle {
+        self.textures.push(texture);
+        TextureHandle(self.textures.len() - 1)
+    }
+
+    pub fn get(&self, handle: TextureHandle) -> &Texture {
+        &self.textures[handle.0]
+    }
 }
 
-pub fn with_texture(f: impl FnOnce(&Texture) -> Color) -> Color {
-    let texture = TEXTURE.get()
-        .expect("Texture not initialized");
-    f(texture)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(value: f32) -> LinearColor {
+        LinearColor::new(value, value, value, 1.0)
+    }
+
+    #[test]
+    fn wrap_index_handles_negative_indices() {
+        assert_eq!(wrap_index(-1, 4, WrapMode::Repeat), 3);
+        assert_eq!(wrap_index(-5, 4, WrapMode::Repeat), 3);
+        assert_eq!(wrap_index(9, 4, WrapMode::Repeat), 1);
+        assert_eq!(wrap_index(-3, 4, WrapMode::Clamp), 0);
+        assert_eq!(wrap_index(7, 4, WrapMode::Clamp), 3);
+        assert_eq!(wrap_index(-1, 4, WrapMode::Mirror), 0);
+        assert_eq!(wrap_index(-4, 4, WrapMode::Mirror), 3);
+        assert_eq!(wrap_index(-5, 4, WrapMode::Mirror), 3);
+    }
+
+    #[test]
+    fn mirror_repeats_every_two_sizes() {
+        let forward: Vec<u32> = (0..8).map(|index| wrap_index(index, 4, WrapMode::Mirror)).collect();
+        assert_eq!(forward, [0, 1, 2, 3, 3, 2, 1, 0]);
+        for index in -16..16 {
+            assert_eq!(wrap_index(index, 4, WrapMode::Mirror), wrap_index(index + 8, 4, WrapMode::Mirror));
+        }
+    }
+
+    #[test]
+    fn level_of_detail_follows_the_footprint() {
+        let texture = Texture::from_pixels(64, 64, vec![gray(0.5); 64 * 64]);
+        let texel = 1.0 / 64.0;
+        assert_eq!(texture.level_of_detail(Vec2::new(texel, 0.0), Vec2::new(0.0, texel)), 0.0);
+        assert_eq!(texture.level_of_detail(Vec2::new(0.5 * texel, 0.0), Vec2::zeros()), 0.0);
+        assert!((texture.level_of_detail(Vec2::new(4.0 * texel, 0.0), Vec2::new(0.0, texel)) - 2.0).abs() < 1e-5);
+        assert!((texture.level_of_detail(Vec2::zeros(), Vec2::new(0.0, 8.0 * texel)) - 3.0).abs() < 1e-5);
+        assert_eq!(texture.level_of_detail(Vec2::new(100.0, 0.0), Vec2::zeros()), 6.0);
+        assert_eq!(texture.level_of_detail(Vec2::new(f32::NAN, 0.0), Vec2::zeros()), 0.0);
+    }
+
+    #[test]
+    fn odd_levels_keep_their_last_row_and_column() {
+        let texture = Texture::from_pixels(5, 1, (0..5).map(|value| gray(value as f32)).collect());
+        let level = &texture.levels[1];
+        assert_eq!((level.width, level.height), (2, 1));
+        assert_eq!(level.data[0].r, 0.5);
+        assert_eq!(level.data[1].r, 3.0);
+
+        let texture = Texture::from_pixels(3, 3, (0..9).map(|value| gray(value as f32)).collect());
+        assert_eq!(texture.levels.len(), 2);
+        assert_eq!(texture.levels[1].data[0].r, 4.0);
+    }
 }
@@ -0,0 +1,64 @@
+// trail.rs
+use nalgebra_glm::Vec3;
+use crate::framebuffer::Framebuffer;
+use crate::line::{LineStyle, draw_line_3d};
+use crate::Uniforms;
+
+// Fixed-size ring buffer with the most recent positions of a body
+pub struct Trail {
+    points: Vec<Vec3>,
+    head: usize, // next slot to write
+    capacity: usize,
+}
+
+impl Trail {
+    pub fn new(capacity: usize) -> Self {
+        Trail {
+            points: Vec::with_capacity(capacity),
+            head: 0,
+            capacity: capacity.max(2),
+        }
+    }
+
+    pub fn push(&mut self, position: Vec3) {
+        if self.points.len() < self.capacity {
+            self.points.push(position);
+        } else {
+            self.points[self.head] = position;
+        }
+        self.head = (self.head + 1) % self.capacity;
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Points from oldest to newest
+    pub fn iter(&self) -> impl Iterator<Item = &Vec3> {
+        let split = if self.points.len() < self.capacity { 0 } else { self.head };
+        self.points[split..].iter().chain(self.points[..split].iter())
+    }
+}
+
+// Polyline whose alpha goes from `style.alpha` at the newest point down to zero at
+// the oldest one the buffer can hold
+pub fn render_trail(framebuffer: &mut Framebuffer, uniforms: &Uniforms, trail: &Trail, style: &LineStyle) {
+    let count = trail.len();
+    let capacity = trail.capacity() as f32;
+
+    let mut previous: Option<&Vec3> = None;
+    for (index, point) in trail.iter().enumerate() {
+        if let Some(start) = previous {
+            let age = (count - index) as f32 / capacity;
+            let fade = (1.0 - age).clamp(0.0, 1.0);
+            let segment_style = style.with_alpha(style.alpha * fade * fade);
+
+            draw_line_3d(framebuffer, uniforms, *start, *point, &segment_style);
+        }
+        previous = Some(point);
+    }
+}
@@ -0,0 +1,325 @@
+// transfer.rs
+use std::f64::consts::PI;
+use std::fmt;
+use nalgebra_glm::{DVec3, Vec3};
+use crate::color::Color;
+use crate::ephemeris::{SolarSystem, DisplayScale, KM_PER_AU, SECONDS_PER_DAY, format_date};
+use crate::framebuffer::Framebuffer;
+use crate::kepler::{lambert, propagate, ecliptic_longitude, wrap_angle};
+use crate::line::{LineStyle, draw_polyline_3d, draw_point_3d};
+use crate::ship::Ship;
+use crate::Uniforms;
+
+// Grid searched for the cheapest Lambert transfer: departures over one synodic
+// period, flight times around the Hohmann time
+const DEPARTURE_SAMPLES: usize = 48;
+const FLIGHT_TIME_SAMPLES: usize = 24;
+// Below this difference in mean motion (rad/day) the phase angle never changes,
+// so there is no launch window to wait for
+const MIN_SYNODIC_RATE: f64 = 1e-9;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferKind {
+    Hohmann,
+    Lambert,
+}
+
+impl fmt::Display for TransferKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransferKind::Hohmann => write!(f, "Hohmann"),
+            TransferKind::Lambert => write!(f, "Lambert"),
+        }
+    }
+}
+
+// A transfer arc around `central`, from the ship's primary (`origin`) or from the
+// ship's own orbit when it already orbits `central` (`origin == None`).
+// States are relative to `central`, in au and au/day.
+#[derive(Clone, Debug)]
+pub struct TransferPlan {
+    pub kind: TransferKind,
+    pub central: usize,
+    pub origin: Option<usize>,
+    pub target: usize,
+    pub departure_jd: f64,
+    pub arrival_jd: f64,
+    pub departure_position: DVec3,
+    pub departure_velocity: DVec3,
+    pub target_radius: f64,
+    // m/s, relative to the departure and arrival bodies (hyperbolic excess for bodies)
+    pub departure_delta_v: f64,
+    pub arrival_delta_v: f64,
+}
+
+impl TransferPlan {
+    pub fn flight_days(&self) -> f64 {
+        self.arrival_jd - self.departure_jd
+    }
+
+    pub fn total_delta_v(&self) -> f64 {
+        self.departure_delta_v + self.arrival_delta_v
+    }
+
+    // Position and velocity on the transfer arc at the given Julian day
+    pub fn state_at(&self, system: &SolarSystem, jd: f64) -> (DVec3, DVec3) {
+        propagate(&self.departure_position, &self.departure_velocity, jd - self.departure_jd, system.gm_au(self.central))
+    }
+
+    // Transfer arc in scene coordinates, drawn around the central body's current scene position
+    pub fn trajectory(&self, system: &SolarSystem, central_position: Vec3, scale: &DisplayScale, segments: usize) -> Vec<Vec3> {
+        (0..=segments)
+            .map(|i| {
+                let jd = self.departure_jd + self.flight_days() * i as f64 / segments as f64;
+                let (position, _) = self.state_at(system, jd);
+                central_position + system.offset_from(self.central, &position, scale)
+            })
+            .collect()
+    }
+
+    pub fn summary(&self, system: &SolarSystem) -> String {
+        let from = match self.origin {
+            Some(origin) => system.bodies[origin].name.as_str(),
+            None => "current orbit",
+        };
+        format!(
+            "{} transfer {} -> {}: depart {}, arrive {} ({:.1} days), delta-v {:.2} + {:.2} = {:.2} km/s",
+            self.kind,
+            from,
+            system.bodies[self.target].name,
+            format_date(self.departure_jd),
+            format_date(self.arrival_jd),
+            self.flight_days(),
+            self.departure_delta_v / 1000.0,
+            self.arrival_delta_v / 1000.0,
+            self.total_delta_v() / 1000.0,
+        )
+    }
+}
+
+// Where the transfer starts and ends, with the ship's state captured at planning time
+struct Endpoints<'a> {
+    system: &'a SolarSystem,
+    central: usize,
+    origin: Option<usize>,
+    target: usize,
+    mu: f64,
+    jd: f64,
+    ship_position: DVec3,
+    ship_velocity: DVec3,
+}
+
+impl Endpoints<'_> {
+    fn departure_state(&self, jd: f64) -> (DVec3, DVec3) {
+        match self.origin {
+            Some(origin) => (self.system.relative_position(origin, jd), self.system.relative_velocity(origin, jd)),
+            None => propagate(&self.ship_position, &self.ship_velocity, jd - self.jd, self.mu),
+        }
+    }
+
+    fn target_state(&self, jd: f64) -> (DVec3, DVec3) {
+        (self.system.relative_position(self.target, jd), self.system.relative_velocity(self.target, jd))
+    }
+
+    fn target_radius(&self) -> f64 {
+        self.system.bodies[self.target].orbit
+            .map(|orbit| orbit.elements_at(self.jd).a)
+            .unwrap_or_else(|| self.target_state(self.jd).0.magnitude())
+    }
+
+    // Mean motions of the departure and target orbits, in rad/day
+    fn mean_motions(&self) -> (f64, f64) {
+        let r1 = self.departure_state(self.jd).0.magnitude();
+        let r2 = self.target_radius();
+        ((self.mu / r1.powi(3)).sqrt(), (self.mu / r2.powi(3)).sqrt())
+    }
+
+    fn hohmann_flight_days(&self) -> f64 {
+        let r1 = self.departure_state(self.jd).0.magnitude();
+        let r2 = self.target_radius();
+        PI * ((r1 + r2).powi(3) / (8.0 * self.mu)).sqrt()
+    }
+
+    fn synodic_period(&self) -> f64 {
+        let (n1, n2) = self.mean_motions();
+        2.0 * PI / (n1 - n2).abs().max(MIN_SYNODIC_RATE)
+    }
+}
+
+// Plans a transfer for the ship to `target`, which must orbit the ship's current
+// primary or the same body as that primary
+pub fn plan_transfer(system: &SolarSystem, ship: &Ship, target: usize, jd: f64, kind: TransferKind) -> Result<TransferPlan, String> {
+    let target_name = &system.bodies[target].name;
+    let Some(central) = system.bodies[target].parent else {
+        return Err(format!("{} does not orbit another body", target_name));
+    };
+
+    let positions = system.heliocentric_positions(jd);
+    let primary = system.soi_body(&ship.position, &positions);
+    if primary == target {
+        return Err(format!("The ship is already at {}", target_name));
+    }
+
+    let origin = if primary == central {
+        None
+    } else if system.bodies[primary].parent == Some(central) {
+        Some(primary)
+    } else {
+        return Err(format!(
+            "{} orbits {}, but the ship is around {}",
+            target_name, system.bodies[central].name, system.bodies[primary].name
+        ));
+    };
+
+    let central_velocity = system.heliocentric_velocity(central, jd);
+    let endpoints = Endpoints {
+        system,
+        central,
+        origin,
+        target,
+        mu: system.gm_au(central),
+        jd,
+        ship_position: ship.position - positions[central],
+        ship_velocity: ship.velocity - central_velocity,
+    };
+
+    let plan = match kind {
+        TransferKind::Hohmann => hohmann_transfer(&endpoints),
+        TransferKind::Lambert => lambert_transfer(&endpoints).ok_or_else(|| "no arc converged".to_string()),
+    };
+    plan.map_err(|reason| format!("No {} transfer found to {}: {}", kind, target_name, reason))
+}
+
+// Tangential burn into an ellipse whose apsis touches the target's orbit, leaving
+// when the target leads by the angle it covers during the flight
+fn hohmann_transfer(endpoints: &Endpoints) -> Result<TransferPlan, String> {
+    let mu = endpoints.mu;
+    let (n1, n2) = endpoints.mean_motions();
+    if (n1 - n2).abs() < MIN_SYNODIC_RATE {
+        return Err("no synodic window".to_string());
+    }
+    let required_lead = wrap_angle(PI - n2 * endpoints.hohmann_flight_days());
+    let phase = {
+        let departure = endpoints.departure_state(endpoints.jd).0;
+        let target = endpoints.target_state(endpoints.jd).0;
+        wrap_angle(ecliptic_longitude(&target) - ecliptic_longitude(&departure))
+    };
+
+    // The lead angle shrinks at n1 - n2, which gives the next window for circular
+    // orbits. Then refine it so the real target sits at the apsis on arrival.
+    let mut departure_jd = endpoints.jd + ((phase - required_lead) / (n1 - n2)).rem_euclid(endpoints.synodic_period());
+    let mut r2 = endpoints.target_radius();
+    for _ in 0..8 {
+        let departure = endpoints.departure_state(departure_jd).0;
+        let r1 = departure.magnitude();
+        let flight_days = PI * ((r1 + r2).powi(3) / (8.0 * mu)).sqrt();
+
+        let arrival = endpoints.target_state(departure_jd + flight_days).0;
+        let miss = wrap_angle(ecliptic_longitude(&arrival) - ecliptic_longitude(&departure) - PI);
+        departure_jd += miss / (n1 - n2);
+        r2 = arrival.magnitude();
+    }
+    departure_jd = departure_jd.max(endpoints.jd);
+
+    let (position, velocity) = endpoints.departure_state(departure_jd);
+    let r1 = position.magnitude();
+    let flight_days = PI * ((r1 + r2).powi(3) / (8.0 * mu)).sqrt();
+    let normal = position.cross(&velocity).normalize();
+    let transfer_velocity = normal.cross(&position).normalize() * (mu * (2.0 / r1 - 2.0 / (r1 + r2))).sqrt();
+
+    let arrival_jd = departure_jd + flight_days;
+    let (_, arrival_velocity) = propagate(&position, &transfer_velocity, flight_days, mu);
+    let (_, target_velocity) = endpoints.target_state(arrival_jd);
+
+    Ok(TransferPlan {
+        kind: TransferKind::Hohmann,
+        central: endpoints.central,
+        origin: endpoints.origin,
+        target: endpoints.target,
+        departure_jd,
+        arrival_jd,
+        departure_position: position,
+        departure_velocity: transfer_velocity,
+        target_radius: r2,
+        departure_delta_v: to_meters_per_second(&(transfer_velocity - velocity)),
+        arrival_delta_v: to_meters_per_second(&(target_velocity - arrival_velocity)),
+    })
+}
+
+// Cheapest Lambert arc over one synodic period, on a coarse grid of departure
+// dates and flight times refined once around the best cell
+fn lambert_transfer(endpoints: &Endpoints) -> Option<TransferPlan> {
+    let hohmann_days = endpoints.hohmann_flight_days();
+    let departure_step = endpoints.synodic_period() / DEPARTURE_SAMPLES as f64;
+    let flight_step = hohmann_days / FLIGHT_TIME_SAMPLES as f64;
+
+    let mut best: Option<(f64, TransferPlan)> = None;
+    let search = |departures: Vec<f64>, flight_times: Vec<f64>, best: &mut Option<(f64, TransferPlan)>| {
+        for &departure_jd in &departures {
+            for &flight_days in &flight_times {
+                if let Some(plan) = lambert_arc(endpoints, departure_jd, flight_days) {
+                    if best.as_ref().is_none_or(|(cost, _)| plan.total_delta_v() < *cost) {
+                        *best = Some((plan.total_delta_v(), plan));
+                    }
+                }
+            }
+        }
+    };
+
+    let departures = (0..DEPARTURE_SAMPLES).map(|i| endpoints.jd + departure_step * i as f64).collect();
+    let flight_times = (0..FLIGHT_TIME_SAMPLES).map(|i| hohmann_days * 0.5 + flight_step * i as f64).collect();
+    search(departures, flight_times, &mut best);
+
+    let (_, coarse) = best.clone()?;
+    let departures = (-4..=4)
+        .map(|i| coarse.departure_jd + departure_step * i as f64 / 4.0)
+        .filter(|jd| *jd >= endpoints.jd)
+        .collect();
+    let flight_times = (-4..=4)
+        .map(|i| coarse.flight_days() + flight_step * i as f64 / 4.0)
+        .filter(|days| *days > 0.0)
+        .collect();
+    search(departures, flight_times, &mut best);
+
+    best.map(|(_, plan)| plan)
+}
+
+fn lambert_arc(endpoints: &Endpoints, departure_jd: f64, flight_days: f64) -> Option<TransferPlan> {
+    let arrival_jd = departure_jd + flight_days;
+    let (position, velocity) = endpoints.departure_state(departure_jd);
+    let (target_position, target_velocity) = endpoints.target_state(arrival_jd);
+    let (departure_velocity, arrival_velocity) = lambert(&position, &target_position, flight_days, endpoints.mu)?;
+
+    Some(TransferPlan {
+        kind: TransferKind::Lambert,
+        central: endpoints.central,
+        origin: endpoints.origin,
+        target: endpoints.target,
+        departure_jd,
+        arrival_jd,
+        departure_position: position,
+        departure_velocity,
+        target_radius: target_position.magnitude(),
+        departure_delta_v: to_meters_per_second(&(departure_velocity - velocity)),
+        arrival_delta_v: to_meters_per_second(&(target_velocity - arrival_velocity)),
+    })
+}
+
+pub fn to_meters_per_second(velocity: &DVec3) -> f64 {
+    velocity.magnitude() * KM_PER_AU * 1000.0 / SECONDS_PER_DAY
+}
+
+// Predicted arc plus markers where the ship leaves and where the target will be on arrival
+pub fn render_transfer(framebuffer: &mut Framebuffer, uniforms: &Uniforms, system: &SolarSystem, plan: &TransferPlan, scene_positions: &[Vec3], scale: &DisplayScale) {
+    let central_position = scene_positions[plan.central];
+    let path = plan.trajectory(system, central_position, scale, 256);
+    let style = LineStyle::new(Color::new(80, 220, 255).into()).with_width(2.0);
+    draw_polyline_3d(framebuffer, uniforms, &path, &style);
+
+    let marker = style.with_width(7.0);
+    let arrival = central_position + system.offset_from(plan.central, &system.relative_position(plan.target, plan.arrival_jd), scale);
+    if let Some(departure) = path.first() {
+        draw_point_3d(framebuffer, uniforms, *departure, &marker);
+    }
+    draw_point_3d(framebuffer, uniforms, arrival, &marker.with_width(9.0));
+}
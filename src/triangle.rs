@@ -3,17 +3,25 @@ use crate::fragment::Fragment;
 use crate::vertex::Vertex;
 use crate::color::Color;
 
-pub fn triangle(v1: &Vertex, v2: &Vertex, v3: &Vertex) -> Vec<Fragment> {
+pub fn triangle(v1: &Vertex, v2: &Vertex, v3: &Vertex, width: usize, height: usize) -> Vec<Fragment> {
   let mut fragments = Vec::new();
   let (a, b, c) = (v1.transformed_position, v2.transformed_position, v3.transformed_position);
   let (t1, t2, t3) = (v1.tex_coords, v2.tex_coords, v3.tex_coords);
 
   let (min_x, min_y, max_x, max_y) = calculate_bounding_box(&a, &b, &c);
 
+  // Solo los pixeles dentro de la pantalla
+  let (min_x, min_y) = (min_x.max(0), min_y.max(0));
+  let (max_x, max_y) = (max_x.min(width as i32 - 1), max_y.min(height as i32 - 1));
+
   let light_dir = Vec3::new(0.0, 0.0, 1.0);
 
   let triangle_area = edge_function(&a, &b, &c);
 
+  // Las coordenadas de textura varían linealmente sobre la pantalla, así que sus
+  // derivadas son las mismas en todo el triángulo
+  let (tex_dx, tex_dy) = texture_derivatives(&[a, b, c], &[t1, t2, t3], triangle_area);
+
   // Iterate over each pixel in the bounding box
   for y in min_y..=max_y {
     for x in min_x..=max_x {
@@ -32,7 +40,7 @@ pub fn triangle(v1: &Vertex, v2: &Vertex, v3: &Vertex) -> Vec<Fragment> {
         let normal = normal.normalize();
 
         // Calculate lighting intensity
-        let ambient_intensity = 0.2; // Ajusta según el efecto deseado (0.0 a 1.0)
+        let ambient_intensity = 0.03; // En luz lineal, alrededor de 0.2 una vez codificado en sRGB
         let diffuse_intensity = dot(&normal, &light_dir).max(0.0);
         let intensity = ambient_intensity + diffuse_intensity;
 
@@ -43,22 +51,32 @@ pub fn triangle(v1: &Vertex, v2: &Vertex, v3: &Vertex) -> Vec<Fragment> {
         // Interpolate depth
         let depth = a.z * w1 + b.z * w2 + c.z * w3;
 
-        // Positions of the original vertex
-        let vertex_position = v1.position * w1 + v2.position * w2 + v3.position * w3;
+        // Position and normal of the original vertex, fixed on the model
+        let object_position = v1.position * w1 + v2.position * w2 + v3.position * w3;
+        let object_normal = (v1.normal * w1 + v2.normal * w2 + v3.normal * w3).normalize();
+
+        let world_position = v1.world_position * w1 + v2.world_position * w2 + v3.world_position * w3;
+        let tangent = v1.transformed_tangent * w1 + v2.transformed_tangent * w2 + v3.transformed_tangent * w3;
 
         // Interpolate texture coordinates
         let tex_u = t1.x * w1 + t2.x * w2 + t3.x * w3;
         let tex_v = t1.y * w1 + t2.y * w2 + t3.y * w3;
 
-        fragments.push(Fragment::new(
-            Vec2::new(x as f32, y as f32),
+        fragments.push(Fragment {
+            position: Vec2::new(x as f32, y as f32),
             color,
             depth,
-            normal,
             intensity,
-            vertex_position,
-            Vec2::new(tex_u, tex_v),
-        ));
+            object_position,
+            object_normal,
+            world_position,
+            world_normal: normal,
+            world_tangent: tangent.xyz(),
+            bitangent_sign: if tangent.w < 0.0 { -1.0 } else { 1.0 },
+            tex_coords: Vec2::new(tex_u, tex_v),
+            tex_dx,
+            tex_dy,
+        });
       }
     }
   }
@@ -83,6 +101,21 @@ fn barycentric_coordinates(p: &Vec3, a: &Vec3, b: &Vec3, c: &Vec3, area: f32) ->
     (w1, w2, w3)
 }
 
+// Gradient of each barycentric weight over the screen, from the edge opposite its vertex
+fn texture_derivatives(screen: &[Vec3; 3], tex: &[Vec2; 3], area: f32) -> (Vec2, Vec2) {
+    if area.abs() < f32::EPSILON {
+        return (Vec2::zeros(), Vec2::zeros());
+    }
+
+    let (mut dx, mut dy) = (Vec2::zeros(), Vec2::zeros());
+    for i in 0..3 {
+        let (start, end) = (screen[(i + 1) % 3], screen[(i + 2) % 3]);
+        dx += tex[i] * ((end.y - start.y) / area);
+        dy += tex[i] * ((start.x - end.x) / area);
+    }
+    (dx, dy)
+}
+
 fn edge_function(a: &Vec3, b: &Vec3, c: &Vec3) -> f32 {
     (c.x - a.x) * (b.y - a.y) - (c.y - a.y) * (b.x - a.x)
 }
@@ -1,4 +1,4 @@
-use nalgebra_glm::{Vec2, Vec3};
+use nalgebra_glm::{Vec2, Vec3, Vec4};
 use crate::color::Color;
 
 #[derive(Clone, Debug)]
@@ -6,9 +6,14 @@ pub struct Vertex {
   pub position: Vec3,
   pub normal: Vec3,
   pub tex_coords: Vec2,
+  // Direction in which u grows along the surface, with the sign of the bitangent in w.
+  // Zero when the model has no texture coordinates.
+  pub tangent: Vec4,
   pub color: Color,
   pub transformed_position: Vec3,
   pub transformed_normal: Vec3,
+  pub transformed_tangent: Vec4,
+  pub world_position: Vec3,
 }
 
 impl Vertex {
@@ -17,9 +22,12 @@ impl Vertex {
       position,
       normal,
       tex_coords,
+      tangent: Vec4::zeros(),
       color: Color::black(),
       transformed_position: position,
       transformed_normal: normal,
+      transformed_tangent: Vec4::zeros(),
+      world_position: position,
     }
   }
 
@@ -28,9 +36,34 @@ impl Vertex {
       position,
       normal: Vec3::new(0.0, 0.0, 0.0),
       tex_coords: Vec2::new(0.0, 0.0),
+      tangent: Vec4::zeros(),
       color,
       transformed_position: Vec3::new(0.0, 0.0, 0.0),
       transformed_normal: Vec3::new(0.0, 0.0, 0.0),
+      transformed_tangent: Vec4::zeros(),
+      world_position: Vec3::new(0.0, 0.0, 0.0),
+    }
+  }
+
+  pub fn with_tangent(mut self, tangent: Vec4) -> Self {
+    self.tangent = tangent;
+    self.transformed_tangent = tangent;
+    self
+  }
+
+  // Vertex a fraction `t` of the way to `other`, for splitting triangles while clipping.
+  // The screen position is left for the caller to project.
+  pub fn lerp(&self, other: &Vertex, t: f32) -> Self {
+    Vertex {
+      position: self.position.lerp(&other.position, t),
+      normal: self.normal.lerp(&other.normal, t),
+      tex_coords: self.tex_coords.lerp(&other.tex_coords, t),
+      tangent: self.tangent.lerp(&other.tangent, t),
+      color: self.color.lerp(&other.color, t),
+      transformed_position: self.transformed_position.lerp(&other.transformed_position, t),
+      transformed_normal: self.transformed_normal.lerp(&other.transformed_normal, t),
+      transformed_tangent: self.transformed_tangent.lerp(&other.transformed_tangent, t),
+      world_position: self.world_position.lerp(&other.world_position, t),
     }
   }
 
@@ -46,9 +79,12 @@ impl Default for Vertex {
       position: Vec3::new(0.0, 0.0, 0.0),
       normal: Vec3::new(0.0, 1.0, 0.0),
       tex_coords: Vec2::new(0.0, 0.0),
+      tangent: Vec4::zeros(),
       color: Color::black(),
       transformed_position: Vec3::new(0.0, 0.0, 0.0),
       transformed_normal: Vec3::new(0.0, 1.0, 0.0),
+      transformed_tangent: Vec4::zeros(),
+      world_position: Vec3::new(0.0, 0.0, 0.0),
     }
   }
 }